@@ -1,7 +1,7 @@
-use hyper::{Response, StatusCode, header};
-use karics::router::ApiService;
-use karics::HttpServiceFactory;
+use karics::http::StatusCode;
+use karics::{HandlerError, HttpServer, Params, Request, Router};
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use std::io;
 use std::sync::{Arc, Mutex};
 
@@ -13,70 +13,48 @@ struct User {
     email: String,
 }
 
-// Factory for creating API services
-struct ApiServiceFactory {
-    router: Arc<karics::router::Router<Vec<u8>>>,
-    users: Arc<Mutex<Vec<User>>>,
-}
-
-impl HttpServiceFactory for ApiServiceFactory {
-    type Service = ApiService;
-
-    fn new_service(&self, _id: usize) -> Self::Service {
-        ApiService::with_context(self.router.clone(), self.users.clone())
-    }
+// Body of POST /users
+#[derive(Debug, Deserialize)]
+struct NewUser {
+    name: String,
+    email: String,
 }
 
 // GET /users
-fn get_all_users(
-    users: Arc<Mutex<Vec<User>>>,
-) -> impl Fn(Vec<String>) -> Response<Vec<u8>> + Clone {
-    move |_params| {
+fn get_all_users(users: Arc<Mutex<Vec<User>>>) -> impl Fn(&Request, Params) -> Value + Clone {
+    move |_req, _params| {
         let users_guard = users.lock().unwrap();
-        let users_json = serde_json::to_vec(&*users_guard).unwrap_or_else(|_| b"[]".to_vec());
-
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(users_json)
-            .unwrap()
+        json!(*users_guard)
     }
 }
 
-// GET /users/:id
+// GET /users/{id}
 fn get_user_by_id(
     users: Arc<Mutex<Vec<User>>>,
-) -> impl Fn(Vec<String>) -> Response<Vec<u8>> + Clone {
-    move |params| {
+) -> impl Fn(&Request, Params) -> (StatusCode, Value) + Clone {
+    move |_req, params| {
         let user_id = params
-            .get(1)
+            .get("id")
             .and_then(|id| id.parse::<usize>().ok())
             .unwrap_or(0);
 
         let users_guard = users.lock().unwrap();
 
         match users_guard.iter().find(|user| user.id == user_id) {
-            Some(user) => {
-                let user_json = serde_json::to_vec(user).unwrap();
-                Response::builder()
-                    .status(StatusCode::OK)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(user_json)
-                    .unwrap()
-            }
-            None => Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(r#"{"error":"User not found"}"#.as_bytes().to_vec())
-                .unwrap(),
+            Some(user) => (StatusCode::OK, json!(user)),
+            None => (StatusCode::NOT_FOUND, json!({"error": "User not found"})),
         }
     }
 }
 
 // POST /users
-fn create_user(users: Arc<Mutex<Vec<User>>>) -> impl Fn(Vec<String>) -> Response<Vec<u8>> + Clone {
-    move |_params| {
-        // In a real app, parse the request body here
+fn create_user(
+    users: Arc<Mutex<Vec<User>>>,
+) -> impl Fn(&Request, Params) -> Result<(StatusCode, Value), HandlerError> + Clone {
+    move |req, _params| {
+        // Parse the request body, a malformed body is answered by the error handler
+        let new_user: NewUser = req.json()?;
+
         let mut users_guard = users.lock().unwrap();
 
         // Generate a new ID
@@ -85,21 +63,23 @@ fn create_user(users: Arc<Mutex<Vec<User>>>) -> impl Fn(Vec<String>) -> Response
         // Create a new user
         let new_user = User {
             id: new_id,
-            name: format!("User {}", new_id),
-            email: format!("user{}@example.com", new_id),
+            name: new_user.name,
+            email: new_user.email,
         };
 
         // Add the user to the store
         users_guard.push(new_user.clone());
 
         // Return the created user
-        let user_json = serde_json::to_vec(&new_user).unwrap();
+        Ok((StatusCode::CREATED, json!(new_user)))
+    }
+}
 
-        Response::builder()
-            .status(StatusCode::CREATED)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(user_json)
-            .unwrap()
+// Map handler errors to responses
+fn handle_error(_req: &Request, err: HandlerError) -> (StatusCode, Value) {
+    match err.downcast_ref::<serde_json::Error>() {
+        Some(_) => (StatusCode::BAD_REQUEST, json!({"error": "Invalid user"})),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, json!({"error": "Internal Server Error"})),
     }
 }
 
@@ -108,28 +88,23 @@ fn main() -> io::Result<()> {
     let users = Arc::new(Mutex::new(Vec::<User>::new()));
 
     // Create router
-    let mut router = karics::router::Router::new();
+    let mut router = Router::new();
 
     // Register routes
     router
-        .get(r"^/users$", get_all_users(users.clone()))
+        .get("/users", get_all_users(users.clone()))
         .unwrap();
     router
-        .get(r"^/users/(\d+)$", get_user_by_id(users.clone()))
+        .get("/users/{id}", get_user_by_id(users.clone()))
         .unwrap();
     router
-        .post(r"^/users$", create_user(users.clone()))
+        .post("/users", create_user(users))
         .unwrap();
-
-    // Create service factory
-    let factory = ApiServiceFactory {
-        router: Arc::new(router),
-        users,
-    };
+    router.error_handler(handle_error);
 
     // Start server
     println!("Server running on http://127.0.0.1:8080");
-    let handle = factory.start("127.0.0.1:8080")?;
+    let handle = HttpServer::create(router, "127.0.0.1:8080")?;
 
     // Wait for server
     handle.join().unwrap();
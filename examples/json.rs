@@ -1,20 +1,30 @@
-use bytes::BufMut;
-use karics::{HttpServer, HttpService, Request, Response};
-use std::io;
+use std::io::Write;
 
-#[derive(Clone)]
-struct HelloJson;
-
-impl HttpService for HelloJson {
-    fn call(&mut self, _req: Request, rsp: &mut Response) -> io::Result<()> {
-        rsp.header("Content-Type: application/json");
-        let w = rsp.body_mut().writer();
-        serde_json::to_writer(w, &serde_json::json!({"message": "Hello, World!"}))?;
-        Ok(())
-    }
-}
+use karics::router::with_response;
+use karics::{HttpServer, Router};
+use serde_json::json;
 
 fn main() {
-    let server = HttpServer(HelloJson).start("127.0.0.1:8080").unwrap();
+    let mut router = Router::new();
+    router
+        .get("/", |_, _| json!({"message": "Hello, World!"}))
+        .unwrap();
+    // writes into the response itself, here to stream one value per line
+    router
+        .get(
+            "/numbers",
+            with_response(|_, _, rsp| {
+                rsp.content_type("application/x-ndjson");
+                rsp.stream(|w| {
+                    for n in 0..10 {
+                        writeln!(w, "{}", json!({ "n": n }))?;
+                    }
+                    Ok(())
+                });
+            }),
+        )
+        .unwrap();
+
+    let server = HttpServer::create(router, "127.0.0.1:8080").unwrap();
     server.wait();
-}
\ No newline at end of file
+}
@@ -1,38 +1,30 @@
-use std::io::BufRead;
+use karics::http::StatusCode;
+use karics::{HandlerError, HttpServer, Params, Request, Router};
+use serde_json::{Value, json};
 
-use bytes::BufMut;
-use karics::{HttpServer, HttpService, Request, Response};
+fn check_token(req: &Request, _params: Params) -> Result<Value, HandlerError> {
+    println!("method: {:?}", req.method());
+    let value: Value = req.json()?;
+    println!("value: {:?}", value);
 
-#[derive(Clone)]
-struct HelloJson;
-
-impl HttpService for HelloJson {
-    fn call(&mut self, req: Request, rsp: &mut Response) -> std::io::Result<()> {
-        let method = req.method();
-        println!("method: {:?}", method);
-        let mut body = req.body();
-        let value: serde_json::Value = serde_json::from_slice(body.fill_buf()?)?;
-        println!("value: {:?}", value);
-        rsp.header("Content-Type: application/json");
-        let w = rsp.body_mut().writer();
-
-        fn get_token(value: &serde_json::Value) -> Option<&str> {
-            value.as_object()?.get("token")?.as_str()
-        }
-        let token = get_token(&value).ok_or(std::io::ErrorKind::InvalidData)?;
-
-        if token == "LOmCXi7MkpRozLJvLrK6fA==" {
-            serde_json::to_writer(w, &serde_json::json!({ "status": "ok" }))?;
-        } else {
-            serde_json::to_writer(w, &serde_json::json!({ "status": "denied" }))?;
-        }
+    fn get_token(value: &Value) -> Option<&str> {
+        value.as_object()?.get("token")?.as_str()
+    }
+    let token = get_token(&value)
+        .ok_or_else(|| HandlerError::with_status(StatusCode::BAD_REQUEST, "missing token"))?;
 
-        Ok(())
+    if token == "LOmCXi7MkpRozLJvLrK6fA==" {
+        Ok(json!({ "status": "ok" }))
+    } else {
+        Ok(json!({ "status": "denied" }))
     }
 }
 
 // curl -v -X POST http://127.0.0.1:8080 -H 'Content-Type: application/json' -d '{"token":"LOmCXi7MkpRozLJvLrK6fA=="}'
 fn main() {
-    let server = HttpServer(HelloJson).start("127.0.0.1:8080").unwrap();
+    let mut router = Router::new();
+    router.post("/", check_token).unwrap();
+
+    let server = HttpServer::create(router, "127.0.0.1:8080").unwrap();
     server.wait();
-}
\ No newline at end of file
+}
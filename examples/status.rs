@@ -1,27 +1,19 @@
-use std::io;
-
-use karics::{HttpServer, HttpService, Request, Response};
-
-#[derive(Clone)]
-struct StatusService;
-
-impl HttpService for StatusService {
-    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
-        let (code, message) = match req.path() {
-            "/200" => (200, "OK"),
-            "/400" => (400, "Bad Request"),
-            "/500" => (500, "Internal Server Error"),
-            _ => (404, "Not Found"),
-        };
-
-        rsp.status_code(code, message);
-        rsp.body(message);
-        Ok(())
-    }
-}
+use karics::http::StatusCode;
+use karics::{HttpServer, Router};
 
 fn main() {
     env_logger::init();
-    let server = HttpServer(StatusService).start("127.0.0.1:8080").unwrap();
+
+    let mut router = Router::new();
+    router.get("/200", |_, _| (StatusCode::OK, "OK")).unwrap();
+    router
+        .get("/400", |_, _| (StatusCode::BAD_REQUEST, "Bad Request"))
+        .unwrap();
+    router
+        .get("/500", |_, _| (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"))
+        .unwrap();
+    // anything else gets the router's 404
+
+    let server = HttpServer::create(router, "127.0.0.1:8080").unwrap();
     server.join().unwrap();
-}
\ No newline at end of file
+}
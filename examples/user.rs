@@ -1,11 +1,10 @@
 // cargo run --example user
 
 
-use hyper::{Method, Response, StatusCode};
-use karics::{HttpService, HttpServiceFactory, Request, Router};
+use karics::extract::{Path, handler};
+use karics::{HttpServer, Router};
 use serde::{Deserialize, Serialize};
 use std::io;
-use std::sync::Arc;
 
 // User data structure
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,52 +14,8 @@ struct User {
     email: String,
 }
 
-// API Service structure
-struct ApiService {
-    router: Arc<Router<Vec<u8>>>,
-}
-
-// Implementation of HttpService for ApiService
-impl HttpService for ApiService {
-    fn call(&mut self, req: Request, rsp: &mut karics::Response) -> io::Result<()> {
-        let method = Method::from_bytes(req.method().as_bytes()).unwrap();
-        let path = req.path();
-        
-        match self.router.handle(&method, path) {
-            Ok(response) => {
-                rsp.status_code(response.status().as_u16() as usize, "OK")
-                    .header("Content-Type: application/json");
-                // Fix: directly use the body since it's already Vec<u8>
-                rsp.body_vec(response.body().to_vec());
-                Ok(())
-            }
-            Err(_) => {
-                rsp.status_code(404, "Not Found")
-                    .header("Content-Type: application/json")
-                    .body(r#"{"error": "Not Found"}"#);
-                Ok(())
-            }
-        }
-    }
-}
-
-// Factory for creating API services
-struct ApiServiceFactory {
-    router: Arc<Router<Vec<u8>>>,
-}
-
-impl HttpServiceFactory for ApiServiceFactory {
-    type Service = ApiService;
 
-    fn new_service(&self, _id: usize) -> Self::Service {
-        ApiService {
-            router: Arc::clone(&self.router),
-        }
-    }
-}
-
-
-fn get_all_users() -> Response<Vec<u8>> {
+fn get_all_users() -> serde_json::Value {
     let users = vec![
         User {
             id: 1,
@@ -69,24 +24,18 @@ fn get_all_users() -> Response<Vec<u8>> {
         },
     ];
     
-    Response::builder()
-        .status(StatusCode::OK)
-        .body(serde_json::to_vec(&users).unwrap())
-        .unwrap()
+    serde_json::json!(users)
 }
 
-// GET /users/{id}
-fn get_user_by_id(params: Vec<String>) -> Response<Vec<u8>> {
+// GET /users/{id}, a non-numeric id is answered with 400
+fn get_user_by_id(Path(id): Path<u64>) -> serde_json::Value {
     let user = User {
-        id: params[0].parse().unwrap(),
+        id,
         name: "John Doe".to_string(),
         email: "john@example.com".to_string(),
     };
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .body(serde_json::to_vec(&user).unwrap())
-        .unwrap()
+    serde_json::json!(user)
 }
 
 fn main() -> io::Result<()> {
@@ -94,19 +43,14 @@ fn main() -> io::Result<()> {
     let mut root = Router::new();
 
     // GET /users
-    root.get("/users", |_| get_all_users()).unwrap()
-        .get("/users/(\\d+)", |params| get_user_by_id(params)).unwrap();
-
-    // Create service factory
-    let factory = ApiServiceFactory {
-        router: Arc::new(root),
-    };
+    root.get("/users", |_, _| get_all_users()).unwrap()
+        .get("/users/{id}", handler(get_user_by_id)).unwrap();
 
     // Start server
-    let handle = factory.start("127.0.0.1:3000")?;
+    let handle = HttpServer::create(root, "127.0.0.1:3000")?;
     println!("Server running on http://127.0.0.1:3000");
     
     // Wait for server
     handle.join().unwrap();
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,485 @@
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::request::{self, Request};
+
+// `%h %l %u %t "%r" %>s %b` of Apache
+const COMMON: &str = r#"{client_ip} - - [{time}] "{method} {path} {protocol}" {status} {bytes}"#;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Writes a line for every request a server answers, set with
+/// [`ServerConfig::access_log`](crate::ServerConfig::access_log):
+///
+/// ```ignore
+/// let log = AccessLog::file("/var/log/app/access.log", LogFormat::combined())?
+///     .rotate(100 << 20, 5);
+/// HttpServer::builder_for(router).bind("0.0.0.0:8080").access_log(log).start()?;
+/// ```
+///
+/// Requests refused before reaching the service, such as those with a
+/// malformed head, are left out. Clones write to the same output; lines
+/// that can't be written are dropped with an error logged.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: Arc<LogFormat>,
+    output: Arc<Mutex<Output>>,
+}
+
+enum Output {
+    Stdout,
+    File(LogFile),
+    Writer(Box<dyn Write + Send>),
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    // the size to rotate at and the number of old files kept
+    rotation: Option<(u64, usize)>,
+}
+
+impl AccessLog {
+    /// Logs to the standard output, as containers usually collect.
+    pub fn stdout(format: LogFormat) -> Self {
+        Self::new(format, Output::Stdout)
+    }
+
+    /// Logs to the file at `path`, created if missing and appended to
+    /// otherwise; see [`rotate`](Self::rotate) to limit its size.
+    pub fn file(path: impl AsRef<Path>, format: LogFormat) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        let file = LogFile {
+            path,
+            file,
+            len,
+            rotation: None,
+        };
+        Ok(Self::new(format, Output::File(file)))
+    }
+
+    /// Logs to `writer`, such as a pipe to a log shipper.
+    pub fn writer(writer: impl Write + Send + 'static, format: LogFormat) -> Self {
+        Self::new(format, Output::Writer(Box::new(writer)))
+    }
+
+    /// Has a log [`file`](Self::file) that would grow past `max_bytes`
+    /// renamed to `access.log.1` before writing on, with older files
+    /// moving on to `access.log.2` and so on up to `keep` of them; the
+    /// oldest is removed. Other outputs aren't rotated.
+    pub fn rotate(self, max_bytes: u64, keep: usize) -> Self {
+        if let Output::File(file) = &mut *self.output() {
+            file.rotation = Some((max_bytes, keep));
+        }
+        self
+    }
+
+    fn new(format: LogFormat, output: Output) -> Self {
+        AccessLog {
+            format: Arc::new(format),
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// What is known of `req` before it is handled, completed by
+    /// [`write`](Self::write) once the response is.
+    pub(crate) fn entry(&self, req: &Request, time: SystemTime) -> Entry {
+        let header = |name| request::header_values(req.headers(), name).next().map(str::to_owned);
+        Entry {
+            time,
+            method: req.method().to_owned(),
+            path: req.path().to_owned(),
+            version: req.version(),
+            client_ip: req.client_ip(),
+            request_id: header("x-request-id"),
+            user_agent: header("user-agent"),
+            referer: header("referer"),
+            route: None,
+            status: 0,
+            bytes: 0,
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Writes the line of a request answered with `status`.
+    pub(crate) fn write(
+        &self,
+        mut entry: Entry,
+        route: Option<Arc<str>>,
+        status: u16,
+        bytes: u64,
+        latency: Duration,
+    ) {
+        entry.route = route;
+        entry.status = status;
+        entry.bytes = bytes;
+        entry.latency = latency;
+        let mut line = self.format.line(&entry);
+        line.push('\n');
+        if let Err(e) = self.output().write_line(line.as_bytes()) {
+            error!("access log err = {:?}", e);
+        }
+    }
+
+    fn output(&self) -> std::sync::MutexGuard<'_, Output> {
+        // a failed write leaves the output as usable as it was
+        self.output.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let output = match &*self.output() {
+            Output::Stdout => "stdout".to_owned(),
+            Output::File(file) => file.path.display().to_string(),
+            Output::Writer(_) => "writer".to_owned(),
+        };
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .field("output", &output)
+            .finish()
+    }
+}
+
+impl Output {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(line),
+            Output::Writer(writer) => writer.write_all(line),
+            Output::File(file) => {
+                if let Some((max_bytes, keep)) = file.rotation
+                    && file.len > 0
+                    && file.len + line.len() as u64 > max_bytes
+                {
+                    file.rotate(keep)?;
+                }
+                file.file.write_all(line)?;
+                file.len += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl LogFile {
+    // Moves the file and the `keep` before it one number on, and starts a
+    // new one
+    fn rotate(&mut self, keep: usize) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // renaming onto a file fails on Windows
+            fs::remove_file(numbered(keep)).ok();
+            for n in (1..keep).rev() {
+                fs::rename(numbered(n), numbered(n + 1)).ok();
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// How [`AccessLog`] lines are written: in the common or combined log
+/// formats of Apache and nginx, as JSON objects, or by a template.
+#[derive(Debug, Clone)]
+pub struct LogFormat(Kind);
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Json,
+    Template(Vec<Piece>),
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Time,
+    TimeIso,
+    Method,
+    Path,
+    Route,
+    Protocol,
+    Status,
+    Bytes,
+    LatencyMs,
+    LatencyUs,
+    ClientIp,
+    RequestId,
+    UserAgent,
+    Referer,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "time" => Field::Time,
+            "time_iso" => Field::TimeIso,
+            "method" => Field::Method,
+            "path" => Field::Path,
+            "route" => Field::Route,
+            "protocol" => Field::Protocol,
+            "status" => Field::Status,
+            "bytes" => Field::Bytes,
+            "latency_ms" => Field::LatencyMs,
+            "latency_us" => Field::LatencyUs,
+            "client_ip" => Field::ClientIp,
+            "request_id" => Field::RequestId,
+            "user_agent" => Field::UserAgent,
+            "referer" => Field::Referer,
+            _ => return None,
+        })
+    }
+}
+
+impl LogFormat {
+    /// The Common Log Format, such as
+    /// `10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /a HTTP/1.1" 200 2326`.
+    pub fn common() -> Self {
+        Self::template(COMMON).expect("valid template")
+    }
+
+    /// The Combined Log Format: the common one followed by the `Referer`
+    /// and `User-Agent` headers, quoted.
+    pub fn combined() -> Self {
+        Self::template(&format!(r#"{COMMON} "{{referer}}" "{{user_agent}}""#)).expect("valid template")
+    }
+
+    /// A JSON object per line, with every field of a
+    /// [`template`](Self::template) under its name, `time` being the
+    /// `time_iso` one; missing values are `null`.
+    pub fn json() -> Self {
+        LogFormat(Kind::Json)
+    }
+
+    /// Lines made of `template`, with the fields named in braces filled
+    /// in and `{{` and `}}` standing for braces:
+    ///
+    /// ```ignore
+    /// LogFormat::template("{method} {route} {status} {latency_ms}ms id={request_id}")?
+    /// ```
+    ///
+    /// The fields are:
+    /// - `time`, when the request was read, as `10/Oct/2000:13:55:36 +0000`,
+    ///   and `time_iso`, as `2000-10-10T13:55:36.123Z`
+    /// - `method`, `path` (the request target, query included) and
+    ///   `protocol`, as `HTTP/1.1`
+    /// - `route`, the pattern of the matched route such as `/users/{id}`
+    /// - `status`, and `bytes` of the response, head included
+    /// - `latency_ms` and `latency_us`, from reading the request head to
+    ///   writing the response
+    /// - `client_ip`, see [`Request::client_ip`]
+    /// - `request_id`, `user_agent` and `referer`, from the `X-Request-Id`,
+    ///   `User-Agent` and `Referer` request headers
+    ///
+    /// Missing values are written as `-`; quotes, backslashes and control
+    /// characters from the request are escaped.
+    pub fn template(template: &str) -> Result<Self, InvalidLogFormat> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(at) = rest.find(['{', '}']) {
+            text.push_str(&rest[..at]);
+            let tail = &rest[at..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                text.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            let end = match tail.find('}') {
+                Some(end) if tail.starts_with('{') => end,
+                _ => return Err(InvalidLogFormat(tail.to_owned())),
+            };
+            let field = Field::from_name(&tail[1..end])
+                .ok_or_else(|| InvalidLogFormat(tail[..=end].to_owned()))?;
+            if !text.is_empty() {
+                pieces.push(Piece::Text(mem::take(&mut text)));
+            }
+            pieces.push(Piece::Field(field));
+            rest = &tail[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(LogFormat(Kind::Template(pieces)))
+    }
+
+    fn line(&self, entry: &Entry) -> String {
+        match &self.0 {
+            Kind::Json => json_line(entry),
+            Kind::Template(pieces) => {
+                let mut line = String::with_capacity(128);
+                for piece in pieces {
+                    match piece {
+                        Piece::Text(text) => line.push_str(text),
+                        Piece::Field(field) => push_field(&mut line, *field, entry),
+                    }
+                }
+                line
+            }
+        }
+    }
+}
+
+/// An access log template with an unknown field or an unmatched brace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLogFormat(String);
+
+impl fmt::Display for InvalidLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid access log field at `{}`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLogFormat {}
+
+/// A request to write an access log line for.
+pub(crate) struct Entry {
+    time: SystemTime,
+    method: String,
+    path: String,
+    version: u8,
+    client_ip: Option<IpAddr>,
+    request_id: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    // known once the response is written
+    route: Option<Arc<str>>,
+    status: u16,
+    bytes: u64,
+    latency: Duration,
+}
+
+fn push_field(line: &mut String, field: Field, entry: &Entry) {
+    let text = |line: &mut String, value: Option<&str>| match value {
+        Some(value) => push_escaped(line, value),
+        None => line.push('-'),
+    };
+    match field {
+        Field::Time => push_clf_time(line, entry.time),
+        Field::TimeIso => push_iso_time(line, entry.time),
+        Field::Method => push_escaped(line, &entry.method),
+        Field::Path => push_escaped(line, &entry.path),
+        Field::Route => text(line, entry.route.as_deref()),
+        Field::Protocol => line.push_str(protocol(entry.version)),
+        Field::Status => {
+            let _ = write!(line, "{}", entry.status);
+        }
+        Field::Bytes => {
+            let _ = write!(line, "{}", entry.bytes);
+        }
+        Field::LatencyMs => {
+            let _ = write!(line, "{:.3}", entry.latency.as_secs_f64() * 1e3);
+        }
+        Field::LatencyUs => {
+            let _ = write!(line, "{}", entry.latency.as_micros());
+        }
+        Field::ClientIp => match entry.client_ip {
+            Some(ip) => {
+                let _ = write!(line, "{ip}");
+            }
+            None => line.push('-'),
+        },
+        Field::RequestId => text(line, entry.request_id.as_deref()),
+        Field::UserAgent => text(line, entry.user_agent.as_deref()),
+        Field::Referer => text(line, entry.referer.as_deref()),
+    }
+}
+
+fn json_line(entry: &Entry) -> String {
+    let mut time = String::new();
+    push_iso_time(&mut time, entry.time);
+    serde_json::json!({
+        "time": time,
+        "method": entry.method,
+        "path": entry.path,
+        "protocol": protocol(entry.version),
+        "route": entry.route.as_deref(),
+        "status": entry.status,
+        "bytes": entry.bytes,
+        "latency_ms": entry.latency.as_secs_f64() * 1e3,
+        "latency_us": entry.latency.as_micros() as u64,
+        "client_ip": entry.client_ip.map(|ip| ip.to_string()),
+        "request_id": entry.request_id,
+        "user_agent": entry.user_agent,
+        "referer": entry.referer,
+    })
+    .to_string()
+}
+
+fn protocol(version: u8) -> &'static str {
+    if version == 0 { "HTTP/1.0" } else { "HTTP/1.1" }
+}
+
+// Appends `value` with quotes, backslashes and control characters escaped
+// as Apache does, for clients not to forge lines of their own
+fn push_escaped(line: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                line.push('\\');
+                line.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(line, "\\x{:02x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+}
+
+// `10/Oct/2000:13:55:36 +0000`
+fn push_clf_time(line: &mut String, time: SystemTime) {
+    let (year, month, day, hour, minute, second) = civil(time);
+    let month = MONTHS[month as usize - 1];
+    let _ = write!(line, "{day:02}/{month}/{year}:{hour:02}:{minute:02}:{second:02} +0000");
+}
+
+// `2000-10-10T13:55:36.123Z`
+fn push_iso_time(line: &mut String, time: SystemTime) {
+    let (year, month, day, hour, minute, second) = civil(time);
+    let millis = time.duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis();
+    let _ = write!(line, "{year}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z");
+}
+
+// The UTC date and time of `time` as year, month, day, hour, minute and
+// second, by the days-to-civil algorithm of Howard Hinnant
+fn civil(time: SystemTime) -> (u64, u64, u64, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // days from 0000-03-01, years starting in March to put leap days last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day, secs / 3_600, secs / 60 % 60, secs % 60)
+}
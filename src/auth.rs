@@ -0,0 +1,98 @@
+//! Credentials from the `Authorization` header.
+//!
+//! ```ignore
+//! let mut admin = router.scope("/admin");
+//! admin.middleware(|req, params, rsp, next| {
+//!     match req.basic_auth() {
+//!         Some(auth) if auth.matches("admin", &password) => next.run(req, params, rsp),
+//!         _ => Err(HandlerError::with_status(StatusCode::UNAUTHORIZED, "sign in first")
+//!             .with_header(WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=\"admin\""))),
+//!     }
+//! });
+//! ```
+use std::fmt;
+
+/// The user name and password of `Authorization: Basic` credentials, see
+/// [`Request::basic_auth`](crate::Request::basic_auth).
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl BasicAuth {
+    /// Decodes the `user:password` token following `Basic `.
+    pub fn decode(token: &str) -> Option<BasicAuth> {
+        let decoded = String::from_utf8(base64_decode(token.trim())?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(BasicAuth {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// Compares both fields with [`constant_time_eq`], so the time taken
+    /// doesn't tell how much of a guess was right.
+    pub fn matches(&self, username: &str, password: &str) -> bool {
+        // `&` rather than `&&`, both are always compared
+        constant_time_eq(self.username.as_bytes(), username.as_bytes())
+            & constant_time_eq(self.password.as_bytes(), password.as_bytes())
+    }
+}
+
+// keeps the password out of logs
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &"..")
+            .finish()
+    }
+}
+
+/// Compares secrets such as tokens or passwords in time that depends only
+/// on their lengths, not on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// The token of an `Authorization` header using `scheme`, which is matched
+/// ignoring case.
+pub(crate) fn token<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+    let (name, token) = value.trim().split_once(' ')?;
+    let token = token.trim();
+    (name.eq_ignore_ascii_case(scheme) && !token.is_empty()).then_some(token)
+}
+
+// Standard alphabet, padding optional
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut bits = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            bits |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = bits.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::time::Duration;
+
+use http::HeaderValue;
+
+/// The directives of a `Cache-Control` header.
+///
+/// ```ignore
+/// // cached by browsers for a minute and by shared caches for an hour
+/// let cache = CacheControl::new()
+///     .public()
+///     .max_age(Duration::from_secs(60))
+///     .s_maxage(Duration::from_secs(3600));
+/// rsp.cache_control(cache);
+/// ```
+///
+/// Directives that contradict each other aren't sent together, the one set
+/// last wins: `public` and `private` exclude each other, and `no-store`
+/// excludes everything that lets a cache keep the response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    must_revalidate: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    /// No directives, which sends no header.
+    pub fn new() -> CacheControl {
+        CacheControl::default()
+    }
+
+    /// Lets shared caches, such as a CDN, store the response.
+    pub fn public(mut self) -> Self {
+        self.keep();
+        self.public = true;
+        self.private = false;
+        self
+    }
+
+    /// Keeps the response out of shared caches, for content meant for one
+    /// user.
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self.public = false;
+        self
+    }
+
+    /// Has caches check with the server before every reuse.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Keeps the response out of every cache.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self.public = false;
+        self.max_age = None;
+        self.s_maxage = None;
+        self.immutable = false;
+        self
+    }
+
+    /// How long the response stays fresh, in whole seconds.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.keep();
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// How long the response stays fresh in shared caches, overriding
+    /// [`max_age`](Self::max_age) there.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.keep();
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// Forbids serving the response once it is stale.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Tells caches the response won't change while fresh, for assets
+    /// with a version in their URL.
+    pub fn immutable(mut self) -> Self {
+        self.keep();
+        self.immutable = true;
+        self
+    }
+
+    /// Whether no directive is set.
+    pub fn is_empty(&self) -> bool {
+        *self == CacheControl::default()
+    }
+
+    // a directive allowing the response to be stored overrides `no-store`
+    fn keep(&mut self) {
+        self.no_store = false;
+    }
+}
+
+/// Formats the directives as the value of a `Cache-Control` header.
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut directives: Vec<Cow<'_, str>> = Vec::new();
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+        ];
+        directives.extend(flags.iter().filter(|(set, _)| *set).map(|(_, d)| Cow::Borrowed(*d)));
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={}", max_age.as_secs()).into());
+        }
+        if let Some(s_maxage) = self.s_maxage {
+            directives.push(format!("s-maxage={}", s_maxage.as_secs()).into());
+        }
+        if self.must_revalidate {
+            directives.push("must-revalidate".into());
+        }
+        if self.immutable {
+            directives.push("immutable".into());
+        }
+        f.write_str(&directives.join(", "))
+    }
+}
+
+/// For setting the header on an `http::Response` returned by a handler:
+///
+/// ```ignore
+/// response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from(&cache));
+/// ```
+impl From<&CacheControl> for HeaderValue {
+    fn from(cache: &CacheControl) -> Self {
+        // directives are plain ASCII
+        HeaderValue::try_from(cache.to_string()).unwrap()
+    }
+}
@@ -0,0 +1,195 @@
+//! decoding of `Transfer-Encoding: chunked` request bodies
+use std::io;
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+// a size or trailer line longer than this is rejected
+const MAX_LINE: usize = 4096;
+// as are more trailer fields than this
+const MAX_TRAILERS: usize = 64;
+// and chunk sizes of more hex digits than fit a `u64`
+const MAX_SIZE_DIGITS: usize = 16;
+
+/// Where the decoder takes the encoded bytes from.
+pub(crate) trait Source {
+    /// Returns buffered bytes, reading more if there are none. `max` is how
+    /// many bytes the decoder needs at most, sources that must not read
+    /// past the body stay within it. Empty at the end of the stream.
+    fn fill(&mut self, max: usize) -> io::Result<&[u8]>;
+
+    fn consume(&mut self, n: usize);
+}
+
+#[derive(Debug)]
+enum State {
+    Size,
+    Data(u64),
+    // the CRLF after a chunk
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+/// The framing state of a chunked body being decoded.
+#[derive(Debug)]
+pub(crate) struct Chunked {
+    state: State,
+    line: Vec<u8>,
+    trailers: HeaderMap,
+}
+
+// `InvalidData` is taken by bodies over the size limit
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Chunked {
+    pub(crate) fn new() -> Self {
+        Chunked {
+            state: State::Size,
+            line: Vec::new(),
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    /// The trailer fields sent after the last chunk, `None` until the body
+    /// has been read to the end.
+    pub(crate) fn trailers(&self) -> Option<&HeaderMap> {
+        matches!(self.state, State::Done).then_some(&self.trailers)
+    }
+
+    /// Reads framing until chunk data follows, returning how much of the
+    /// current chunk is left, or `0` once the body has ended.
+    pub(crate) fn data_len(&mut self, src: &mut impl Source) -> io::Result<u64> {
+        loop {
+            match self.state {
+                State::Data(n) => return Ok(n),
+                State::Done => return Ok(0),
+                State::Size => {
+                    let line = self.read_line(src)?;
+                    let size = chunk_size(line).ok_or_else(|| invalid("invalid chunk size"))?;
+                    self.state = if size == 0 { State::Trailer } else { State::Data(size) };
+                }
+                State::DataEnd => {
+                    if !self.read_line(src)?.is_empty() {
+                        return Err(invalid("missing CRLF after chunk data"));
+                    }
+                    self.state = State::Size;
+                }
+                State::Trailer => {
+                    let line = self.read_line(src)?;
+                    if line.is_empty() {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    // fields that aren't `name: value` are skipped
+                    let field = line.iter().position(|&b| b == b':').and_then(|colon| {
+                        let name = HeaderName::from_bytes(&line[..colon]).ok()?;
+                        let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii()).ok()?;
+                        Some((name, value))
+                    });
+                    if let Some((name, value)) = field {
+                        if self.trailers.len() == MAX_TRAILERS {
+                            return Err(invalid("too many trailer fields"));
+                        }
+                        self.trailers.append(name, value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks `n` bytes of chunk data as consumed.
+    pub(crate) fn consumed(&mut self, n: usize) {
+        if let State::Data(left) = self.state {
+            let left = left - n as u64;
+            self.state = if left == 0 { State::DataEnd } else { State::Data(left) };
+        }
+    }
+
+    /// Decodes body bytes into `buf`, `0` at the end of the body.
+    pub(crate) fn read(&mut self, src: &mut impl Source, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.data_len(src)?;
+        if left == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        let data = src.fill(max)?;
+        if data.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let n = data.len().min(max);
+        buf[..n].copy_from_slice(&data[..n]);
+        src.consume(n);
+        self.consumed(n);
+        Ok(n)
+    }
+
+    // Reads a CRLF terminated line, without the line ending
+    fn read_line(&mut self, src: &mut impl Source) -> io::Result<&[u8]> {
+        self.line.clear();
+        loop {
+            let data = src.fill(1)?;
+            if data.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let (n, done) = match data.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (data.len(), false),
+            };
+            if self.line.len() + n > MAX_LINE {
+                return Err(invalid("chunk line too long"));
+            }
+            self.line.extend_from_slice(&data[..n]);
+            src.consume(n);
+            if done {
+                let line = self.line.strip_suffix(b"\n").unwrap_or_default();
+                return Ok(line.strip_suffix(b"\r").unwrap_or(line));
+            }
+        }
+    }
+}
+
+// The size at the start of a chunk line: hex digits only, so a size a
+// proxy in front reads differently, such as `+a` or ` a`, is refused.
+// Chunk extensions after `;` are ignored
+fn chunk_size(line: &[u8]) -> Option<u64> {
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 || digits > MAX_SIZE_DIGITS {
+        return None;
+    }
+    let rest = &line[digits..];
+    let ext = rest.iter().position(|&b| b != b' ' && b != b'\t');
+    if !rest.is_empty() && ext.is_none_or(|i| rest[i] != b';') {
+        return None;
+    }
+    let size = std::str::from_utf8(&line[..digits]).ok()?;
+    u64::from_str_radix(size, 16).ok()
+}
+
+/// Reads the decoded body from a [`Source`].
+pub(crate) struct ChunkedReader<S> {
+    chunked: Chunked,
+    src: S,
+}
+
+impl<S: Source> ChunkedReader<S> {
+    pub(crate) fn new(src: S) -> Self {
+        ChunkedReader {
+            chunked: Chunked::new(),
+            src,
+        }
+    }
+
+    /// The trailer fields, if the body was read to the end, and the source.
+    pub(crate) fn into_parts(self) -> (Option<HeaderMap>, S) {
+        let trailers = self.chunked.trailers().is_some().then_some(self.chunked.trailers);
+        (trailers, self.src)
+    }
+}
+
+impl<S: Source> io::Read for ChunkedReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.chunked.read(&mut self.src, buf)
+    }
+}
@@ -0,0 +1,155 @@
+//! `Content-Encoding` of response bodies, behind the `compression-gzip`
+//! and `compression-br` features
+use std::io::{self, Write};
+
+use http::{StatusCode, header};
+
+use crate::Response;
+use crate::request::parse_weighted;
+
+/// A coding the server can apply to a response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Coding {
+    #[cfg(feature = "compression-br")]
+    Brotli,
+    #[cfg(feature = "compression-gzip")]
+    Gzip,
+    #[cfg(feature = "compression-gzip")]
+    Deflate,
+}
+
+// in order of preference when the client rates them the same
+const CODINGS: &[Coding] = &[
+    #[cfg(feature = "compression-br")]
+    Coding::Brotli,
+    #[cfg(feature = "compression-gzip")]
+    Coding::Gzip,
+    #[cfg(feature = "compression-gzip")]
+    Coding::Deflate,
+];
+
+impl Coding {
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression-br")]
+            Coding::Brotli => "br",
+            #[cfg(feature = "compression-gzip")]
+            Coding::Gzip => "gzip",
+            #[cfg(feature = "compression-gzip")]
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression-br")]
+            Coding::Brotli => {
+                // quality 5 of 11, the higher ones cost too much per request
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(body)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+            #[cfg(feature = "compression-gzip")]
+            Coding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "compression-gzip")]
+            Coding::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The coding the client prefers according to the `Accept-Encoding`
+/// values, `None` for the body as it is.
+pub(crate) fn negotiate<'a>(accept_encoding: impl Iterator<Item = &'a str>) -> Option<Coding> {
+    let ranges: Vec<(&str, f32)> = accept_encoding
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_weighted)
+        .collect();
+    let quality = |name: &str| {
+        ranges
+            .iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+            .or_else(|| ranges.iter().find(|(coding, _)| *coding == "*"))
+            .map(|&(_, q)| q)
+    };
+    let mut best: Option<(Coding, f32)> = None;
+    for &coding in CODINGS {
+        let q = quality(coding.name()).unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Compresses the body of a handler's response with `coding` if it is at
+/// least `min_size` bytes, held in memory and worth compressing, adjusting
+/// the headers to match. Responses that could be compressed vary by
+/// `Accept-Encoding`, whether or not this one was.
+pub(crate) fn compress_response(rsp: &mut Response<'_>, coding: Option<Coding>, min_size: usize) -> io::Result<()> {
+    if !compressible(rsp) {
+        return Ok(());
+    }
+    let compressed = match rsp.body_in_memory() {
+        Some(body) if body.len() >= min_size => match coding {
+            Some(coding) => Some(coding.encode(body)?).filter(|compressed| compressed.len() < body.len()),
+            None => None,
+        },
+        _ => return Ok(()),
+    };
+    rsp.append_header(header::VARY, "accept-encoding")?;
+    let (Some(coding), Some(compressed)) = (coding, compressed) else {
+        return Ok(());
+    };
+    rsp.set_header(header::CONTENT_ENCODING, coding.name())?;
+    // the encoded body is another representation, so a strong validator
+    // of the plain one no longer holds byte for byte
+    if let Some(etag) = rsp.header_value(&header::ETAG)
+        && !etag.starts_with(b"W/")
+    {
+        let weak = [b"W/", etag].concat();
+        rsp.set_header(header::ETAG, weak)?;
+    }
+    rsp.body_vec(compressed);
+    Ok(())
+}
+
+// Bodies with a status that forbids or doesn't suit a coding, bodies
+// encoded by the handler and formats that are compressed already are sent
+// as they are
+fn compressible(rsp: &Response<'_>) -> bool {
+    let status = rsp.status_sent();
+    if status < 200
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+        || rsp.header_value(&header::CONTENT_ENCODING).is_some()
+        || rsp.header_value(&header::CONTENT_RANGE).is_some()
+    {
+        return false;
+    }
+    let Some(content_type) = rsp
+        .header_value(&header::CONTENT_TYPE)
+        .and_then(|v| std::str::from_utf8(v).ok())
+        .and_then(crate::MediaType::parse)
+    else {
+        return true;
+    };
+    match content_type.main_type() {
+        "text" => true,
+        "image" => content_type.subtype() == "svg+xml",
+        "audio" | "video" | "font" => false,
+        _ => !matches!(
+            content_type.subtype(),
+            "zip" | "gzip" | "x-gzip" | "zstd" | "x-7z-compressed" | "x-rar-compressed" | "pdf" | "octet-stream"
+        ),
+    }
+}
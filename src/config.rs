@@ -0,0 +1,470 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::access_log::AccessLog;
+use crate::date::Clock;
+use crate::error_handler::{ErrorHandler, GenericErrors};
+use crate::shutdown::ShutdownHandle;
+
+/// Settings for [`HttpServer::start_with`](crate::HttpServer::start_with).
+///
+/// ```ignore
+/// let config = ServerConfig::new().trusted_proxies(["10.0.0.0/8", "::1"])?;
+/// HttpServer::create_with(router, "0.0.0.0:8080", config)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    trusted_proxies: Vec<Cidr>,
+    pub(crate) max_headers: usize,
+    pub(crate) max_header_bytes: usize,
+    pub(crate) max_uri_length: usize,
+    pub(crate) max_response_headers: usize,
+    pub(crate) log_exchanges: bool,
+    pub(crate) pretty_json: bool,
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) text_charset: Option<&'static str>,
+    pub(crate) on_response: Option<ResponseHook>,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) shutdown: Option<ShutdownHandle>,
+    pub(crate) keep_alive: bool,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) max_requests_per_connection: Option<usize>,
+    pub(crate) header_timeout: Option<Duration>,
+    pub(crate) body_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<(usize, Overload)>,
+    pub(crate) reuse_port: Option<usize>,
+    pub(crate) backlog: u32,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) nodelay: bool,
+    pub(crate) linger: Option<Duration>,
+    pub(crate) tcp_keepalive: Option<Duration>,
+    pub(crate) access_log: Option<AccessLog>,
+    pub(crate) error_handler: Arc<dyn ErrorHandler>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            trusted_proxies: Vec::new(),
+            max_headers: 16,
+            max_header_bytes: 16 * 1024,
+            max_uri_length: 8 * 1024,
+            max_response_headers: crate::response::DEFAULT_MAX_HEADERS,
+            log_exchanges: false,
+            pretty_json: false,
+            clock: None,
+            text_charset: Some(crate::response::DEFAULT_CHARSET),
+            on_response: None,
+            request_timeout: None,
+            shutdown: None,
+            keep_alive: true,
+            keep_alive_timeout: None,
+            max_requests_per_connection: None,
+            header_timeout: None,
+            body_timeout: None,
+            write_timeout: None,
+            max_connections: None,
+            reuse_port: None,
+            backlog: 128,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            nodelay: false,
+            linger: None,
+            tcp_keepalive: None,
+            access_log: None,
+            error_handler: Arc::new(GenericErrors),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The most header fields a request may have, `16` by default.
+    /// Requests with more are answered with
+    /// `431 Request Header Fields Too Large`.
+    pub fn max_headers(mut self, count: usize) -> Self {
+        self.max_headers = count;
+        self
+    }
+
+    /// The largest request line plus headers, 16 KiB by default, answered
+    /// with `431 Request Header Fields Too Large` beyond that.
+    pub fn max_header_bytes(mut self, bytes: usize) -> Self {
+        self.max_header_bytes = bytes;
+        self
+    }
+
+    /// The longest request target, 8 KiB by default, answered with
+    /// `414 URI Too Long` beyond that.
+    pub fn max_uri_length(mut self, bytes: usize) -> Self {
+        self.max_uri_length = bytes;
+        self
+    }
+
+    /// The most headers a handler may add to a response, `64` by default,
+    /// not counting those the server writes itself. See
+    /// [`Response::header`](crate::Response::header) for what happens
+    /// beyond that.
+    pub fn max_response_headers(mut self, count: usize) -> Self {
+        self.max_response_headers = count;
+        self
+    }
+
+    /// How long a request may take from the moment its head was read,
+    /// stamped on it as [`Request::deadline`](crate::Request::deadline) for
+    /// handlers to budget their work by. Unset by default; it isn't
+    /// enforced by the server.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps connections open for further requests, as HTTP/1.1 clients
+    /// expect unless they send `Connection: close`. On by default; off
+    /// answers every request with `Connection: close` and closes the
+    /// connection after it.
+    pub fn keep_alive(mut self, enabled: bool) -> Self {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// How long an open connection may wait for its next request before
+    /// the server closes it, told to clients as `Keep-Alive: timeout=N`.
+    /// Unset by default, which leaves idle connections open until the
+    /// client closes them.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// The most requests served on one connection, the last of them
+    /// answered with `Connection: close`; clients are told how many are
+    /// left as `Keep-Alive: max=N`. Unlimited by default.
+    pub fn max_requests_per_connection(mut self, count: usize) -> Self {
+        self.max_requests_per_connection = Some(count);
+        self
+    }
+
+    /// The most connections served at once, with what happens to those
+    /// beyond, see [`Overload`]. Unlimited by default, which lets a burst
+    /// of clients start as many coroutines as it takes.
+    ///
+    /// ```ignore
+    /// ServerConfig::new().max_connections(10_000, Overload::Reject { retry_after: Duration::from_secs(5) })
+    /// ```
+    pub fn max_connections(mut self, max: usize, overload: Overload) -> Self {
+        self.max_connections = Some((max, overload));
+        self
+    }
+
+    /// Binds `listeners` sockets to each address with `SO_REUSEPORT`, every
+    /// one with a coroutine accepting on it, for the kernel to spread new
+    /// connections among them instead of one loop accepting them all. One
+    /// per worker thread is a good start:
+    ///
+    /// ```ignore
+    /// ServerConfig::new().reuse_port(num_cpus::get())
+    /// ```
+    ///
+    /// Other servers binding the port with the option, such as more
+    /// processes of the same user, take a share of its connections too.
+    /// Servers of an [`HttpServiceFactory`](crate::HttpServiceFactory)
+    /// still hand connections to one loop creating their services. Unset
+    /// by default, which binds one listener per address, as happens on
+    /// systems without the option such as Windows.
+    pub fn reuse_port(mut self, listeners: usize) -> Self {
+        self.reuse_port = Some(listeners);
+        self
+    }
+
+    /// The most connections the system queues for a listener before they
+    /// are accepted, `128` by default as for `TcpListener::bind`. Systems
+    /// cap it, such as Linux at `net.core.somaxconn`.
+    pub fn backlog(mut self, connections: u32) -> Self {
+        self.backlog = connections;
+        self
+    }
+
+    /// The size of the receive buffer of connections, `SO_RCVBUF`. Set on
+    /// the listeners, which connections inherit it from, for the TCP window
+    /// to be scaled to it from the handshake on. System default if unset.
+    pub fn recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    /// The size of the send buffer of connections, `SO_SNDBUF`, set as
+    /// [`recv_buffer_size`](Self::recv_buffer_size) is. System default if
+    /// unset.
+    pub fn send_buffer_size(mut self, bytes: usize) -> Self {
+        self.send_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on connections, sending small responses without
+    /// waiting to fill a packet. Off by default; responses are written
+    /// whole, which leaves little for Nagle's algorithm to delay.
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Sets `SO_LINGER` on connections: closing one waits up to `timeout`
+    /// for unsent data to go out, blocking the worker thread meanwhile on
+    /// some systems. `Duration::ZERO` resets connections on close instead,
+    /// sparing the server `TIME_WAIT` sockets at the risk of clients losing
+    /// the end of the last response. Unset by default, which closes
+    /// connections in the background.
+    pub fn linger(mut self, timeout: Duration) -> Self {
+        self.linger = Some(timeout);
+        self
+    }
+
+    /// Enables TCP keepalive on connections, probing those idle for
+    /// `idle` to find peers that went away without closing them. Off by
+    /// default.
+    pub fn tcp_keepalive(mut self, idle: Duration) -> Self {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// How long a client may take to send a request head, counted from the
+    /// connection opening for the first request and from the first byte
+    /// of the head for later ones. Requests taking longer are answered
+    /// with `408 Request Timeout` and the connection closed, which keeps
+    /// clients trickling in headers from holding connections open. Unset
+    /// by default.
+    pub fn header_timeout(mut self, timeout: Duration) -> Self {
+        self.header_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a read of the request body may wait for data. A body that
+    /// stops arriving for longer is answered with `408 Request Timeout`
+    /// and the connection closed. Unset by default.
+    pub fn body_timeout(mut self, timeout: Duration) -> Self {
+        self.body_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a write of the response may wait for the client to take
+    /// data, after which the connection is closed. Unset by default, which
+    /// lets clients that stop reading hold the connection open.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Logs every request head and the response written for it, as well
+    /// as the bytes of requests that can't be parsed, at `info` level.
+    /// Meant for troubleshooting misbehaving clients, as it logs headers
+    /// such as `Authorization` and `Cookie` in the clear.
+    pub fn log_exchanges(mut self, enabled: bool) -> Self {
+        self.log_exchanges = enabled;
+        self
+    }
+
+    /// Has [`Response::json`](crate::Response::json) indent its output for
+    /// people to read, off by default. Meant for development, such as
+    /// behind an environment variable:
+    ///
+    /// ```ignore
+    /// ServerConfig::new().pretty_json(std::env::var_os("PRETTY_JSON").is_some())
+    /// ```
+    pub fn pretty_json(mut self, enabled: bool) -> Self {
+        self.pretty_json = enabled;
+        self
+    }
+
+    /// Dates responses by `clock` instead of the system time, such as a
+    /// [`FixedClock`](crate::date::FixedClock) in tests. Unset by default,
+    /// which reads the time without a call per response.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// The charset added to a `text/*` Content-Type that names none, such
+    /// as `text/plain` becoming `text/plain; charset=utf-8`, so that
+    /// browsers and proxies don't guess. `utf-8` by default, the encoding
+    /// of Rust strings; `None` sends Content-Types as they are.
+    pub fn text_charset(mut self, charset: Option<&'static str>) -> Self {
+        self.text_charset = charset;
+        self
+    }
+
+    /// Calls `hook` with what was sent for every response, for access
+    /// logs and metrics. Unset by default, which takes no measurements.
+    ///
+    /// ```ignore
+    /// ServerConfig::new().on_response(|stats| {
+    ///     metrics::histogram!("ttfb_seconds").record(stats.time_to_first_byte);
+    ///     metrics::counter!("sent_bytes").increment(stats.bytes);
+    /// })
+    /// ```
+    pub fn on_response<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ResponseStats) + Send + Sync + 'static,
+    {
+        self.on_response = Some(ResponseHook(Arc::new(hook)));
+        self
+    }
+
+    /// Writes a line per request to `log`, see [`AccessLog`]. Unset by
+    /// default.
+    pub fn access_log(mut self, log: AccessLog) -> Self {
+        self.access_log = Some(log);
+        self
+    }
+
+    /// Decides what clients are told when a service fails, see
+    /// [`ErrorHandler`]. [`GenericErrors`] by default, which answers with
+    /// a bare `500 Internal Server Error`:
+    ///
+    /// ```ignore
+    /// let config = if cfg!(debug_assertions) {
+    ///     ServerConfig::new().error_handler(DetailedErrors)
+    /// } else {
+    ///     ServerConfig::new()
+    /// };
+    /// ```
+    pub fn error_handler(mut self, handler: impl ErrorHandler + 'static) -> Self {
+        self.error_handler = Arc::new(handler);
+        self
+    }
+
+    /// Lets `handle` shut the server down gracefully, see
+    /// [`ShutdownHandle`]. Unset by default, which leaves stopping the
+    /// server to the end of the process.
+    pub fn shutdown(mut self, handle: ShutdownHandle) -> Self {
+        self.shutdown = Some(handle);
+        self
+    }
+
+    /// Trusts the `Forwarded` and `X-Forwarded-For` headers of requests
+    /// coming from these networks, such as a load balancer's, for
+    /// [`Request::client_ip`](crate::Request::client_ip). Networks are
+    /// written like `10.0.0.0/8`, a plain address is a network of one.
+    pub fn trusted_proxies<I>(mut self, proxies: I) -> Result<Self, InvalidCidr>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for proxy in proxies {
+            self.trusted_proxies.push(proxy.as_ref().parse()?);
+        }
+        Ok(self)
+    }
+
+    /// Whether `ip` belongs to a trusted proxy.
+    pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// What a server does with connections over
+/// [`ServerConfig::max_connections`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overload {
+    /// Stops accepting until a connection closes, leaving new ones
+    /// waiting in the listen backlog.
+    Queue,
+    /// Answers new connections with `503 Service Unavailable` and a
+    /// `Retry-After` of `retry_after` in whole seconds, then closes them.
+    Reject { retry_after: Duration },
+}
+
+/// What the server sent for one request, see
+/// [`ServerConfig::on_response`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseStats {
+    /// The status code, `500` for a handler that failed.
+    pub status: u16,
+    /// Bytes handed to the connection for the response: the head, the
+    /// body with its chunk framing and any `1xx` responses before it.
+    pub bytes: u64,
+    /// From the request head having been read to the response head being
+    /// ready to send, which includes the time spent in the handler.
+    pub time_to_first_byte: Duration,
+    /// From the request head having been read to the whole response being
+    /// written or buffered for the connection; pipelined responses are
+    /// buffered and sent together.
+    pub duration: Duration,
+}
+
+// Boxed `on_response` callback, printed without its contents
+#[derive(Clone)]
+pub(crate) struct ResponseHook(pub(crate) Arc<dyn Fn(&ResponseStats) + Send + Sync>);
+
+impl fmt::Debug for ResponseHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ResponseHook")
+    }
+}
+
+/// A network in CIDR notation, such as `192.168.0.0/16` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket show up as `::ffff:a.b.c.d`
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|&p| p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+/// A trusted proxy that is not an address or network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCidr(String);
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid network `{}`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
@@ -1,6 +1,22 @@
-use std::cell::UnsafeCell;
-use std::fmt::{self, Write};
-use std::sync::Arc;
+//! The time in the `Date` header of responses.
+//!
+//! Servers read the system time, ticked once a second in the background
+//! and formatted at most once a second per thread, so sending the header
+//! takes neither a lock nor a clock read. A [`Clock`] set with
+//! [`ServerConfig::clock`](crate::ServerConfig::clock) or
+//! [`Response::clock`](crate::Response::clock) replaces it, such as a
+//! [`FixedClock`] for tests that compare whole responses:
+//!
+//! ```ignore
+//! let mut rsp = Response::builder().body("");
+//! rsp.clock(Arc::new(FixedClock(UNIX_EPOCH)));
+//! service.call(req, &mut rsp)?;
+//! assert!(contains(&rsp.to_http1_bytes()?, b"Date: Thu, 01 Jan 1970 00:00:00 GMT"));
+//! ```
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bytes::BytesMut;
 use once_cell::sync::Lazy;
@@ -8,54 +24,123 @@ use once_cell::sync::Lazy;
 // "Sun, 06 Nov 1994 08:49:37 GMT".len()
 const DATE_VALUE_LENGTH: usize = 29;
 
-static CURRENT_DATE: Lazy<Arc<DataWrap>> = Lazy::new(|| {
-    let date = Arc::new(DataWrap(UnsafeCell::new(Date::new())));
-    let date_clone = date.clone();
+/// A source of the time responses are dated with, which must be between
+/// the years 1970 and 9999.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+impl fmt::Debug for dyn Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Clock").field(&self.now()).finish()
+    }
+}
+
+/// The system time, read on every call.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Always the same time, for responses that don't change from one run to
+/// the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+// Seconds since the epoch, ticked by a coroutine started on first use
+static CURRENT_SECS: Lazy<&'static AtomicU64> = Lazy::new(|| {
+    static SECS: AtomicU64 = AtomicU64::new(0);
+    SECS.store(unix_secs(SystemTime::now()), Ordering::Relaxed);
     may::go!(move || loop {
-        may::coroutine::sleep(std::time::Duration::from_millis(500));
-        unsafe { &mut *(date_clone.0).get() }.update();
+        may::coroutine::sleep(Duration::from_millis(500));
+        SECS.store(unix_secs(SystemTime::now()), Ordering::Relaxed);
     });
-    date
+    &SECS
 });
 
-struct DataWrap(UnsafeCell<Date>);
-unsafe impl Sync for DataWrap {}
+thread_local! {
+    // the second last formatted on this thread and its header value
+    static FORMATTED: Cell<(u64, [u8; DATE_VALUE_LENGTH])> = const { Cell::new((0, [0; DATE_VALUE_LENGTH])) };
+}
+
+/// Appends the current `Date` value, refreshed once a second.
+#[inline]
+pub(crate) fn append_date(dst: &mut BytesMut) {
+    append_date_at(CURRENT_SECS.load(Ordering::Relaxed), dst);
+}
 
-#[doc(hidden)]
+// Appends the value for `secs`, formatting it only if this thread last
+// formatted another second
 #[inline]
-pub fn append_date(dst: &mut BytesMut) {
-    let date = unsafe { &*CURRENT_DATE.0.get() };
-    dst.extend_from_slice(date.as_bytes());
+fn append_date_at(secs: u64, dst: &mut BytesMut) {
+    let (formatted_secs, mut bytes) = FORMATTED.get();
+    if formatted_secs != secs {
+        format_date(UNIX_EPOCH + Duration::from_secs(secs), &mut bytes);
+        FORMATTED.set((secs, bytes));
+    }
+    dst.extend_from_slice(&bytes);
 }
 
-struct Date {
-    bytes: [u8; DATE_VALUE_LENGTH],
+/// Appends the `Date` value for the time of `clock`, or the current one
+/// without a clock.
+pub(crate) fn append_date_from(clock: Option<&dyn Clock>, dst: &mut BytesMut) {
+    let Some(clock) = clock else {
+        return append_date(dst);
+    };
+    let mut bytes = [0; DATE_VALUE_LENGTH];
+    format_date(clock.now(), &mut bytes);
+    dst.extend_from_slice(&bytes);
 }
 
-impl Date {
-    fn new() -> Date {
-        let mut date = Date {
-            bytes: [0; DATE_VALUE_LENGTH],
-        };
-        date.update();
-        date
-    }
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn format_date(time: SystemTime, bytes: &mut [u8; DATE_VALUE_LENGTH]) {
+    let date = httpdate::HttpDate::from(time).to_string();
+    bytes.copy_from_slice(date.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    #[inline]
-    fn as_bytes(&self) -> &[u8] {
-        &self.bytes
+    // RFC 9110's example date
+    const SECS: u64 = 784_111_777;
+
+    fn appended(append: impl FnOnce(&mut BytesMut)) -> String {
+        let mut dst = BytesMut::new();
+        append(&mut dst);
+        String::from_utf8(dst.to_vec()).unwrap()
     }
 
-    fn update(&mut self) {
-        let t = std::time::SystemTime::now();
-        let date = httpdate::HttpDate::from(t);
-        write!(self, "{date}").unwrap();
+    #[test]
+    fn fixed_clock() {
+        let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(SECS));
+        assert_eq!(appended(|dst| append_date_from(Some(&clock), dst)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let clock = FixedClock(UNIX_EPOCH);
+        assert_eq!(appended(|dst| append_date_from(Some(&clock), dst)), "Thu, 01 Jan 1970 00:00:00 GMT");
     }
-}
 
-impl fmt::Write for Date {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.bytes.copy_from_slice(s.as_bytes());
-        Ok(())
+    #[test]
+    fn formats_once_per_second() {
+        assert_eq!(appended(|dst| append_date_at(SECS, dst)), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        // the same second is served from the cache, even if it went stale
+        FORMATTED.set((SECS, [b'x'; DATE_VALUE_LENGTH]));
+        assert_eq!(appended(|dst| append_date_at(SECS, dst)), "x".repeat(DATE_VALUE_LENGTH));
+
+        assert_eq!(appended(|dst| append_date_at(SECS + 1, dst)), "Sun, 06 Nov 1994 08:49:38 GMT");
+        assert_eq!(FORMATTED.get().0, SECS + 1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,44 @@
+//! decoding of `Content-Encoding` request bodies, behind the
+//! `decompression` feature
+use std::io::{self, Read};
+
+use brotli_decompressor::Decompressor;
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+/// Undoes the codings listed in a `Content-Encoding` header, failing with
+/// `InvalidData` once more than `limit` bytes come out, so a small
+/// compressed body can't expand without bounds.
+///
+/// Unknown codings fail with `Unsupported`, corrupt data with
+/// `InvalidInput`.
+pub(crate) fn decode(content_encoding: &str, body: Vec<u8>, limit: usize) -> io::Result<Vec<u8>> {
+    let mut body = body;
+    // codings are listed in the order they were applied
+    for coding in content_encoding.rsplit(',').map(str::trim) {
+        let decoder: Box<dyn Read + '_> = match coding.to_ascii_lowercase().as_str() {
+            "identity" | "" => continue,
+            "gzip" | "x-gzip" => Box::new(GzDecoder::new(&body[..])),
+            "deflate" => Box::new(ZlibDecoder::new(&body[..])),
+            "br" => Box::new(Decompressor::new(&body[..], 4096)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported content coding `{coding}`"),
+                ));
+            }
+        };
+        let mut decoded = Vec::new();
+        decoder
+            .take((limit as u64).saturating_add(1))
+            .read_to_end(&mut decoded)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if decoded.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed request body exceeds the size limit",
+            ));
+        }
+        body = decoded;
+    }
+    Ok(body)
+}
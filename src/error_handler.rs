@@ -0,0 +1,90 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+/// Decides what clients are told when a service fails with an
+/// `io::Error`, set with
+/// [`ServerConfig::error_handler`](crate::ServerConfig::error_handler).
+///
+/// [`GenericErrors`] by default, which tells them nothing of the error;
+/// [`DetailedErrors`] shows its message, for development. Closures taking
+/// the error answer it as they return:
+///
+/// ```ignore
+/// ServerConfig::new().error_handler(|e: &io::Error| match e.kind() {
+///     io::ErrorKind::TimedOut => ErrorResponse::json(504, "upstream timed out"),
+///     _ => ErrorResponse::json(500, "internal error"),
+/// })
+/// ```
+///
+/// The error is logged at `error` level whatever the answer.
+pub trait ErrorHandler: Send + Sync {
+    fn handle(&self, error: &io::Error) -> ErrorResponse;
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(&io::Error) -> ErrorResponse + Send + Sync,
+{
+    fn handle(&self, error: &io::Error) -> ErrorResponse {
+        self(error)
+    }
+}
+
+impl fmt::Debug for dyn ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+/// The answer to a request whose service failed, see [`ErrorHandler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    /// The status code, sent as `500` if it isn't a valid one.
+    pub status: u16,
+    pub content_type: Cow<'static, str>,
+    pub body: Vec<u8>,
+}
+
+impl ErrorResponse {
+    /// A `text/plain` body.
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        ErrorResponse {
+            status,
+            content_type: Cow::Borrowed("text/plain; charset=utf-8"),
+            body: body.into().into_bytes(),
+        }
+    }
+
+    /// A JSON body of the form `{"error": "<message>"}`.
+    pub fn json(status: u16, message: &str) -> Self {
+        ErrorResponse {
+            status,
+            content_type: Cow::Borrowed("application/json"),
+            body: serde_json::json!({ "error": message }).to_string().into_bytes(),
+        }
+    }
+}
+
+/// Answers every error with `500 Internal Server Error` and nothing more,
+/// keeping what went wrong from clients. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenericErrors;
+
+impl ErrorHandler for GenericErrors {
+    fn handle(&self, _error: &io::Error) -> ErrorResponse {
+        ErrorResponse::text(500, "Internal Server Error")
+    }
+}
+
+/// Answers every error with `500 Internal Server Error` and its message,
+/// for development; the message can reveal file paths, addresses and
+/// the like to clients.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DetailedErrors;
+
+impl ErrorHandler for DetailedErrors {
+    fn handle(&self, error: &io::Error) -> ErrorResponse {
+        ErrorResponse::text(500, error.to_string())
+    }
+}
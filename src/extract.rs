@@ -0,0 +1,477 @@
+//! Typed handler arguments resolved from the request.
+//!
+//! Handlers taking extractors are registered through [`handler`]:
+//!
+//! ```ignore
+//! use karics::extract::{handler, Json, Path, Query};
+//!
+//! fn update_user(
+//!     Path(id): Path<u32>,
+//!     Query(opts): Query<UpdateOptions>,
+//!     Json(user): Json<UpdateUser>,
+//! ) -> Result<Value, HandlerError> {
+//!     // ...
+//! }
+//!
+//! router.put("/users/{id}", handler(update_user))?;
+//! ```
+//!
+//! An argument that can't be extracted answers the request with its error,
+//! `400 Bad Request` for malformed path parameters or query strings.
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::ops::{Deref, DerefMut};
+
+use http::StatusCode;
+use serde::de::value::{Error, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+
+use crate::router::{HandlerError, IntoResponse, Params};
+use crate::Request;
+
+/// Types that can be taken from a request as a handler argument.
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request, params: &Params) -> Result<Self, HandlerError>;
+}
+
+/// The route parameters deserialized into `T`.
+///
+/// A struct or map takes the parameters by name, a tuple in pattern order,
+/// and any other type requires the route to have a single parameter:
+/// `Path<u32>` for `/users/{id}`, `Path<(String, u32)>` for
+/// `/repos/{owner}/{id}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Path<T>(pub T);
+
+/// The query string deserialized into a struct or map `T`, see
+/// [`Request::query`].
+///
+/// A missing query string is read as an empty one, so every field needs a
+/// default or has to be an `Option`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Query<T>(pub T);
+
+/// The JSON body deserialized into `T`, see [`Request::json`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+/// A clone of the value of type `T` in the
+/// [request extensions](Request::extensions), put there by middleware.
+///
+/// Fails with `500 Internal Server Error` if there is none, as that means
+/// the middleware is missing from the route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Extension<T>(pub T);
+
+macro_rules! wrapper {
+    ($($name:ident),*) => {$(
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+    )*};
+}
+
+wrapper!(Path, Query, Json, Extension);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(_: &Request, params: &Params) -> Result<Self, HandlerError> {
+        T::deserialize(PathDeserializer(params))
+            .map(Path)
+            .map_err(|e| HandlerError::with_status(StatusCode::BAD_REQUEST, e))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(_: &Request, params: &Params) -> Result<Self, HandlerError> {
+        from_query(params.query()).map(Query)
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request, _: &Params) -> Result<Self, HandlerError> {
+        req.json().map(Json)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(req: &Request, _: &Params) -> Result<Self, HandlerError> {
+        req.extensions().get::<T>().cloned().map(Extension).ok_or_else(|| {
+            HandlerError::with_status(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("missing request extension `{}`", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+impl FromRequest for Params {
+    fn from_request(_: &Request, params: &Params) -> Result<Self, HandlerError> {
+        Ok(params.clone())
+    }
+}
+
+impl FromRequest for crate::Query {
+    fn from_request(_: &Request, params: &Params) -> Result<Self, HandlerError> {
+        Ok(params.query().clone())
+    }
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &Request, params: &Params) -> Result<Self, HandlerError> {
+        Ok(T::from_request(req, params).ok())
+    }
+}
+
+impl<T: FromRequest> FromRequest for Result<T, HandlerError> {
+    fn from_request(req: &Request, params: &Params) -> Result<Self, HandlerError> {
+        Ok(T::from_request(req, params))
+    }
+}
+
+/// Functions whose arguments are all [`FromRequest`], see [`handler`].
+pub trait ExtractHandler<Args>: Send + Sync + 'static {
+    type Output: IntoResponse;
+
+    fn call(&self, req: &Request, params: &Params) -> Result<Self::Output, HandlerError>;
+}
+
+macro_rules! extract_handler {
+    ($($arg:ident),*) => {
+        #[allow(non_snake_case, unused_variables)]
+        impl<F, R, $($arg,)*> ExtractHandler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($arg: FromRequest,)*
+        {
+            type Output = R;
+
+            fn call(&self, req: &Request, params: &Params) -> Result<R, HandlerError> {
+                $(let $arg = $arg::from_request(req, params)?;)*
+                Ok(self($($arg),*))
+            }
+        }
+    };
+}
+
+extract_handler!();
+extract_handler!(T1);
+extract_handler!(T1, T2);
+extract_handler!(T1, T2, T3);
+extract_handler!(T1, T2, T3, T4);
+extract_handler!(T1, T2, T3, T4, T5);
+extract_handler!(T1, T2, T3, T4, T5, T6);
+extract_handler!(T1, T2, T3, T4, T5, T6, T7);
+extract_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+
+/// Adapts a function taking up to eight extractors into a route handler.
+///
+/// Arguments are extracted in order and the first failure answers the
+/// request; wrap an argument in `Option` to make it optional.
+pub fn handler<H, Args>(
+    handler: H,
+) -> impl Fn(&Request, Params) -> Result<H::Output, HandlerError> + Send + Sync + 'static
+where
+    H: ExtractHandler<Args>,
+{
+    move |req, params| handler.call(req, &params)
+}
+
+/// Deserializes query pairs into `T`, answering `400 Bad Request` if they
+/// don't fit.
+pub(crate) fn from_query<T: DeserializeOwned>(query: &crate::Query) -> Result<T, HandlerError> {
+    T::deserialize(QueryDeserializer(query)).map_err(|e| HandlerError::with_status(StatusCode::BAD_REQUEST, e))
+}
+
+// A single path or query value, parsed as whatever type is asked for
+#[derive(Clone, Copy)]
+struct Value<'de>(&'de str);
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self.0.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(de::Error::invalid_value(Unexpected::Str(self.0), &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Value<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    // `?page=` leaves an optional field unset
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // unit variants by name, such as `?sort=asc`
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+// The query pairs as a map, with the values of a repeated key together so
+// they can fill a sequence such as `tag: Vec<String>`
+struct QueryDeserializer<'de>(&'de crate::Query);
+
+impl<'de> de::Deserializer<'de> for QueryDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut keys: HashMap<&str, usize> = HashMap::new();
+        let mut grouped: Vec<(&str, Vec<&str>)> = Vec::new();
+        for (key, value) in self.0.iter() {
+            match keys.entry(key) {
+                Entry::Occupied(entry) => grouped[*entry.get()].1.push(value),
+                Entry::Vacant(entry) => {
+                    entry.insert(grouped.len());
+                    grouped.push((key, vec![value]));
+                }
+            }
+        }
+        MapDeserializer::new(grouped.into_iter().map(|(key, values)| (key, Values(values)))).deserialize_map(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+// The values of one query key, never empty: a sequence of them, or the
+// first one for anything else
+struct Values<'de>(Vec<&'de str>);
+
+impl<'de> Values<'de> {
+    fn first(&self) -> Value<'de> {
+        Value(self.0[0])
+    }
+}
+
+macro_rules! first_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            self.first().$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Values<'de> {
+    type Error = Error;
+
+    // a repeated key reads as a sequence when the type takes anything
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            self.first().deserialize_any(visitor)
+        }
+    }
+
+    first_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_unit deserialize_map deserialize_identifier deserialize_ignored_any
+    }
+
+    // `?tags=` leaves an optional field unset
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.0.len() == 1 && self.0[0].is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.first().deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.first().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        SeqDeserializer::new(self.0.into_iter().map(Value)).deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Values<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+// The named route parameters, as a map, a sequence or a single value
+struct PathDeserializer<'de>(&'de Params);
+
+impl<'de> PathDeserializer<'de> {
+    fn single(&self) -> Result<Value<'de>, Error> {
+        let mut params = self.0.iter();
+        match (params.next(), params.next()) {
+            (Some((_, value)), None) => Ok(Value(value)),
+            _ => Err(de::Error::custom(format!(
+                "expected a single route parameter, the route has {}",
+                self.0.iter().count()
+            ))),
+        }
+    }
+}
+
+macro_rules! single_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            self.single()?.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for PathDeserializer<'de> {
+    type Error = Error;
+
+    single_value! {
+        deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_option deserialize_unit deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.single()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        SeqDeserializer::new(self.0.iter().map(|(_, value)| Value(value))).deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        MapDeserializer::new(self.0.iter().map(|(name, value)| (name, Value(value)))).deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+}
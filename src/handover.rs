@@ -0,0 +1,203 @@
+use std::env;
+use std::fmt;
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, parent_id};
+use std::process::{self, Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::socket_activation;
+
+// Tells the new process of a handover the id of the old one and the number
+// of sockets passed, as `<pid> <count>`
+const HANDOVER_VAR: &str = "KARICS_HANDOVER";
+
+// The first of the sockets passed, followed by the readiness pipe, as
+// systemd numbers them
+const FIRST_FD: RawFd = 3;
+
+// Whether the sockets were taken over, which happens once per process
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The process a server's listeners were handed to by
+/// [`ShutdownHandle::hand_over`](crate::ShutdownHandle::hand_over), such as
+/// the new binary of a deployment.
+pub struct Successor {
+    child: Child,
+    ready: PipeReader,
+}
+
+impl Successor {
+    /// Waits up to `timeout` for the new process to accept connections,
+    /// which it says with [`Predecessor::ready`], for the old one to start
+    /// draining.
+    ///
+    /// Fails with `ErrorKind::TimedOut` if it doesn't in time, and with
+    /// `ErrorKind::UnexpectedEof` if it exits or drops its [`Predecessor`]
+    /// before; the old process then keeps serving, and should stop the
+    /// new one.
+    pub fn wait_ready(&mut self, timeout: Duration) -> io::Result<()> {
+        let start = Instant::now();
+        let mut poll = libc::pollfd {
+            fd: self.ready.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // polled without blocking, so a coroutine waiting here parks
+        // rather than its thread
+        while unsafe { libc::poll(&mut poll, 1, 0) } == 0 {
+            if start.elapsed() >= timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the new process isn't ready",
+                ));
+            }
+            may::coroutine::sleep(Duration::from_millis(10));
+        }
+        match self.ready.read(&mut [0])? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the new process exited before it was ready",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    pub fn into_child(self) -> Child {
+        self.child
+    }
+}
+
+impl fmt::Debug for Successor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Successor").field("id", &self.id()).finish_non_exhaustive()
+    }
+}
+
+/// The listeners handed over to this process by the one that started it
+/// with [`ShutdownHandle::hand_over`](crate::ShutdownHandle::hand_over).
+pub struct Predecessor {
+    id: u32,
+    listeners: Vec<TcpListener>,
+    ready: PipeWriter,
+}
+
+impl Predecessor {
+    /// Takes over the listeners handed to the process, to start its servers
+    /// on. Call it at startup, while the old process waits for it.
+    ///
+    /// None if the process wasn't started by a handover, as well as from
+    /// calls after the first; child processes don't inherit the listeners.
+    /// Fails if a passed socket is no TCP listener.
+    pub fn take() -> io::Result<Option<Self>> {
+        let Ok(var) = env::var(HANDOVER_VAR) else {
+            return Ok(None);
+        };
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "KARICS_HANDOVER is malformed");
+        let (id, count) = var.split_once(' ').ok_or_else(malformed)?;
+        let id: u32 = id.parse().map_err(|_| malformed())?;
+        let count: RawFd = count.parse().map_err(|_| malformed())?;
+        // the variable may be left over from starting a parent process
+        if id != parent_id() || TAKEN.swap(true, Ordering::SeqCst) {
+            return Ok(None);
+        }
+        // owned first, so the old process hears of a failure below by the
+        // pipe closing
+        let ready_fd = FIRST_FD.saturating_add(count);
+        set_cloexec(ready_fd)?;
+        let ready = PipeWriter::from(unsafe { OwnedFd::from_raw_fd(ready_fd) });
+        let listeners = (FIRST_FD..ready_fd)
+            .map(|fd| socket_activation::listener(fd, "the previous process"))
+            .collect::<io::Result<_>>()?;
+        Ok(Some(Predecessor { id, listeners, ready }))
+    }
+
+    /// The process id of the old process.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The listeners, in the order the old process's servers were started
+    /// on them; empty after the first call.
+    pub fn take_listeners(&mut self) -> Vec<TcpListener> {
+        std::mem::take(&mut self.listeners)
+    }
+
+    /// Tells the old process the servers are started on the listeners, for
+    /// it to stop accepting and drain its connections.
+    pub fn ready(mut self) -> io::Result<()> {
+        self.ready.write_all(&[1])
+    }
+}
+
+impl fmt::Debug for Predecessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Predecessor")
+            .field("id", &self.id)
+            .field("listeners", &self.listeners)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Starts `command` with `listeners` and the pipe it tells it is ready by.
+pub(crate) fn spawn(listeners: &[std::net::TcpListener], mut command: Command) -> io::Result<Successor> {
+    if listeners.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no listeners to hand over"));
+    }
+    let (ready, ready_tx) = io::pipe()?;
+    let count = RawFd::try_from(listeners.len()).unwrap_or(RawFd::MAX);
+    // copied above the numbers they are given in the new process first, so
+    // placing one there can't close another
+    let above = FIRST_FD.saturating_add(count).saturating_add(1);
+    let copies = listeners
+        .iter()
+        .map(|listener| dup_above(listener.as_raw_fd(), above))
+        .chain(std::iter::once(dup_above(ready_tx.as_raw_fd(), above)))
+        .collect::<io::Result<Vec<OwnedFd>>>()?;
+    let fds: Vec<RawFd> = copies.iter().map(AsRawFd::as_raw_fd).collect();
+    command.env(HANDOVER_VAR, format!("{} {count}", process::id()));
+    // safety: only `dup2` runs between fork and exec, which is
+    // async-signal-safe and allocates nothing
+    unsafe {
+        command.pre_exec(move || {
+            for (target, &fd) in (FIRST_FD..).zip(&fds) {
+                // unlike its source, the copy stays open across exec
+                if libc::dup2(fd, target) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    // the pipe reads end of file once the new process alone holds it and
+    // closes it
+    drop((copies, ready_tx));
+    Ok(Successor { child, ready })
+}
+
+// A copy of `fd` numbered `min` or more, closed on exec
+fn dup_above(fd: RawFd, min: RawFd) -> io::Result<OwnedFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min) } {
+        -1 => Err(io::Error::last_os_error()),
+        copy => Ok(unsafe { OwnedFd::from_raw_fd(copy) }),
+    }
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,17 +1,23 @@
 //! http server implementation on top of `MAY`
-use std::io::{self, Read, Write};
+use std::fs::File;
+use std::io::{self, IoSlice, Read, Write};
 use std::mem::MaybeUninit;
-use std::net::ToSocketAddrs;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::request::{self, Request};
-use crate::response::{self, Response};
+use crate::config::{Overload, ResponseStats, ServerConfig};
+use crate::date::Clock;
+use crate::request::{self, Conn, ConnState, Request};
+use crate::response::{self, Deferred, Response};
+use crate::shutdown::{Registration, ShutdownHandle};
 
-#[cfg(unix)]
-use bytes::Buf;
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use smallvec::SmallVec;
 #[cfg(unix)]
 use may::io::WaitIo;
 use may::net::{TcpListener, TcpStream};
+use may::sync::{Condvar, Mutex};
 use may::{coroutine, go};
 
 
@@ -40,10 +46,62 @@ pub trait HttpServiceFactory: Send + Sized + 'static {
     // create a new http service for each connection
     fn new_service(&self, id: usize) -> Self::Service;
 
+    /// Called for every connection accepted from `peer`, before its service
+    /// is created, for the requests on it to see the state it returns as
+    /// [`Request::conn_state`]. A state of just the peer by default.
+    fn on_connect(&self, peer: SocketAddr) -> ConnState {
+        ConnState::new(peer)
+    }
+
+    /// Called with the state of a connection once it is closed. It runs in
+    /// the coroutine that served the connection, without the factory, so
+    /// what it needs goes in the state, such as a handle to metrics.
+    fn on_disconnect(state: ConnState) {
+        let _ = state;
+    }
+
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
-        let listener = TcpListener::bind(addr)?;
+        self.start_with(addr, ServerConfig::default())
+    }
+
+    /// Like [`start`](Self::start), with the given server settings.
+    fn start_with<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: ServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        self.start_on([addr], config)
+    }
+
+    /// Like [`start_with`](Self::start_with), listening on every address of
+    /// `addrs`, such as `["0.0.0.0:8080", "[::]:8080"]`. Connections to all
+    /// of them share the factory and the settings, `max_connections`
+    /// counting them together.
+    fn start_on<I>(self, addrs: I, config: ServerConfig) -> io::Result<coroutine::JoinHandle<()>>
+    where
+        I: IntoIterator,
+        I::Item: ToSocketAddrs,
+    {
+        self.start_from(bind_all(addrs, &config)?, config)
+    }
+
+    /// Like [`start_on`](Self::start_on), accepting on listeners bound
+    /// beforehand, such as those of [`systemd_listeners`](crate::systemd_listeners)
+    /// or of tests binding port `0` themselves. The socket settings of
+    /// `config` applying to listeners are left to whoever bound them.
+    fn start_from<I>(
+        self,
+        listeners: I,
+        config: ServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>>
+    where
+        I: IntoIterator<Item = net::TcpListener>,
+    {
+        let listeners = adopt(listeners, &config)?;
+        let config = Arc::new(config);
+        let slots = config.max_connections.map(Slots::new);
         go!(
             coroutine::Builder::new().name("TcpServerFac".to_owned()),
             move || {
@@ -51,29 +109,285 @@ pub trait HttpServiceFactory: Send + Sized + 'static {
                 use std::os::fd::AsRawFd;
                 #[cfg(windows)]
                 use std::os::windows::io::AsRawSocket;
-                for stream in listener.incoming() {
-                    let mut stream = t_c!(stream);
+                let incoming = accept_all(listeners, config.shutdown.clone());
+                accept_loop(incoming, &config, slots, |stream, slot| {
                     #[cfg(unix)]
                     let id = stream.as_raw_fd() as usize;
                     #[cfg(windows)]
                     let id = stream.as_raw_socket() as usize;
-                    // t_c!(stream.set_nodelay(true));
+                    let state = self.on_connect(stream.peer_addr()?);
                     let service = self.new_service(id);
+                    let config = config.clone();
                     let builder = may::coroutine::Builder::new().id(id);
-                    go!(
-                        builder,
-                        move || if let Err(e) = each_connection_loop(&mut stream, service) {
-                            error!("service err = {:?}", e);
-                            stream.shutdown(std::net::Shutdown::Both).ok();
-                        }
-                    )
-                    .unwrap();
-                }
+                    go!(builder, move || {
+                        Self::on_disconnect(serve_connection(stream, service, config, state));
+                        drop(slot);
+                    })
+                    .map(drop)
+                });
             }
         )
     }
 }
 
+// Binds the listeners for each of `addrs`
+pub(crate) fn bind_all<I>(addrs: I, config: &ServerConfig) -> io::Result<Vec<net::TcpListener>>
+where
+    I: IntoIterator,
+    I::Item: ToSocketAddrs,
+{
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        listeners.extend(bind(addr, config)?);
+    }
+    Ok(listeners)
+}
+
+// Takes over `listeners` for a server, which the shutdown is to wake
+fn adopt<I>(listeners: I, config: &ServerConfig) -> io::Result<Vec<TcpListener>>
+where
+    I: IntoIterator<Item = net::TcpListener>,
+{
+    let listeners = listeners
+        .into_iter()
+        .map(TcpListener::from_std)
+        .collect::<io::Result<Vec<_>>>()?;
+    if listeners.is_empty() {
+        return err(io::Error::new(io::ErrorKind::InvalidInput, "no address to listen on"));
+    }
+    if let Some(shutdown) = &config.shutdown {
+        for listener in &listeners {
+            if !shutdown.listen(listener)? {
+                return err(io::Error::other("the server was shut down"));
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+// Binds the listeners for `addr` on the first address it resolves to that
+// can be bound, those after the first one of `ServerConfig::reuse_port`
+// sharing its port
+fn bind<L: ToSocketAddrs>(addr: L, config: &ServerConfig) -> io::Result<Vec<net::TcpListener>> {
+    let mut first = err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    ));
+    for addr in addr.to_socket_addrs()? {
+        first = bind_socket(addr, config);
+        if first.is_ok() {
+            break;
+        }
+    }
+    let mut listeners = vec![first?];
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    for _ in 1..config.reuse_port.unwrap_or(1) {
+        let addr = listeners[0].local_addr()?;
+        listeners.push(bind_socket(addr, config)?);
+    }
+    Ok(listeners)
+}
+
+// Binds a listener with the socket settings of `config`
+fn bind_socket(addr: SocketAddr, config: &ServerConfig) -> io::Result<net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    // as `TcpListener::bind` does, letting a restarted server bind while
+    // connections of the last one linger
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    if config.reuse_port.is_some() {
+        socket.set_reuse_port(true)?;
+    }
+    if let Some(bytes) = config.recv_buffer_size {
+        socket.set_recv_buffer_size(bytes)?;
+    }
+    if let Some(bytes) = config.send_buffer_size {
+        socket.set_send_buffer_size(bytes)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(i32::try_from(config.backlog).unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+// Applies the socket settings of `config` to a connection just accepted
+fn tune(stream: &TcpStream, config: &ServerConfig) -> io::Result<()> {
+    let socket = socket2::SockRef::from(stream.inner());
+    if config.nodelay {
+        socket.set_nodelay(true)?;
+    }
+    if let Some(linger) = config.linger {
+        socket.set_linger(Some(linger))?;
+    }
+    if let Some(idle) = config.tcp_keepalive {
+        socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))?;
+    }
+    Ok(())
+}
+
+// The connections accepted on `listeners`. Several listeners are accepted
+// on by a coroutine each, passing connections on to the caller's loop
+// until the shutdown wakes them or the iterator is dropped, which cancels
+// them and so closes their listeners; it's dropped along with the server
+// coroutine, also when that is cancelled.
+fn accept_all(
+    mut listeners: Vec<TcpListener>,
+    shutdown: Option<ShutdownHandle>,
+) -> Box<dyn Iterator<Item = io::Result<TcpStream>>> {
+    if listeners.len() == 1 {
+        let listener = listeners.remove(0);
+        return Box::new(std::iter::from_fn(move || Some(listener.accept().map(|(stream, _)| stream))));
+    }
+    let (tx, rx) = may::sync::mpsc::channel();
+    let mut acceptors = Acceptors(Vec::with_capacity(listeners.len()));
+    for listener in listeners {
+        let tx = tx.clone();
+        let shutdown = shutdown.clone();
+        let acceptor = go!(move || {
+            for stream in listener.incoming() {
+                let stopped = shutdown.as_ref().is_some_and(|shutdown| shutdown.is_shutdown());
+                if stopped || tx.send(stream).is_err() {
+                    break;
+                }
+            }
+        });
+        acceptors.0.push(acceptor);
+    }
+    Box::new(std::iter::from_fn(move || {
+        // held by the iterator, so they're cancelled when it's dropped
+        let _acceptors = &acceptors;
+        rx.recv().ok()
+    }))
+}
+
+// The coroutines accepting on the listeners of one server, cancelled
+// together once it stops
+struct Acceptors(Vec<coroutine::JoinHandle<()>>);
+
+impl Drop for Acceptors {
+    fn drop(&mut self) {
+        for acceptor in &self.0 {
+            if !acceptor.is_done() {
+                // safety: an acceptor holds nothing but its listener and a
+                // sender, which are dropped as it unwinds
+                unsafe { acceptor.coroutine().cancel() };
+            }
+        }
+    }
+}
+
+// Accepts the connections of `incoming` until the shutdown, counting them
+// against `ServerConfig::max_connections` and handing them to `spawn` to
+// be served
+fn accept_loop<I, F>(incoming: I, config: &ServerConfig, slots: Option<Arc<Slots>>, mut spawn: F)
+where
+    I: Iterator<Item = io::Result<TcpStream>>,
+    F: FnMut(TcpStream, Option<Slot>) -> io::Result<()>,
+{
+    let shutdown = config.shutdown.as_ref();
+    for stream in incoming {
+        // the shutdown wakes the loop with a failed accept or a connection of its own
+        if shutdown.is_some_and(|shutdown| shutdown.is_shutdown()) {
+            break;
+        }
+        let stream = t_c!(stream);
+        let slot = match &slots {
+            Some(slots) => match slots.take() {
+                Ok(slot) => Some(slot),
+                Err(retry_after) => {
+                    refuse(stream, retry_after, config);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        t_c!(tune(&stream, config));
+        t_c!(spawn(stream, slot));
+    }
+}
+
+// Serves the connection on `stream` until it closes, then switches it to
+// the protocol of an upgrade, returning its state
+fn serve_connection<T: HttpService>(
+    mut stream: TcpStream,
+    service: T,
+    config: Arc<ServerConfig>,
+    state: ConnState,
+) -> ConnState {
+    let mut conn = Conn::new(&stream, config, state);
+    if let Err(e) = each_connection_loop(&mut stream, service, &mut conn) {
+        error!("service err = {:?}", e);
+        stream.shutdown(std::net::Shutdown::Both).ok();
+    } else if let Some((upgrade, buffered)) = conn.take_upgrade()
+        && let Err(e) = upgrade.run(stream, buffered)
+    {
+        error!("upgrade err = {:?}", e);
+    }
+    conn.into_state()
+}
+
+// The connections a server has open, held to `ServerConfig::max_connections`
+struct Slots {
+    max: usize,
+    overload: Overload,
+    open: Mutex<usize>,
+    freed: Condvar,
+}
+
+// A connection counted in `Slots`, until it drops
+struct Slot(Arc<Slots>);
+
+impl Slots {
+    fn new((max, overload): (usize, Overload)) -> Arc<Self> {
+        Arc::new(Slots {
+            max,
+            overload,
+            open: Mutex::new(0),
+            freed: Condvar::new(),
+        })
+    }
+
+    // Counts a connection just accepted, first waiting for another one to
+    // close when at the limit, or refusing it with the `Retry-After` to
+    // answer it with
+    fn take(self: &Arc<Self>) -> Result<Slot, Duration> {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max {
+            match self.overload {
+                Overload::Queue => open = self.freed.wait(open).unwrap(),
+                Overload::Reject { retry_after } => return Err(retry_after),
+            }
+        }
+        *open += 1;
+        Ok(Slot(self.clone()))
+    }
+}
+
+// How long a refused connection may take to receive its 503
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Answers a connection over `ServerConfig::max_connections` and closes it.
+// The 503 is written on a coroutine of its own, so a client that doesn't
+// read holds up neither the accept loop nor the connection for long
+fn refuse(stream: TcpStream, retry_after: Duration, config: &ServerConfig) {
+    let mut buf = BytesMut::new();
+    response::encode_overload(retry_after, config.clock.as_deref(), &mut buf);
+    go!(move || {
+        let mut stream = stream;
+        if stream.set_write_timeout(Some(REFUSAL_TIMEOUT)).is_ok() {
+            stream.write_all(&buf).ok();
+        }
+    });
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
 #[inline]
 #[cold]
 pub(crate) fn err<T>(e: io::Error) -> io::Result<T> {
@@ -95,53 +409,596 @@ pub(crate) fn reserve_buf(buf: &mut BytesMut) {
 pub struct HttpServer<T>(pub T);
 
 #[cfg(unix)]
-fn each_connection_loop<T: HttpService>(stream: &mut TcpStream, mut service: T) -> io::Result<()> {
-    let mut req_buf = BytesMut::with_capacity(BUF_LEN);
-    let mut rsp_buf = BytesMut::with_capacity(BUF_LEN);
-    let mut body_buf = BytesMut::with_capacity(4096);
+#[inline]
+fn nonblock_read(stream: &mut impl Read, req_buf: &mut BytesMut) -> io::Result<bool> {
+    reserve_buf(req_buf);
+    let read_buf: &mut [u8] = unsafe { std::mem::transmute(req_buf.chunk_mut()) };
+    let len = read_buf.len();
+
+    let mut read_cnt = 0;
+    while read_cnt < len {
+        match stream.read(unsafe { read_buf.get_unchecked_mut(read_cnt..) }) {
+            Ok(0) => return err(io::Error::new(io::ErrorKind::BrokenPipe, "closed")),
+            Ok(n) => read_cnt += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => return err(e),
+        }
+    }
+
+    unsafe { req_buf.advance_mut(read_cnt) };
+    Ok(read_cnt < len)
+}
+
+#[cfg(unix)]
+#[inline]
+fn nonblock_write(stream: &mut impl Write, rsp_buf: &mut BytesMut) -> io::Result<usize> {
+    let write_buf = rsp_buf.chunk();
+    let len = write_buf.len();
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let mut written = 0;
+    while written < len {
+        match stream.write(unsafe { write_buf.get_unchecked(written..) }) {
+            Ok(0) => return err(io::Error::new(io::ErrorKind::BrokenPipe, "closed")),
+            Ok(n) => written += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => return err(e),
+        }
+    }
+    rsp_buf.advance(written);
+    Ok(written)
+}
+
+// The keep-alive settings of a server, with the requests a connection
+// has left under them
+struct KeepAlive {
+    enabled: bool,
+    timeout: Option<Duration>,
+    left: Option<usize>,
+}
+
+impl KeepAlive {
+    fn new(config: &ServerConfig) -> Self {
+        KeepAlive {
+            enabled: config.keep_alive,
+            timeout: config.keep_alive_timeout,
+            left: config.max_requests_per_connection,
+        }
+    }
+
+    // Counts a request, returning whether the connection stays open after
+    // it when the client asked for that
+    fn admit(&mut self, requested: bool) -> bool {
+        if let Some(left) = &mut self.left {
+            *left = left.saturating_sub(1);
+        }
+        requested && self.enabled && self.left != Some(0)
+    }
 
+    // The `Keep-Alive` header telling clients the limits, if there are any
+    fn header(&self) -> Option<String> {
+        let timeout = self.timeout.map(|timeout| format!("timeout={}", timeout.as_secs()));
+        let max = self.left.map(|left| format!("max={left}"));
+        match (timeout, max) {
+            (Some(timeout), Some(max)) => Some(format!("Keep-Alive: {timeout}, {max}")),
+            (Some(limit), None) | (None, Some(limit)) => Some(format!("Keep-Alive: {limit}")),
+            (None, None) => None,
+        }
+    }
+}
+
+// Tells the client whether the connection stays open, HTTP/1.1 keeps it
+// open unless told otherwise, and under which limits. HTTP/1.0 clients get
+// no informational responses either.
+fn connection_header(rsp: &mut Response, version: u8, keep_alive: bool, limits: &KeepAlive) {
+    if version == 0 {
+        rsp.drop_informational();
+    }
+    if !keep_alive {
+        rsp.server_header("Connection: close");
+        return;
+    }
+    if version == 0 {
+        rsp.server_header("Connection: keep-alive");
+    }
+    if let Some(header) = limits.header() {
+        rsp.server_header(header);
+    }
+}
+
+// The read timeouts of a connection, see `ServerConfig::header_timeout`,
+// `keep_alive_timeout` and `body_timeout`
+struct ReadTimeouts {
+    header: Option<Duration>,
+    idle: Option<Duration>,
+    body: Option<Duration>,
+    // when the head being received is due, the first from the connection
+    // opening and later ones from their first byte
+    head_deadline: Option<Instant>,
+}
+
+impl ReadTimeouts {
+    fn new(config: &ServerConfig) -> Self {
+        ReadTimeouts {
+            header: config.header_timeout,
+            idle: config.keep_alive_timeout,
+            body: config.body_timeout,
+            head_deadline: config.header_timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    // Stops the header timeout once a request was answered
+    fn served(&mut self) {
+        self.head_deadline = None;
+    }
+
+    // Starts the header timeout if part of a request head is buffered
+    fn receiving(&mut self, buffered: bool) {
+        if buffered && self.head_deadline.is_none() {
+            self.head_deadline = self.header.map(|timeout| Instant::now() + timeout);
+        }
+    }
+
+    // How long to wait for more of a request, `waiting` for a connection
+    // between requests, and whether running out of it is the header timeout
+    fn limit(&self, waiting: bool) -> Option<(Duration, bool)> {
+        let idle = self.idle.filter(|_| waiting);
+        let head = self.head_deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (idle, head) {
+            (Some(idle), Some(head)) if idle < head => Some((idle, false)),
+            (_, Some(head)) => Some((head, true)),
+            (Some(idle), None) => Some((idle, false)),
+            (None, None) => None,
+        }
+    }
+}
+
+// Reads more of the next request into `buf`, `None` if nothing arrived
+// within `timeout`. The read timeout is put back to `body_timeout` after,
+// for handlers reading the body.
+fn read_within(
+    stream: &mut TcpStream,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+    body_timeout: Option<Duration>,
+) -> io::Result<Option<usize>> {
+    if timeout.is_some_and(|timeout| timeout.is_zero()) {
+        return Ok(None);
+    }
+    if timeout != body_timeout {
+        stream.set_read_timeout(timeout)?;
+    }
+    let read = stream.read(buf);
+    if timeout != body_timeout {
+        stream.set_read_timeout(body_timeout)?;
+    }
+    match read {
+        Ok(n) => Ok(Some(n)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => Ok(None),
+        Err(e) => err(e),
+    }
+}
+
+// Answers a request head over the limits before the connection is closed,
+// `log` holds the bytes received if exchanges are logged
+#[cold]
+fn reject(
+    mut stream: &TcpStream,
+    rsp_buf: &mut BytesMut,
+    e: io::Error,
+    clock: Option<&dyn Clock>,
+    log: Option<&[u8]>,
+) -> io::Result<()> {
+    let start = rsp_buf.len();
+    if let Some(rejection) = request::rejection(&e) {
+        response::encode_rejection(rejection.code, rejection.reason, clock, rsp_buf);
+        stream.write_all(rsp_buf).ok();
+    }
+    if let Some(received) = log {
+        log_exchange(stream, received, &rsp_buf[start..]);
+    }
+    err(e)
+}
+
+// Answers a request head that took longer than the header timeout
+#[cold]
+fn head_timeout(
+    stream: &TcpStream,
+    rsp_buf: &mut BytesMut,
+    clock: Option<&dyn Clock>,
+    log: Option<&[u8]>,
+) -> io::Result<()> {
+    reject(stream, rsp_buf, request::reject(408, "Request Timeout"), clock, log)
+}
+
+// Logs a request head and the response to it, see `ServerConfig::log_exchanges`
+#[cold]
+fn log_exchange(stream: &TcpStream, request: &[u8], response: &[u8]) {
+    let peer = stream.peer_addr().map_or_else(|_| "unknown peer".to_owned(), |addr| addr.to_string());
+    info!(
+        "exchange with {peer}\n>>> request\n{}\n<<< response\n{}",
+        String::from_utf8_lossy(request).trim_end(),
+        String::from_utf8_lossy(response).trim_end(),
+    );
+}
+
+// Sends a body `encode` left out, after the responses buffered before it,
+// returning its length with any chunk framing
+fn write_deferred(mut stream: &TcpStream, rsp_buf: &mut BytesMut, body: Deferred) -> io::Result<u64> {
+    match body {
+        Deferred::Stream(write, trailers) => {
+            let buffered = rsp_buf.len() as u64;
+            Ok(response::write_stream(write, trailers, &mut stream, rsp_buf)? - buffered)
+        }
+        Deferred::File(file, len) => {
+            stream.write_all(rsp_buf)?;
+            rsp_buf.clear();
+            send_file(stream, file, len)?;
+            Ok(len)
+        }
+        Deferred::Bytes(body) => {
+            write_vectored(stream, rsp_buf, &body)?;
+            Ok(body.iter().map(|bytes| bytes.len() as u64).sum())
+        }
+    }
+}
+
+// Writes the buffered responses with `body` after them, in vectored writes
+// rather than copying `body` into the buffer
+fn write_vectored(mut stream: &TcpStream, rsp_buf: &mut BytesMut, body: &[Bytes]) -> io::Result<()> {
+    let mut slices: SmallVec<[IoSlice; 2]> = std::iter::once(&rsp_buf[..])
+        .chain(body.iter().map(|bytes| &bytes[..]))
+        .filter(|slice| !slice.is_empty())
+        .map(IoSlice::new)
+        .collect();
+    let mut unwritten = &mut slices[..];
+    while !unwritten.is_empty() {
+        let n = stream.write_vectored(unwritten)?;
+        if n == 0 {
+            return err(io::Error::new(io::ErrorKind::WriteZero, "closed"));
+        }
+        IoSlice::advance_slices(&mut unwritten, n);
+    }
+    drop(slices);
+    rsp_buf.clear();
+    Ok(())
+}
+
+// Copies `len` bytes of a regular file to the socket. std does so with
+// `sendfile` on Linux, which moves the file position along, so after the
+// nonblocking socket fills up the copy resumes from there.
+#[cfg(unix)]
+fn send_file(stream: &TcpStream, mut file: File, len: u64) -> io::Result<()> {
+    use std::io::Seek;
+
+    let end = file.stream_position()? + len;
     loop {
-        let read_blocked = nonblock_read(stream.inner_mut(), &mut req_buf)?;
+        let left = end - file.stream_position()?;
+        if left == 0 {
+            return Ok(());
+        }
+        match io::copy(&mut (&file).take(left), &mut stream.inner()) {
+            Ok(0) => return err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while sent")),
+            Ok(_) => {}
+            // only the stream's own writes wait no longer than the write timeout
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && stream.write_timeout()?.is_some() => {
+                return copy_file(stream, &file, left);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => stream.wait_io(),
+            Err(e) => return err(e),
+        }
+    }
+}
 
-        // prepare the requests, we should make sure the request is fully read
-        loop {
-            let mut headers = [MaybeUninit::uninit(); request::MAX_HEADERS];
-            let req = match request::decode(&mut headers, &mut req_buf, stream)? {
-                Some(req) => req,
-                None => break,
-            };
-            reserve_buf(&mut rsp_buf);
-            let mut rsp = Response::new(&mut body_buf);
-            match service.call(req, &mut rsp) {
-                Ok(()) => response::encode(rsp, &mut rsp_buf),
-                Err(e) => {
-                    eprintln!("service err = {:?}", e);
-                    response::encode_error(e, &mut rsp_buf);
+#[cfg(not(unix))]
+fn send_file(stream: &TcpStream, file: File, len: u64) -> io::Result<()> {
+    copy_file(stream, &file, len)
+}
+
+// Copies `len` bytes of a file through a buffer
+fn copy_file(mut stream: &TcpStream, file: &File, len: u64) -> io::Result<()> {
+    if io::copy(&mut file.take(len), &mut stream)? < len {
+        return err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while sent"));
+    }
+    Ok(())
+}
+
+// What a connection keeps from one request to the next
+struct Session {
+    config: Arc<ServerConfig>,
+    req_buf: BytesMut,
+    rsp_buf: BytesMut,
+    body_buf: BytesMut,
+    headers: Vec<MaybeUninit<httparse::Header<'static>>>,
+    limits: KeepAlive,
+    timeouts: ReadTimeouts,
+    registration: Option<Registration>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Session {
+    // Sets up the connection on `stream`, `None` if the shutdown has
+    // started and it is to be closed
+    fn start(stream: &TcpStream, config: Arc<ServerConfig>) -> io::Result<Option<Self>> {
+        let registration = match &config.shutdown {
+            Some(shutdown) => match shutdown.register(stream)? {
+                Some(registration) => Some(registration),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+        let timeouts = ReadTimeouts::new(&config);
+        if timeouts.body.is_some() {
+            stream.set_read_timeout(timeouts.body)?;
+        }
+        if config.write_timeout.is_some() {
+            stream.set_write_timeout(config.write_timeout)?;
+        }
+        Ok(Some(Session {
+            req_buf: BytesMut::with_capacity(BUF_LEN),
+            rsp_buf: BytesMut::with_capacity(BUF_LEN),
+            body_buf: BytesMut::with_capacity(4096),
+            headers: vec![MaybeUninit::uninit(); config.max_headers],
+            limits: KeepAlive::new(&config),
+            timeouts,
+            registration,
+            #[cfg(feature = "tracing")]
+            span: crate::trace::connection_span(stream.peer_addr().ok()),
+            config,
+        }))
+    }
+
+    // Serves the next request in `req_buf`, adding the response to
+    // `rsp_buf`. `None` if the request isn't all there yet, otherwise
+    // whether the connection stays open; the last response is written
+    // before it closes
+    fn serve_next<T: HttpService>(
+        &mut self,
+        service: &mut T,
+        mut stream: &TcpStream,
+        conn: &mut Conn,
+    ) -> io::Result<Option<bool>> {
+        let Session {
+            config,
+            req_buf,
+            rsp_buf,
+            body_buf,
+            headers,
+            limits,
+            timeouts,
+            registration,
+            ..
+        } = self;
+        let log = config.log_exchanges;
+        let headers = request::header_slots(headers);
+        let req = match request::decode(headers, req_buf, stream, conn) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                let log = log.then_some(&req_buf[..]);
+                return reject(stream, rsp_buf, e, config.clock.as_deref(), log).map(|()| None);
+            }
+        };
+        reserve_buf(rsp_buf);
+        let (version, mut keep_alive) = (req.version(), limits.admit(req.keep_alive()));
+        let head_request = req.method() == "HEAD";
+        let head = log.then(|| req.raw_head().to_vec());
+        let clock = &config.clock;
+        let entry = config.access_log.as_ref().map(|access_log| {
+            let now = clock.as_ref().map_or_else(SystemTime::now, |clock| clock.now());
+            access_log.entry(&req, now)
+        });
+        let start = rsp_buf.len();
+        let timed = config.on_response.is_some() || config.access_log.is_some();
+        let started = timed.then(Instant::now);
+        let mut rsp = Response::new(body_buf, config.max_response_headers);
+        rsp.pretty_json(config.pretty_json);
+        rsp.text_charset(config.text_charset);
+        if let Some(clock) = clock {
+            rsp.clock(clock.clone());
+        }
+        // a `1xx` can't overtake the responses buffered before this one
+        if version != 0 && start == 0 {
+            rsp.interim(stream);
+        }
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::request_span(&req, &self.span);
+        let error_handler = &*config.error_handler;
+        let mut upgrade = None;
+        let result = service.call(req, &mut rsp);
+        // a body the handler didn't read would be parsed as the next request
+        conn.skip_unread(req_buf, stream);
+        let (status, deferred) = match result {
+            // a route timeout sent a 503 and closed the connection
+            Ok(()) if rsp.is_sent_early() => {
+                keep_alive = false;
+                (503, None)
+            }
+            Ok(()) => {
+                // connections close after their response once shutting down or with a
+                // request body left unread, and leave HTTP after switching protocols
+                upgrade = rsp.take_upgrade();
+                keep_alive &= upgrade.is_none()
+                    && !conn.body_left()
+                    && !registration.as_ref().is_some_and(Registration::stopping);
+                if upgrade.is_none() {
+                    connection_header(&mut rsp, version, keep_alive, limits);
+                }
+                let status = rsp.status_sent();
+                match response::encode(rsp, rsp_buf, head_request) {
+                    Ok(deferred) => (status, deferred),
+                    Err(e) => {
+                        // the connection closes after the error instead of switching
+                        upgrade = None;
+                        (response::encode_error(e, error_handler, clock.as_deref(), rsp_buf), None)
+                    }
                 }
             }
+            Err(e) if request::rejection(&e).is_some() => {
+                return reject(stream, rsp_buf, e, clock.as_deref(), head.as_deref()).map(|()| None);
+            }
+            Err(e) => {
+                error!("service err = {:?}", e);
+                keep_alive &= !conn.body_left();
+                (response::encode_error(e, error_handler, clock.as_deref(), rsp_buf), None)
+            }
+        };
+        let timing = started.map(|started| (started, started.elapsed()));
+        if let Some(head) = head {
+            log_exchange(stream, &head, &rsp_buf[start..]);
+        }
+        let mut bytes = (rsp_buf.len() - start) as u64;
+        if let Some(body) = deferred {
+            bytes += write_deferred(stream, rsp_buf, body)?;
+        }
+        let route = conn.take_route();
+        #[cfg(feature = "tracing")]
+        crate::trace::record(&span, status, route.as_deref());
+        if let Some((started, time_to_first_byte)) = timing {
+            let duration = started.elapsed();
+            if let Some(hook) = &config.on_response {
+                let stats = ResponseStats {
+                    status,
+                    bytes,
+                    time_to_first_byte,
+                    duration,
+                };
+                (hook.0)(&stats);
+            }
+            if let (Some(access_log), Some(entry)) = (&config.access_log, entry) {
+                access_log.write(entry, route, status, bytes, duration);
+            }
+        }
+        conn.reset_arena();
+        conn.restore_read_ahead(req_buf);
+        timeouts.served();
+        if !keep_alive {
+            stream.write_all(rsp_buf)?;
+            if let Some(upgrade) = upgrade {
+                conn.set_upgrade(upgrade, req_buf.split().freeze());
+            }
+        }
+        Ok(Some(keep_alive))
+    }
+}
+
+#[cfg(unix)]
+fn each_connection_loop<T: HttpService>(
+    stream: &mut TcpStream,
+    mut service: T,
+    conn: &mut Conn,
+) -> io::Result<()> {
+    let Some(mut session) = Session::start(stream, conn.config().clone())? else {
+        return Ok(());
+    };
+    let log = session.config.log_exchanges;
+    let write_timeout = session.config.write_timeout;
+
+    loop {
+        let read_blocked = nonblock_read(stream.inner_mut(), &mut session.req_buf)?;
+
+        // prepare the requests, we should make sure the request is fully read
+        while let Some(keep_alive) = session.serve_next(&mut service, stream, conn)? {
+            if !keep_alive {
+                return Ok(());
+            }
             // here need to use no_delay tcp option
             // nonblock_write(stream.inner_mut(), &mut rsp_buf)?;
         }
 
+        let Session {
+            config,
+            req_buf,
+            rsp_buf,
+            timeouts,
+            registration,
+            ..
+        } = &mut session;
         // write out the responses
-        nonblock_write(stream.inner_mut(), &mut rsp_buf)?;
+        nonblock_write(stream.inner_mut(), rsp_buf)?;
+        // a client not taking them gets the write timeout to do so
+        if write_timeout.is_some() && !rsp_buf.is_empty() {
+            stream.write_all(rsp_buf)?;
+            rsp_buf.clear();
+        }
+        timeouts.receiving(!req_buf.is_empty());
 
         if read_blocked {
-            stream.wait_io();
+            // waiting for the next request, which the keep-alive timeout
+            // limits and the shutdown ends by closing the connection
+            let waiting = req_buf.is_empty() && rsp_buf.is_empty();
+            let idle = registration.as_ref().filter(|_| waiting);
+            if idle.is_some_and(|registration| !registration.idle()) {
+                return Ok(());
+            }
+            match timeouts.limit(waiting) {
+                Some((timeout, head)) => {
+                    reserve_buf(req_buf);
+                    let read_buf: &mut [u8] = unsafe { std::mem::transmute(req_buf.chunk_mut()) };
+                    match read_within(stream, read_buf, Some(timeout), timeouts.body)? {
+                        Some(0) => return err(io::Error::new(io::ErrorKind::BrokenPipe, "closed")),
+                        Some(n) => unsafe { req_buf.advance_mut(n) },
+                        None if head => {
+                            let log = log.then_some(&req_buf[..]);
+                            return head_timeout(stream, rsp_buf, config.clock.as_deref(), log);
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                None => stream.wait_io(),
+            }
+            if idle.is_some_and(|registration| !registration.busy()) {
+                return Ok(());
+            }
         }
     }
 }
 
 #[cfg(not(unix))]
-fn each_connection_loop<T: HttpService>(stream: &mut TcpStream, mut service: T) -> io::Result<()> {
-    let mut req_buf = BytesMut::with_capacity(BUF_LEN);
-    let mut rsp_buf = BytesMut::with_capacity(BUF_LEN);
-    let mut body_buf = BytesMut::with_capacity(BUF_LEN);
+fn each_connection_loop<T: HttpService>(
+    stream: &mut TcpStream,
+    mut service: T,
+    conn: &mut Conn,
+) -> io::Result<()> {
+    let Some(mut session) = Session::start(stream, conn.config().clone())? else {
+        return Ok(());
+    };
+    let log = session.config.log_exchanges;
     loop {
+        let Session {
+            config,
+            req_buf,
+            rsp_buf,
+            timeouts,
+            registration,
+            ..
+        } = &mut session;
+        // waiting for the next request, which the keep-alive timeout
+        // limits and the shutdown ends by closing the connection
+        let waiting = req_buf.is_empty();
+        let idle = registration.as_ref().filter(|_| waiting);
+        if idle.is_some_and(|registration| !registration.idle()) {
+            return Ok(());
+        }
         // read the socket for requests
-        reserve_buf(&mut req_buf);
+        reserve_buf(req_buf);
         let read_buf: &mut [u8] = unsafe { std::mem::transmute(&mut *req_buf.chunk_mut()) };
-        let read_cnt = stream.read(read_buf)?;
+        let limit = timeouts.limit(waiting);
+        let timeout = limit.map(|(timeout, _)| timeout);
+        let read_cnt = match read_within(stream, read_buf, timeout, timeouts.body)? {
+            Some(n) => n,
+            None if limit.is_some_and(|(_, head)| head) => {
+                let log = log.then_some(&req_buf[..]);
+                return head_timeout(stream, rsp_buf, config.clock.as_deref(), log);
+            }
+            None => return Ok(()),
+        };
+        if idle.is_some_and(|registration| !registration.busy()) {
+            return Ok(());
+        }
         if read_cnt == 0 {
             //connection was closed
             return err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
@@ -149,26 +1006,16 @@ fn each_connection_loop<T: HttpService>(stream: &mut TcpStream, mut service: T)
         unsafe { req_buf.advance_mut(read_cnt) };
 
         // prepare the requests
-        if read_cnt > 0 {
-            loop {
-                let mut headers = [MaybeUninit::uninit(); request::MAX_HEADERS];
-                let req = match request::decode(&mut headers, &mut req_buf, stream)? {
-                    Some(req) => req,
-                    None => break,
-                };
-                let mut rsp = Response::new(&mut body_buf);
-                match service.call(req, &mut rsp) {
-                    Ok(()) => response::encode(rsp, &mut rsp_buf),
-                    Err(e) => {
-                        eprintln!("service err = {:?}", e);
-                        response::encode_error(e, &mut rsp_buf);
-                    }
-                }
+        while let Some(keep_alive) = session.serve_next(&mut service, stream, conn)? {
+            if !keep_alive {
+                return Ok(());
             }
         }
 
         // send the result back to client
-        stream.write_all(&rsp_buf)?;
+        stream.write_all(&session.rsp_buf)?;
+        session.rsp_buf.clear();
+        session.timeouts.receiving(!session.req_buf.is_empty());
     }
 }
 
@@ -176,22 +1023,60 @@ impl<T: HttpService + Clone + Send + Sync + 'static> HttpServer<T> {
     /// Spawns the http service, binding to the given address
     /// return a coroutine that you can cancel it when need to stop the service
     pub fn start<L: ToSocketAddrs>(self, addr: L) -> io::Result<coroutine::JoinHandle<()>> {
-        let listener = TcpListener::bind(addr)?;
+        self.start_with(addr, ServerConfig::default())
+    }
+
+    /// Like [`start`](Self::start), with the given server settings.
+    pub fn start_with<L: ToSocketAddrs>(
+        self,
+        addr: L,
+        config: ServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>> {
+        self.start_on([addr], config)
+    }
+
+    /// Like [`start_with`](Self::start_with), listening on every address of
+    /// `addrs`, such as `["0.0.0.0:8080", "[::]:8080"]`. Connections to all
+    /// of them share the service and the settings, `max_connections`
+    /// counting them together.
+    pub fn start_on<I>(self, addrs: I, config: ServerConfig) -> io::Result<coroutine::JoinHandle<()>>
+    where
+        I: IntoIterator,
+        I::Item: ToSocketAddrs,
+    {
+        self.start_from(bind_all(addrs, &config)?, config)
+    }
+
+    /// Like [`start_on`](Self::start_on), accepting on listeners bound
+    /// beforehand, such as those of [`systemd_listeners`](crate::systemd_listeners)
+    /// or of tests binding port `0` themselves. The socket settings of
+    /// `config` applying to listeners are left to whoever bound them.
+    pub fn start_from<I>(
+        self,
+        listeners: I,
+        config: ServerConfig,
+    ) -> io::Result<coroutine::JoinHandle<()>>
+    where
+        I: IntoIterator<Item = net::TcpListener>,
+    {
+        let listeners = adopt(listeners, &config)?;
+        let config = Arc::new(config);
+        let slots = config.max_connections.map(Slots::new);
         let service = self.0;
         go!(
             coroutine::Builder::new().name("TcpServer".to_owned()),
             move || {
-                for stream in listener.incoming() {
-                    let mut stream = t_c!(stream);
-                    // t_c!(stream.set_nodelay(true));
+                let incoming = accept_all(listeners, config.shutdown.clone());
+                accept_loop(incoming, &config, slots, |stream, slot| {
                     let service = service.clone();
-                    go!(
-                        move || if let Err(e) = each_connection_loop(&mut stream, service) {
-                            error!("service err = {:?}", e);
-                            stream.shutdown(std::net::Shutdown::Both).ok();
-                        }
-                    );
-                }
+                    let config = config.clone();
+                    go!(move || {
+                        let state = stream.peer_addr().map(ConnState::new).unwrap_or_default();
+                        serve_connection(stream, service, config, state);
+                        drop(slot);
+                    });
+                    Ok(())
+                })
             }
         )
     }
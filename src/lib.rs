@@ -1,13 +1,62 @@
 #[macro_use]
 extern crate log;
 
-mod date;
+mod access_log;
+pub mod auth;
+mod cache_control;
+mod chunked;
+#[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+mod compress;
+mod config;
+mod cookie;
+pub mod date;
+mod error_handler;
+#[cfg(unix)]
+mod handover;
+#[cfg(feature = "decompression")]
+mod decompress;
+pub mod extract;
 mod http_server;
+mod mime;
+mod query;
+pub mod range;
 mod request;
 mod response;
 pub mod router;
+mod security_headers;
+mod server_builder;
+mod shutdown;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(unix)]
+mod socket_activation;
+mod upgrade;
+mod url;
 
+pub use access_log::{AccessLog, InvalidLogFormat, LogFormat};
+#[cfg(feature = "arena")]
+pub use bumpalo;
+pub use cache_control::CacheControl;
+pub use config::{Cidr, InvalidCidr, Overload, ResponseStats, ServerConfig};
+pub use cookie::{Cookie, SameSite};
+pub use error_handler::{DetailedErrors, ErrorHandler, ErrorResponse, GenericErrors};
+#[cfg(unix)]
+pub use handover::{Predecessor, Successor};
+pub use http;
 pub use http_server::{HttpServer, HttpService, HttpServiceFactory};
-pub use request::{BodyReader, Request};
-pub use response::Response;
-pub use router::Router;
+pub use mime::MediaType;
+pub use query::Query;
+pub use request::{BodyChunks, BodyReader, ConnState, Request};
+pub use response::{ChunkWriter, Cors, Response, StreamWriter};
+pub use router::{HandlerError, IntoResponse, Params, Router};
+pub use security_headers::SecurityHeaders;
+pub use server_builder::HttpServerBuilder;
+pub use shutdown::ShutdownHandle;
+#[cfg(feature = "tracing")]
+pub use trace::TraceContext;
+#[cfg(feature = "tracing")]
+pub use tracing;
+#[cfg(unix)]
+pub use socket_activation::systemd_listeners;
+pub use upgrade::{Upgrade, Upgraded};
+pub use url::Url;
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::path::Path;
+
+/// A parsed media type such as `text/html; charset=utf-8`, see
+/// [`Request::content_type`](crate::Request::content_type).
+///
+/// The type, subtype and parameter names are compared ignoring case and
+/// kept lowercase; parameter values are kept as sent, without the quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    // `type/subtype`, lowercase
+    essence: String,
+    slash: usize,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Parses a `Content-Type` value, `None` unless it starts with a valid
+    /// `type/subtype`. Malformed parameters are skipped.
+    pub fn parse(value: &str) -> Option<MediaType> {
+        let (essence, mut rest) = value.split_once(';').unwrap_or((value, ""));
+        let (ty, subtype) = essence.trim().split_once('/')?;
+        if !is_token(ty) || !is_token(subtype) {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ';']);
+            if rest.is_empty() {
+                break;
+            }
+            let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+            let name = rest[..name_end].trim();
+            rest = &rest[name_end..];
+            // a parameter without a value
+            let Some(after) = rest.strip_prefix('=') else {
+                continue;
+            };
+            let after = after.trim_start();
+            let value;
+            (value, rest) = match after.strip_prefix('"') {
+                Some(quoted) => quoted_string(quoted),
+                None => {
+                    let end = after.find(';').unwrap_or(after.len());
+                    (after[..end].trim_end().to_string(), &after[end..])
+                }
+            };
+            if is_token(name) {
+                params.push((name.to_ascii_lowercase(), value));
+            }
+        }
+
+        Some(MediaType {
+            essence: format!("{ty}/{subtype}").to_ascii_lowercase(),
+            slash: ty.len(),
+            params,
+        })
+    }
+
+    /// The type and subtype without parameters, such as `application/json`.
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// The part before the `/`, such as `text` in `text/html`.
+    pub fn main_type(&self) -> &str {
+        &self.essence[..self.slash]
+    }
+
+    /// The part after the `/`, such as `html` in `text/html`.
+    pub fn subtype(&self) -> &str {
+        &self.essence[self.slash + 1..]
+    }
+
+    /// The structured syntax suffix, such as `json` in
+    /// `application/problem+json`.
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype().rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    /// The value of the parameter called `name`, which is matched ignoring
+    /// case.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The parameters as name/value pairs in the order they were sent.
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// The `charset` parameter, such as `utf-8`.
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// The `boundary` parameter separating the parts of a `multipart` body.
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+
+    /// Whether this is `application/json` or another `+json` type.
+    pub fn is_json(&self) -> bool {
+        self.essence == "application/json" || (self.main_type() == "application" && self.suffix() == Some("json"))
+    }
+
+    /// Whether this matches `pattern`, a `type/subtype` that may use `*` for
+    /// either part, such as `image/*`.
+    pub fn matches(&self, pattern: &str) -> bool {
+        let Some((ty, subtype)) = pattern.split_once('/') else {
+            return false;
+        };
+        let (ty, subtype) = (ty.trim(), subtype.split(';').next().unwrap_or_default().trim());
+        (ty == "*" || ty.eq_ignore_ascii_case(self.main_type()))
+            && (subtype == "*" || subtype.eq_ignore_ascii_case(self.subtype()))
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.essence)?;
+        for (name, value) in &self.params {
+            if is_token(value) {
+                write!(f, "; {name}={value}")?;
+            } else {
+                write!(f, "; {name}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Guesses the content type from the file extension.
+pub(crate) fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// The value of a quoted string whose opening quote was already taken, and
+// what follows the closing one
+fn quoted_string(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &input[i + 1..]),
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            c => value.push(c),
+        }
+    }
+    // unterminated, take what is there
+    (value, "")
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
@@ -0,0 +1,161 @@
+//! `Range` requests and `206 Partial Content` responses.
+//!
+//! [`respond`] covers the usual case of a body held in memory:
+//!
+//! ```ignore
+//! fn video(req: &Request, _: Params) -> Response<Vec<u8>> {
+//!     let data = std::fs::read("movie.mp4").unwrap();
+//!     range::respond(req, "video/mp4", data)
+//! }
+//! ```
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::ops::Range;
+
+use http::{HeaderValue, Method, Response, StatusCode, header};
+
+use crate::Request;
+
+// more ranges than this are ignored rather than served
+const MAX_RANGES: usize = 16;
+
+/// Why the ranges of a `Range` header can't be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    /// Not a valid `bytes` range set; the header should be ignored and the
+    /// full body sent.
+    Invalid,
+    /// No range overlaps the body, answered with
+    /// `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeError::Invalid => f.write_str("invalid range header"),
+            RangeError::Unsatisfiable => f.write_str("range not satisfiable"),
+        }
+    }
+}
+
+impl std::error::Error for RangeError {}
+
+/// Parses a `Range` header value such as `bytes=0-499, -200` against a
+/// body of `len` bytes, returning the satisfiable ranges in the order they
+/// were asked for.
+pub fn parse(header: &str, len: u64) -> Result<Vec<Range<u64>>, RangeError> {
+    let (unit, specs) = header.split_once('=').ok_or(RangeError::Invalid)?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Err(RangeError::Invalid);
+    }
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        count += 1;
+        if count > MAX_RANGES {
+            return Err(RangeError::Invalid);
+        }
+        let (first, last) = spec.split_once('-').ok_or(RangeError::Invalid)?;
+        let number = |s: &str| s.trim().parse::<u64>().map_err(|_| RangeError::Invalid);
+        let range = match (first.trim(), last.trim()) {
+            // the last `n` bytes
+            ("", suffix) => len.saturating_sub(number(suffix)?)..len,
+            (first, "") => number(first)?..len,
+            (first, last) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if last < first {
+                    return Err(RangeError::Invalid);
+                }
+                first..last.saturating_add(1).min(len)
+            }
+        };
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+    match (count, ranges.is_empty()) {
+        (0, _) => Err(RangeError::Invalid),
+        (_, true) => Err(RangeError::Unsatisfiable),
+        _ => Ok(ranges),
+    }
+}
+
+/// Answers `req` with `body`, or with the parts of it asked for in a
+/// `Range` header.
+///
+/// A single range is sent as `206 Partial Content` with `Content-Range`,
+/// several as a `multipart/byteranges` body, and ranges outside of `body`
+/// answer `416 Range Not Satisfiable`. Without a usable `Range` header
+/// the whole body is sent with `200 OK`, see [`Request::byte_ranges`].
+/// Every response announces `Accept-Ranges: bytes`. A `content_type` that
+/// isn't a valid header value, e.g. one with a line break, is sent as
+/// `application/octet-stream`.
+pub fn respond<ResponseBody: From<Vec<u8>>>(
+    req: &Request,
+    content_type: &str,
+    body: Vec<u8>,
+) -> Response<ResponseBody> {
+    let content_type = match HeaderValue::from_str(content_type) {
+        Ok(_) => content_type,
+        Err(_) => "application/octet-stream",
+    };
+    let len = body.len() as u64;
+    let builder = Response::builder().header(header::ACCEPT_RANGES, "bytes");
+    let response = match req.byte_ranges(len) {
+        None => builder
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body.into()),
+        Some(Err(_)) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(Vec::new().into()),
+        Some(Ok(ranges)) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_RANGE, content_range(&range, len))
+                .body(body[range.start as usize..range.end as usize].to_vec().into())
+        }
+        Some(Ok(ranges)) => {
+            let boundary = boundary(len);
+            let mut multipart = Vec::new();
+            for range in ranges {
+                multipart.extend_from_slice(part_head(&boundary, content_type, &range, len).as_bytes());
+                multipart.extend_from_slice(&body[range.start as usize..range.end as usize]);
+                multipart.extend_from_slice(b"\r\n");
+            }
+            multipart.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, format!("multipart/byteranges; boundary={boundary}"))
+                .body(multipart.into())
+        }
+    };
+    response.expect("range response headers are valid")
+}
+
+pub(crate) fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+// Separates the parts of a `multipart/byteranges` body
+pub(crate) fn boundary(len: u64) -> String {
+    format!("{:016x}", RandomState::new().hash_one(len))
+}
+
+// The delimiter and headers ahead of one part of a `multipart/byteranges`
+// body, whose bytes follow with a line break
+pub(crate) fn part_head(boundary: &str, content_type: &str, range: &Range<u64>, len: u64) -> String {
+    format!(
+        "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+        content_range(range, len)
+    )
+}
+
+// Range only applies to GET, and a HEAD mirrors it
+pub(crate) fn applies_to(method: &str) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
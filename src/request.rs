@@ -1,13 +1,44 @@
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::fmt;
 use std::io::{self, BufRead, Read};
 use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub(crate) const MAX_HEADERS: usize = 16;
-
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{Extensions, HeaderMap, StatusCode};
 use may::net::TcpStream;
+use serde::de::DeserializeOwned;
 
+use crate::auth::{self, BasicAuth};
+use crate::chunked::{Chunked, ChunkedReader, Source};
+use crate::config::ServerConfig;
+use crate::extract;
 use crate::http_server::err;
+use crate::mime::MediaType;
+use crate::query::Query;
+use crate::range::{self, RangeError};
+use crate::router::{HandlerError, Params};
+use crate::upgrade::Upgrade;
+use crate::url::Url;
+
+// How much more of a body over its size limit, or of one the handler left
+// unread, is read and dropped, so the connection can serve the next
+// request; it closes instead if more is left
+const MAX_DRAIN: u64 = 64 * 1024;
+
+// Largest body `Request::read_body` reads, and `ApiService` unless
+// configured otherwise
+pub(crate) const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+// The body of a request no handler has started to read yet
+#[derive(Clone, Copy)]
+enum Unread {
+    Length(usize),
+    Chunked,
+}
 
 pub struct BodyReader<'buf, 'stream> {
     // remaining bytes for body
@@ -17,22 +48,162 @@ pub struct BodyReader<'buf, 'stream> {
     // total read count
     total_read: usize,
     // used to read extra body bytes
-    stream: &'stream mut TcpStream,
+    stream: &'stream TcpStream,
+    // set for a `Transfer-Encoding: chunked` body, which ignores `body_limit`
+    chunked: Option<Chunked>,
+    // set when the body was buffered before, which is then read instead
+    replay: Option<Replay>,
+}
+
+struct Replay {
+    body: io::Cursor<Vec<u8>>,
+    trailers: Option<HeaderMap>,
+}
+
+fn read_more_data(req_buf: &mut BytesMut, mut stream: &TcpStream) -> io::Result<usize> {
+    crate::http_server::reserve_buf(req_buf);
+    let read_buf: &mut [u8] = unsafe { std::mem::transmute(req_buf.chunk_mut()) };
+    let n = stream.read(read_buf).map_err(body_error)?;
+    unsafe { req_buf.advance_mut(n) };
+    Ok(n)
+}
+
+// Reads ahead into `req_buf`, whatever follows the body stays there for
+// the next request
+struct BufferedSource<'a> {
+    req_buf: &'a mut BytesMut,
+    stream: &'a TcpStream,
+}
+
+impl Source for BufferedSource<'_> {
+    fn fill(&mut self, _max: usize) -> io::Result<&[u8]> {
+        if self.req_buf.is_empty() {
+            read_more_data(self.req_buf, self.stream)?;
+        }
+        Ok(self.req_buf.chunk())
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.req_buf.advance(n);
+    }
+}
+
+// Reads into the room left in `req_buf`, as the headers still borrow it and
+// it can't grow; whatever arrives after the body stays there for the next
+// request. Once there is no room, reads into `pending` instead, and what is
+// left of it after the body is kept with `Conn::read_ahead`
+struct ExactSource<'a> {
+    req_buf: &'a mut BytesMut,
+    stream: &'a TcpStream,
+    pending: Vec<u8>,
+    pos: usize,
 }
 
-impl BodyReader<'_, '_> {
+impl Source for ExactSource<'_> {
+    fn fill(&mut self, _max: usize) -> io::Result<&[u8]> {
+        if self.pos < self.pending.len() {
+            return Ok(&self.pending[self.pos..]);
+        }
+        if self.req_buf.is_empty() {
+            if self.req_buf.capacity() == 0 {
+                self.pending.resize(4096, 0);
+                let n = self.stream.read(&mut self.pending).map_err(body_error)?;
+                self.pending.truncate(n);
+                self.pos = 0;
+                return Ok(&self.pending);
+            }
+            let read_buf: &mut [u8] = unsafe { std::mem::transmute(self.req_buf.chunk_mut()) };
+            let n = self.stream.read(read_buf).map_err(body_error)?;
+            unsafe { self.req_buf.advance_mut(n) };
+        }
+        Ok(self.req_buf.chunk())
+    }
+
+    fn consume(&mut self, n: usize) {
+        if self.pos < self.pending.len() {
+            self.pos += n;
+        } else {
+            self.req_buf.advance(n);
+        }
+    }
+}
+
+impl<'buf, 'stream> BodyReader<'buf, 'stream> {
+    /// The trailer fields of a chunked body, `None` until the body has been
+    /// read to the end and for bodies that aren't chunked. A body buffered
+    /// by [`Request::read_body`] has them from the start.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        if let Some(replay) = &self.replay {
+            return replay.trailers.as_ref();
+        }
+        self.chunked.as_ref()?.trailers()
+    }
+
+    /// Turns the reader into an iterator over the body as it arrives, in
+    /// chunks of whatever was received, so an upload can be hashed or
+    /// passed on without holding all of it.
+    ///
+    /// Waiting for data parks the coroutine, not the thread, so other
+    /// connections keep being served meanwhile.
+    ///
+    /// ```ignore
+    /// let mut hasher = Sha256::new();
+    /// for chunk in req.body().chunks() {
+    ///     hasher.update(&chunk?);
+    /// }
+    /// ```
+    pub fn chunks(self) -> BodyChunks<'buf, 'stream> {
+        BodyChunks { reader: self }
+    }
+
     fn read_more_data(&mut self) -> io::Result<usize> {
-        crate::http_server::reserve_buf(self.req_buf);
-        let read_buf: &mut [u8] = unsafe { std::mem::transmute(self.req_buf.chunk_mut()) };
-        let n = self.stream.read(read_buf)?;
-        unsafe { self.req_buf.advance_mut(n) };
-        Ok(n)
+        read_more_data(self.req_buf, self.stream)
+    }
+
+    fn source(&mut self) -> BufferedSource<'_> {
+        BufferedSource {
+            req_buf: self.req_buf,
+            stream: self.stream,
+        }
+    }
+
+    fn fill_from_connection(&mut self) -> io::Result<&[u8]> {
+        if let Some(mut chunked) = self.chunked.take() {
+            let left = chunked.data_len(&mut self.source());
+            self.chunked = Some(chunked);
+            let left = usize::try_from(left?).unwrap_or(usize::MAX);
+            if left == 0 {
+                return Ok(&[]);
+            }
+            if self.req_buf.is_empty() && self.read_more_data()? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let n = self.req_buf.len().min(left);
+            return Ok(&self.req_buf.chunk()[0..n]);
+        }
+        let remain = self.body_limit - self.total_read;
+        if remain == 0 {
+            return Ok(&[]);
+        }
+        if self.req_buf.is_empty() {
+            self.read_more_data()?;
+        }
+        let n = self.req_buf.len().min(remain);
+        Ok(&self.req_buf.chunk()[0..n])
     }
 }
 
 impl Read for BodyReader<'_, '_> {
     // the user should control the body reading, don't exceeds the body!
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(replay) = &mut self.replay {
+            return replay.body.read(buf);
+        }
+        if let Some(mut chunked) = self.chunked.take() {
+            let n = chunked.read(&mut self.source(), buf);
+            self.chunked = Some(chunked);
+            return n;
+        }
         if self.total_read >= self.body_limit {
             return Ok(0);
         }
@@ -54,18 +225,23 @@ impl Read for BodyReader<'_, '_> {
 
 impl BufRead for BodyReader<'_, '_> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        let remain = self.body_limit - self.total_read;
-        if remain == 0 {
-            return Ok(&[]);
+        match self.replay {
+            Some(ref mut replay) => replay.body.fill_buf(),
+            None => self.fill_from_connection(),
         }
-        if self.req_buf.is_empty() {
-            self.read_more_data()?;
-        }
-        let n = self.req_buf.len().min(remain);
-        Ok(&self.req_buf.chunk()[0..n])
     }
 
     fn consume(&mut self, amt: usize) {
+        if let Some(replay) = &mut self.replay {
+            replay.body.consume(amt);
+            return;
+        }
+        if let Some(chunked) = &mut self.chunked {
+            assert!(amt <= self.req_buf.len());
+            chunked.consumed(amt);
+            self.req_buf.advance(amt);
+            return;
+        }
         assert!(amt <= self.body_limit - self.total_read);
         assert!(amt <= self.req_buf.len());
         self.total_read += amt;
@@ -86,14 +262,213 @@ impl Drop for BodyReader<'_, '_> {
     }
 }
 
+/// The body in chunks as they are received, see [`BodyReader::chunks`].
+pub struct BodyChunks<'buf, 'stream> {
+    reader: BodyReader<'buf, 'stream>,
+}
+
+impl BodyChunks<'_, '_> {
+    /// See [`BodyReader::trailers`].
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.reader.trailers()
+    }
+}
+
+impl Iterator for BodyChunks<'_, '_> {
+    type Item = io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<io::Result<Bytes>> {
+        let chunk = match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(buf) => Bytes::copy_from_slice(buf),
+            Err(e) => return Some(Err(e)),
+        };
+        self.reader.consume(chunk.len());
+        Some(Ok(chunk))
+    }
+}
+
 // we should hold the mut ref of req_buf
 // before into body, this req_buf is only for holding headers
 // after into body, this req_buf is mutable to read extra body bytes
 // and the headers buf can be reused
 pub struct Request<'buf, 'header, 'stream> {
     req: httparse::Request<'header, 'buf>,
+    // the request line and headers as received
+    head: &'buf [u8],
     req_buf: &'buf mut BytesMut,
-    stream: &'stream mut TcpStream,
+    stream: &'stream TcpStream,
+    // body read ahead by `read_body`
+    body: Option<Vec<u8>>,
+    // sent after a chunked body, set once it has been read
+    trailers: Option<HeaderMap>,
+    // the buffered body has had its `Content-Encoding` undone
+    #[cfg(feature = "decompression")]
+    decompressed: bool,
+    conn: &'stream Conn,
+    extensions: RefCell<Extensions>,
+    // set by the router once a route matched
+    params: RefCell<Option<Params>>,
+    // when the head was read
+    received: Instant,
+    // the end of the timeout of the route handling the request
+    route_deadline: Cell<Option<Instant>>,
+}
+
+/// The state of a connection the requests on it share, set up by
+/// [`HttpServiceFactory::on_connect`](crate::HttpServiceFactory::on_connect)
+/// and seen as [`Request::conn_state`]. Its extensions hold what a
+/// server keeps per connection, such as the user it authenticated.
+#[derive(Debug, Default)]
+pub struct ConnState {
+    peer: Option<SocketAddr>,
+    extensions: Extensions,
+}
+
+impl ConnState {
+    pub fn new(peer: SocketAddr) -> Self {
+        ConnState {
+            peer: Some(peer),
+            extensions: Extensions::new(),
+        }
+    }
+
+    /// The address of the client, `None` if it couldn't be read.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+}
+
+/// What requests know about their connection, set up once when it is
+/// accepted.
+pub(crate) struct Conn {
+    remote: Option<SocketAddr>,
+    local: Option<SocketAddr>,
+    config: Arc<ServerConfig>,
+    state: RefCell<ConnState>,
+    // the pattern of the route the last request matched, for the access log
+    // and tracing
+    route: RefCell<Option<Arc<str>>>,
+    // the protocol the connection switches to, with the bytes read past
+    // the request that asked for it
+    upgrade: RefCell<Option<(Upgrade, Bytes)>>,
+    // bytes read past a request body once `req_buf` had no room left for
+    // them, which go in front of the next request
+    read_ahead: RefCell<Vec<u8>>,
+    // the body of the current request, until it is read
+    unread: Cell<Option<Unread>>,
+    // set once a request body couldn't be skipped, which closes the connection
+    body_left: Cell<bool>,
+    // lent to one request at a time, reset in between
+    #[cfg(feature = "arena")]
+    arena: bumpalo::Bump,
+}
+
+impl Conn {
+    pub(crate) fn new(stream: &TcpStream, config: Arc<ServerConfig>, state: ConnState) -> Self {
+        Conn {
+            remote: stream.peer_addr().ok(),
+            local: stream.local_addr().ok(),
+            config,
+            state: RefCell::new(state),
+            route: RefCell::new(None),
+            upgrade: RefCell::new(None),
+            read_ahead: RefCell::new(Vec::new()),
+            unread: Cell::new(None),
+            body_left: Cell::new(false),
+            #[cfg(feature = "arena")]
+            arena: bumpalo::Bump::new(),
+        }
+    }
+
+    pub(crate) fn config(&self) -> &Arc<ServerConfig> {
+        &self.config
+    }
+
+    /// The state of the connection, once it is closed.
+    pub(crate) fn into_state(self) -> ConnState {
+        self.state.into_inner()
+    }
+
+    /// Frees what the last request allocated from its arena, keeping the
+    /// memory for the next one.
+    pub(crate) fn reset_arena(&mut self) {
+        #[cfg(feature = "arena")]
+        self.arena.reset();
+    }
+
+    /// The pattern of the route the last request matched, if it was noted
+    /// for the access log or tracing.
+    pub(crate) fn take_route(&mut self) -> Option<Arc<str>> {
+        self.route.get_mut().take()
+    }
+
+    /// Switches the connection to `upgrade` once the HTTP loop returns.
+    pub(crate) fn set_upgrade(&mut self, upgrade: Upgrade, buffered: Bytes) {
+        *self.upgrade.get_mut() = Some((upgrade, buffered));
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<(Upgrade, Bytes)> {
+        self.upgrade.get_mut().take()
+    }
+
+    /// Whether a request body was left in the connection, too long to skip
+    /// or cut short, so the next request can't be told apart from it and
+    /// the connection has to close.
+    pub(crate) fn body_left(&self) -> bool {
+        self.body_left.get()
+    }
+
+    // Hands the body of the current request to whoever reads it
+    fn take_unread(&self) -> Option<Unread> {
+        self.unread.take()
+    }
+
+    /// Skips the body of the last request if its handler didn't read it,
+    /// which would otherwise be parsed as the next request. Up to 64 KiB
+    /// are skipped; a longer body, or one that doesn't arrive, is left and
+    /// closes the connection, see [`body_left`](Self::body_left).
+    pub(crate) fn skip_unread(&self, req_buf: &mut BytesMut, stream: &TcpStream) {
+        let skipped = match self.take_unread() {
+            None => return,
+            Some(Unread::Length(len)) => {
+                let buffered = len.min(req_buf.len());
+                req_buf.advance(buffered);
+                let rest = (len - buffered) as u64;
+                rest <= MAX_DRAIN && io::copy(&mut stream.take(rest), &mut io::sink()).is_ok_and(|n| n == rest)
+            }
+            Some(Unread::Chunked) => {
+                let mut reader = ChunkedReader::new(BufferedSource { req_buf, stream });
+                let skipped = io::copy(&mut (&mut reader).take(MAX_DRAIN + 1), &mut io::sink());
+                skipped.is_ok_and(|n| n <= MAX_DRAIN) && reader.into_parts().0.is_some()
+            }
+        };
+        if !skipped {
+            self.body_left.set(true);
+        }
+    }
+
+    fn read_ahead(&self, bytes: &[u8]) {
+        self.read_ahead.borrow_mut().extend_from_slice(bytes);
+    }
+
+    /// Moves the bytes the last request read past its body into `req_buf`,
+    /// which had no room for them, and so holds nothing read after them.
+    pub(crate) fn restore_read_ahead(&mut self, req_buf: &mut BytesMut) {
+        let read_ahead = self.read_ahead.get_mut();
+        if !read_ahead.is_empty() {
+            req_buf.extend_from_slice(read_ahead);
+            read_ahead.clear();
+        }
+    }
 }
 
 impl<'buf, 'stream> Request<'buf, '_, 'stream> {
@@ -101,28 +476,612 @@ impl<'buf, 'stream> Request<'buf, '_, 'stream> {
         self.req.method.unwrap()
     }
 
+    /// The request target as sent, including the query string; see
+    /// [`url`](Self::url) for its parts.
     pub fn path(&self) -> &str {
         self.req.path.unwrap()
     }
 
+    /// The request target split into path, query and fragment, with dot
+    /// segments resolved:
+    ///
+    /// ```ignore
+    /// let url = req.url();
+    /// let section = url.segments().next().unwrap_or_default();
+    /// let page: u32 = url.query_pairs().get("page").and_then(|p| p.parse().ok()).unwrap_or(1);
+    /// ```
+    pub fn url(&self) -> Url<'_> {
+        Url::parse(self.path())
+    }
+
+    /// The raw query string following `?` in the request target, if any.
+    pub fn query_string(&self) -> Option<&str> {
+        self.path().split_once('?').map(|(_, query)| query)
+    }
+
+    /// Deserializes the percent-decoded query string into `T`, with the
+    /// values of a repeated key such as `?tag=a&tag=b` going into a `Vec`
+    /// field. `req.query::<Query>()` gives the pairs untyped, with the values
+    /// of a repeated key next to each other.
+    ///
+    /// A missing query string reads as an empty one, and values that don't
+    /// fit `T` fail with `400 Bad Request`.
+    ///
+    /// ```ignore
+    /// #[derive(Deserialize)]
+    /// struct Search {
+    ///     #[serde(default)]
+    ///     page: u32,
+    ///     #[serde(default)]
+    ///     tag: Vec<String>,
+    /// }
+    ///
+    /// let search: Search = req.query()?;
+    /// ```
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, HandlerError> {
+        let query = self.query_string().map(Query::parse).unwrap_or_default();
+        extract::from_query(&query)
+    }
+
+    /// The minor HTTP version, `1` for HTTP/1.1 and `0` for HTTP/1.0.
     pub fn version(&self) -> u8 {
         self.req.version.unwrap()
     }
 
+    /// Whether the connection stays open after this request: for HTTP/1.1
+    /// unless the client sends `Connection: close`, for HTTP/1.0 only if it
+    /// sends `Connection: keep-alive`. The server closes the connection
+    /// once the response is written otherwise.
+    pub fn keep_alive(&self) -> bool {
+        let mut options = header_values(self.headers(), "connection")
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
+        if self.version() == 0 {
+            options.any(|option| option.eq_ignore_ascii_case("keep-alive"))
+        } else {
+            !options.any(|option| option.eq_ignore_ascii_case("close"))
+        }
+    }
+
+    /// Whether the client asks to switch the connection to `protocol`, such
+    /// as `h2c`, with `Connection: Upgrade` and `protocol` among those of
+    /// its `Upgrade` header, compared ignoring case. Always `false` for
+    /// HTTP/1.0, which has no upgrades. See [`Upgrade`](crate::Upgrade).
+    pub fn upgrade_requested(&self, protocol: &str) -> bool {
+        let tokens = |name| {
+            header_values(self.headers(), name)
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+        };
+        self.version() == 1
+            && tokens("connection").any(|option| option.eq_ignore_ascii_case("upgrade"))
+            && tokens("upgrade").any(|offer| offer.eq_ignore_ascii_case(protocol))
+    }
+
+    /// The request headers in the order and spelling they were sent;
+    /// compare names with `eq_ignore_ascii_case`, as the lookups here do.
     pub fn headers(&self) -> &[httparse::Header<'_>] {
         self.req.headers
     }
 
-    pub fn body(self) -> BodyReader<'buf, 'stream> {
+    /// The request line and headers exactly as received, up to and
+    /// including the blank line ending them, for troubleshooting clients
+    /// that send something unexpected.
+    pub fn raw_head(&self) -> &[u8] {
+        self.head
+    }
+
+    /// The cookies sent in `Cookie` headers, as name/value pairs in the
+    /// order they appear.
+    pub fn cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.req
+            .headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("cookie"))
+            .filter_map(|h| std::str::from_utf8(h.value).ok())
+            .flat_map(crate::cookie::parse)
+    }
+
+    /// The value of the first cookie called `name`.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Typed values attached to the request, e.g. by middleware for the
+    /// handlers behind it.
+    ///
+    /// Panics while [`extensions_mut`](Self::extensions_mut) is borrowed.
+    pub fn extensions(&self) -> Ref<'_, Extensions> {
+        self.extensions.borrow()
+    }
+
+    /// Gives mutable access to the [`extensions`](Self::extensions).
+    ///
+    /// Takes `&self` because middleware and handlers only get a shared
+    /// reference to the request:
+    ///
+    /// ```ignore
+    /// let mut api = router.scope("/api");
+    /// api.middleware(|req, params, rsp, next| {
+    ///     let user = authenticate(req)?;
+    ///     req.extensions_mut().insert(user);
+    ///     next.run(req, params, rsp)
+    /// });
+    ///
+    /// fn profile(req: &Request, _: Params) -> Result<Value, HandlerError> {
+    ///     let user = req.extensions().get::<User>().cloned().ok_or("not signed in")?;
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// Panics while the extensions are borrowed elsewhere, so don't hold
+    /// the guard across `next.run`.
+    pub fn extensions_mut(&self) -> RefMut<'_, Extensions> {
+        self.extensions.borrow_mut()
+    }
+
+    /// The parameters of the route the [router](crate::Router) matched, so
+    /// code that only gets the request, such as an error handler or a rate
+    /// limit key, can see them too. `None` before routing.
+    ///
+    /// Panics if the router dispatches the request again while the guard
+    /// is held.
+    pub fn params(&self) -> Option<Ref<'_, Params>> {
+        Ref::filter_map(self.params.borrow(), Option::as_ref).ok()
+    }
+
+    /// The value of the matched route parameter called `name`, see
+    /// [`Params::get`].
+    ///
+    /// ```ignore
+    /// let per_tenant = RateLimit::new(100, Duration::from_secs(1))
+    ///     .by_key(|req| req.param("tenant").map(|tenant| tenant.to_string()));
+    /// router.scope("/tenants/{tenant}").rate_limiter(per_tenant);
+    /// ```
+    pub fn param(&self, name: &str) -> Option<Ref<'_, str>> {
+        Ref::filter_map(self.params.borrow(), |params| params.as_ref()?.get(name)).ok()
+    }
+
+    pub(crate) fn set_params(&self, params: &Params) {
+        if self.conn.config.access_log.is_some() || cfg!(feature = "tracing") {
+            *self.conn.route.borrow_mut() = params.pattern.clone();
+        }
+        *self.params.borrow_mut() = Some(params.clone());
+    }
+
+    /// The credentials of an `Authorization: Basic` header, `None` if there
+    /// are none or they can't be decoded.
+    pub fn basic_auth(&self) -> Option<BasicAuth> {
+        BasicAuth::decode(auth::token(self.authorization()?, "Basic")?)
+    }
+
+    /// The token of an `Authorization: Bearer` header. Compare it with
+    /// [`auth::constant_time_eq`] when checking it against a known secret.
+    pub fn bearer_token(&self) -> Option<&str> {
+        auth::token(self.authorization()?, "Bearer")
+    }
+
+    fn authorization(&self) -> Option<&str> {
+        self.req
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("authorization"))
+            .and_then(|h| std::str::from_utf8(h.value).ok())
+    }
+
+    /// The language in `available` the client prefers according to its
+    /// `Accept-Language` header, such as `req.preferred_language(&["en", "de", "th"])`.
+    ///
+    /// Languages are ranked by quality, then by their order in the header.
+    /// A range like `en` covers `en-GB`, and a range like `en-GB` falls back
+    /// to `en` if nothing closer is available. `None` if the header is
+    /// missing or allows none of `available`, leaving the default to the
+    /// caller.
+    pub fn preferred_language<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        let ranges: Vec<(&str, f32)> = header_values(self.headers(), "accept-language")
+            .flat_map(|value| value.split(','))
+            .filter_map(parse_weighted)
+            .collect();
+        let mut best: Option<(&'a str, f32, usize)> = None;
+        for &tag in available {
+            let Some((q, index)) = language_quality(&ranges, tag) else {
+                continue;
+            };
+            let better = best.is_none_or(|(_, best_q, best_index)| {
+                q > best_q || q == best_q && index < best_index
+            });
+            if q > 0.0 && better {
+                best = Some((tag, q, index));
+            }
+        }
+        best.map(|(tag, _, _)| tag)
+    }
+
+    /// The byte ranges asked for in the `Range` header, resolved against a
+    /// body of `len` bytes, see [`range::parse`].
+    ///
+    /// `None` if there is no usable header: for methods other than GET and
+    /// HEAD, for a malformed header, and when `If-Range` is sent, as its
+    /// validator can't be checked here and the full body is the safe answer.
+    pub fn byte_ranges(&self, len: u64) -> Option<Result<Vec<Range<u64>>, RangeError>> {
+        if !range::applies_to(self.method()) {
+            return None;
+        }
+        let mut value = None;
+        for header in self.req.headers.iter() {
+            if header.name.eq_ignore_ascii_case("if-range") {
+                return None;
+            }
+            if header.name.eq_ignore_ascii_case("range") {
+                value = std::str::from_utf8(header.value).ok();
+            }
+        }
+        match range::parse(value?, len) {
+            Err(RangeError::Invalid) => None,
+            result => Some(result),
+        }
+    }
+
+    /// A bump allocator for short-lived data of this request, such as
+    /// strings built while parsing parameters. Everything allocated from it
+    /// is freed at once after the response has been encoded, and the
+    /// memory is reused for the next request on the connection.
+    ///
+    /// ```ignore
+    /// use karics::bumpalo::collections::String;
+    ///
+    /// let mut key = String::new_in(req.arena());
+    /// key.push_str(req.param("tenant").as_deref().unwrap_or_default());
+    /// key.push(':');
+    /// key.push_str(req.path());
+    /// ```
+    #[cfg(feature = "arena")]
+    pub fn arena(&self) -> &bumpalo::Bump {
+        &self.conn.arena
+    }
+
+    /// The state of the connection, shared by the requests on it, see
+    /// [`HttpServiceFactory::on_connect`](crate::HttpServiceFactory::on_connect).
+    /// Such as a user authenticated once per connection:
+    ///
+    /// ```ignore
+    /// let user = req.conn_state().extensions().get::<User>().cloned();
+    /// let user = match user {
+    ///     Some(user) => user,
+    ///     None => {
+    ///         let user = authenticate(req)?;
+    ///         req.conn_state().extensions_mut().insert(user.clone());
+    ///         user
+    ///     }
+    /// };
+    /// ```
+    ///
+    /// Don't hold it while calling this again, which panics.
+    pub fn conn_state(&self) -> RefMut<'_, ConnState> {
+        self.conn.state.borrow_mut()
+    }
+
+    /// The address of the client on the other end of the connection.
+    ///
+    /// Behind a reverse proxy this is the proxy's address.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.conn.remote
+    }
+
+    /// The local address the connection was accepted on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.conn.local
+    }
+
+    /// The address of the client, looked up in the `Forwarded` or
+    /// `X-Forwarded-For` headers if the connection comes from a
+    /// [trusted proxy](crate::ServerConfig::trusted_proxies).
+    ///
+    /// The forwarding chain is followed from the nearest hop while it
+    /// consists of trusted proxies; the first other address is the client.
+    /// Without trusted proxies this is the [`remote_addr`](Self::remote_addr).
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        let config = &self.conn.config;
+        let mut client = self.remote_addr()?.ip();
+        if !config.is_trusted_proxy(client) {
+            return Some(client);
+        }
+        let headers = self.headers();
+        let forwarded: Vec<&str> = if headers.iter().any(|h| h.name.eq_ignore_ascii_case("forwarded")) {
+            header_values(headers, "forwarded")
+                .flat_map(|value| value.split(','))
+                .map(|element| {
+                    element
+                        .split(';')
+                        .find_map(|pair| {
+                            let (name, value) = pair.split_once('=')?;
+                            name.trim().eq_ignore_ascii_case("for").then_some(value)
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        } else {
+            header_values(headers, "x-forwarded-for")
+                .flat_map(|value| value.split(','))
+                .collect()
+        };
+        for hop in forwarded.iter().rev() {
+            // `unknown` and obfuscated identifiers end the chain
+            let Some(ip) = parse_forwarded_ip(hop) else {
+                break;
+            };
+            client = ip;
+            if !config.is_trusted_proxy(ip) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    /// When the request has to be answered by, from the server's
+    /// [request timeout](crate::ServerConfig::request_timeout), a shorter
+    /// one the client asks for with `X-Request-Timeout: <seconds>`, such as
+    /// `X-Request-Timeout: 2.5`, or the
+    /// [timeout of the route](crate::router::RouteMut::timeout)
+    /// handling it, whichever ends first. `None` if none is set.
+    ///
+    /// Pass the [`time_left`](Self::time_left) on to database queries or
+    /// calls to other services rather than starting work that can't finish.
+    pub fn deadline(&self) -> Option<Instant> {
+        let requested = header_values(self.headers(), "x-request-timeout")
+            .next()
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+        let timeout = match (self.conn.config.request_timeout, requested) {
+            (Some(server), Some(client)) => Some(server.min(client)),
+            (server, client) => server.or(client),
+        };
+        // a timeout too large for an `Instant` means no deadline
+        let deadline = timeout.and_then(|timeout| self.received.checked_add(timeout));
+        match (deadline, self.route_deadline.get()) {
+            (Some(deadline), Some(route)) => Some(deadline.min(route)),
+            (deadline, route) => deadline.or(route),
+        }
+    }
+
+    /// Ends the request by `deadline` at the latest, see `RouteMut::timeout`.
+    pub(crate) fn limit(&self, deadline: Instant) {
+        let deadline = self.route_deadline.get().map_or(deadline, |limit| limit.min(deadline));
+        self.route_deadline.set(Some(deadline));
+    }
+
+    /// The connection, for its timeouts.
+    pub(crate) fn stream(&self) -> &TcpStream {
+        self.stream
+    }
+
+    /// The time until the [`deadline`](Self::deadline), zero once it has
+    /// passed.
+    pub fn time_left(&self) -> Option<Duration> {
+        Some(self.deadline()?.saturating_duration_since(Instant::now()))
+    }
+
+    /// Reads the rest of the body from the connection.
+    ///
+    /// Once [`read_body`](Self::read_body) has run, the reader goes over
+    /// the buffered body again instead.
+    pub fn body(mut self) -> BodyReader<'buf, 'stream> {
+        // the reader drains what is left of the body once dropped
+        self.conn.take_unread();
+        let replay = self.body.take().map(|body| Replay {
+            body: io::Cursor::new(body),
+            trailers: self.trailers.take(),
+        });
+        let unread = replay.is_none();
         BodyReader {
-            body_limit: self.content_length(),
+            body_limit: if unread { self.content_length() } else { 0 },
+            chunked: (unread && self.is_chunked()).then(Chunked::new),
+            replay,
             total_read: 0,
             stream: self.stream,
             req_buf: self.req_buf,
         }
     }
 
-    fn content_length(&self) -> usize {
+    /// Reads the whole body into memory, so it can be accessed through
+    /// `&Request` with [`body_bytes`](Self::body_bytes).
+    ///
+    /// The buffered body can be read any number of times, so middleware
+    /// such as a signature check or a mirror sending the request on to a
+    /// second backend can consume it and still leave it to the handler:
+    /// [`body_reader`](Self::body_reader) starts over on every call, and
+    /// [`body`](Self::body) streams it once more. Calling `read_body` again
+    /// returns the already buffered body.
+    ///
+    /// A `Transfer-Encoding: chunked` body is decoded; malformed chunk
+    /// framing fails with `ErrorKind::InvalidInput`. Bodies larger than
+    /// 1 MiB fail like those over the limit of
+    /// [`read_body_limited`](Self::read_body_limited), which takes another
+    /// limit.
+    pub fn read_body(&mut self) -> io::Result<&[u8]> {
+        self.read_body_limited(DEFAULT_BODY_LIMIT)
+    }
+
+    /// Like [`read_body`](Self::read_body), but fails with
+    /// `ErrorKind::InvalidData` if the body is larger than `limit` bytes.
+    ///
+    /// An oversized body isn't buffered. Up to 64 KiB more of it are
+    /// skipped, so the connection can still serve the next request; if
+    /// more is left, it closes after the response.
+    pub fn read_body_limited(&mut self, limit: usize) -> io::Result<&[u8]> {
+        if self.body.is_none() {
+            self.conn.take_unread();
+            if self.is_chunked() {
+                let body = self.read_chunked(limit)?;
+                self.body = Some(body);
+            } else if self.content_length() > limit {
+                self.skip_body()?;
+                return Err(too_large());
+            } else {
+                let len = self.content_length();
+                // the headers still borrow `req_buf`, so only advance it here
+                // and read anything that is missing straight from the stream,
+                // growing the body with what arrives rather than by the length
+                // the client announced
+                let buffered = len.min(self.req_buf.len());
+                let mut body = self.req_buf[..buffered].to_vec();
+                self.req_buf.advance(buffered);
+                let rest = (len - buffered) as u64;
+                let read = self.stream.take(rest).read_to_end(&mut body).map_err(body_error);
+                if !read.as_ref().is_ok_and(|&n| n as u64 == rest) {
+                    self.conn.body_left.set(true);
+                    read?;
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.body = Some(body);
+            }
+        }
+        Ok(self.body_bytes())
+    }
+
+    // Decodes a chunked body, skipping it if it is larger than `limit`
+    fn read_chunked(&mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut reader = ChunkedReader::new(ExactSource {
+            req_buf: self.req_buf,
+            stream: self.stream,
+            pending: Vec::new(),
+            pos: 0,
+        });
+        let mut body = Vec::new();
+        let read = (&mut reader).take((limit as u64).saturating_add(1)).read_to_end(&mut body);
+        let too_long = body.len() > limit;
+        let read = match read {
+            Ok(_) if too_long => io::copy(&mut (&mut reader).take(MAX_DRAIN), &mut io::sink()).map(drop),
+            read => read.map(drop),
+        };
+        // the connection can't go on from a body that ended early or failed
+        let (trailers, source) = reader.into_parts();
+        match trailers {
+            Some(_) => self.conn.read_ahead(&source.pending[source.pos..]),
+            None => self.conn.body_left.set(true),
+        }
+        read?;
+        if too_long {
+            self.body = Some(Vec::new());
+            return Err(too_large());
+        }
+        self.trailers = trailers;
+        Ok(body)
+    }
+
+    // Skips a body over the size limit, or leaves it if it is too long to
+    // wait for
+    fn skip_body(&mut self) -> io::Result<()> {
+        let len = self.content_length();
+        let buffered = len.min(self.req_buf.len());
+        self.req_buf.advance(buffered);
+        let rest = (len - buffered) as u64;
+        self.body = Some(Vec::new());
+        if rest > MAX_DRAIN {
+            self.conn.body_left.set(true);
+            return Ok(());
+        }
+        let skipped = io::copy(&mut self.stream.take(rest), &mut io::sink()).map_err(body_error)?;
+        if skipped < rest {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    /// Undoes the `gzip`, `deflate` or `br` coding named in the
+    /// `Content-Encoding` header on the body buffered by
+    /// [`read_body`](Self::read_body), reading the body first if that
+    /// hasn't happened yet.
+    ///
+    /// Fails with `ErrorKind::InvalidData` once the body as sent or the
+    /// decompressed body grows past `limit` bytes, `Unsupported` for other
+    /// codings and `InvalidInput` for corrupt data. The headers keep
+    /// describing the body as sent; calling it again does nothing.
+    #[cfg(feature = "decompression")]
+    pub fn decompress_body(&mut self, limit: usize) -> io::Result<&[u8]> {
+        if self.body.is_none() {
+            self.read_body_limited(limit)?;
+        }
+        if !self.decompressed
+            && let Some(encoding) = header_values(self.req.headers, "content-encoding").next()
+        {
+            let body = self.body.take().unwrap_or_default();
+            let decoded = crate::decompress::decode(encoding, body, limit);
+            self.decompressed = true;
+            self.body = Some(decoded?);
+        }
+        Ok(self.body_bytes())
+    }
+
+    /// The trailer fields sent after a chunked body, such as a checksum
+    /// announced with `Trailer: Content-MD5`. `None` until
+    /// [`read_body`](Self::read_body) has read the body, and for bodies
+    /// that aren't chunked; see [`BodyReader::trailers`] when streaming.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    /// The body buffered by [`read_body`](Self::read_body), empty if it
+    /// has not been read.
+    pub fn body_bytes(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+
+    /// The buffered body as a reader, e.g. for deserializers reading from
+    /// `impl Read`. Empty if the body has not been read.
+    pub fn body_reader(&self) -> impl BufRead + '_ {
+        self.body_bytes()
+    }
+
+    /// Deserializes the buffered JSON body into `T`.
+    ///
+    /// Fails with a `415 Unsupported Media Type` error unless the
+    /// Content-Type is `application/json` or another `+json` type, and with
+    /// `400 Bad Request` if the body is not valid JSON for `T`; the latter
+    /// wraps the `serde_json::Error`. The body must have been read, which
+    /// [`ApiService`](crate::router::ApiService) does up to the router's
+    /// [body limit](crate::Router::body_limit).
+    ///
+    /// ```ignore
+    /// fn create_user(req: &Request, _: Params) -> Result<(StatusCode, Value), HandlerError> {
+    ///     let user: NewUser = req.json()?;
+    ///     // ...
+    /// }
+    /// ```
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, HandlerError> {
+        if !self.content_type().is_some_and(|ct| ct.is_json()) {
+            return Err(HandlerError::with_status(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected a JSON body",
+            ));
+        }
+        serde_json::from_slice(self.body_bytes())
+            .map_err(|e| HandlerError::with_status(StatusCode::BAD_REQUEST, e))
+    }
+
+    /// The parsed `Content-Type` header, `None` without one or if it isn't
+    /// a valid media type.
+    ///
+    /// ```ignore
+    /// match req.content_type() {
+    ///     Some(ct) if ct.is_json() => ...,
+    ///     Some(ct) if ct.essence() == "multipart/form-data" => {
+    ///         let boundary = ct.boundary().ok_or("missing boundary")?;
+    ///         ...
+    ///     }
+    ///     _ => ...,
+    /// }
+    /// ```
+    pub fn content_type(&self) -> Option<MediaType> {
+        MediaType::parse(header_values(self.headers(), "content-type").next()?)
+    }
+
+    /// The length announced in the `Content-Length` header, `0` without one
+    /// or for a chunked body.
+    pub fn content_length(&self) -> usize {
+        if self.is_chunked() {
+            return 0;
+        }
         let mut len = 0;
         for header in self.req.headers.iter() {
             if header.name.eq_ignore_ascii_case("content-length") {
@@ -132,6 +1091,72 @@ impl<'buf, 'stream> Request<'buf, '_, 'stream> {
         }
         len
     }
+
+    // Whether the body is sent with `Transfer-Encoding: chunked`; `decode`
+    // refuses any other transfer coding, and one with `Content-Length`
+    fn is_chunked(&self) -> bool {
+        self.req.headers.iter().any(|h| h.name.eq_ignore_ascii_case("transfer-encoding"))
+    }
+}
+
+pub(crate) fn header_values<'h>(headers: &'h [httparse::Header<'_>], name: &str) -> impl Iterator<Item = &'h str> {
+    headers
+        .iter()
+        .filter(move |h| h.name.eq_ignore_ascii_case(name))
+        .filter_map(|h| std::str::from_utf8(h.value).ok())
+}
+
+// `value;q=0.8` as the value and its quality, `1` if not given
+pub(crate) fn parse_weighted(item: &str) -> Option<(&str, f32)> {
+    let mut parts = item.split(';').map(str::trim);
+    let value = parts.next().filter(|value| !value.is_empty())?;
+    let q = parts
+        .find_map(|param| {
+            let (name, q) = param.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("q").then(|| q.trim().parse().ok())?
+        })
+        .unwrap_or(1.0f32);
+    Some((value, q.clamp(0.0, 1.0)))
+}
+
+// The quality of `tag` and the position of the range deciding it: the most
+// specific range covering the tag, or else a more specific range of the same
+// language, such as `en-GB` for `en`
+fn language_quality(ranges: &[(&str, f32)], tag: &str) -> Option<(f32, usize)> {
+    // `en` covers `en` and `en-GB` but not `eng`
+    fn covers(range: &str, tag: &str) -> bool {
+        let (range, tag) = (range.as_bytes(), tag.as_bytes());
+        tag.len() >= range.len()
+            && tag[..range.len()].eq_ignore_ascii_case(range)
+            && tag.get(range.len()).is_none_or(|&b| b == b'-')
+    }
+    let best = |matches: &dyn Fn(&str) -> bool| {
+        ranges
+            .iter()
+            .enumerate()
+            .filter(|(_, (range, _))| matches(range))
+            .max_by_key(|&(index, (range, _))| {
+                let specificity = if *range == "*" { 0 } else { range.len() };
+                (specificity, std::cmp::Reverse(index))
+            })
+            .map(|(index, &(_, q))| (q, index))
+    };
+    best(&|range| range == "*" || covers(range, tag)).or_else(|| best(&|range| range != "*" && covers(tag, range)))
+}
+
+// `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::17` or `"[2001:db8::17]:4711"`
+fn parse_forwarded_ip(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    hop.parse()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "request body exceeds the size limit")
 }
 
 impl fmt::Debug for Request<'_, '_, '_> {
@@ -140,10 +1165,116 @@ impl fmt::Debug for Request<'_, '_, '_> {
     }
 }
 
+/// A request the server refuses to read on, answered with `code` before
+/// the connection is closed.
+#[derive(Debug)]
+pub(crate) struct Rejection {
+    pub(crate) code: usize,
+    pub(crate) reason: &'static str,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "request rejected: {} {}", self.code, self.reason)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+pub(crate) fn reject(code: usize, reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Rejection { code, reason })
+}
+
+/// The refusal an error carries, if it is one.
+pub(crate) fn rejection(e: &io::Error) -> Option<&Rejection> {
+    e.get_ref()?.downcast_ref::<Rejection>()
+}
+
+// A body that stopped arriving for `ServerConfig::body_timeout` is answered
+// with 408 and the connection closed, as the rest of it can't be told
+// apart from the next request
+fn body_error(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => io::Error::new(
+            io::ErrorKind::TimedOut,
+            Rejection {
+                code: 408,
+                reason: "Request Timeout",
+            },
+        ),
+        _ => e,
+    }
+}
+
+// Checks the limits that don't depend on the head being complete
+fn check_head(buf: &[u8], config: &ServerConfig) -> io::Result<()> {
+    let line = buf.split(|&b| b == b'\n').next().unwrap_or_default();
+    let target = line.split(|&b| b == b' ').nth(1).unwrap_or_default();
+    if target.len() > config.max_uri_length {
+        return err(reject(414, "URI Too Long"));
+    }
+    if buf.len() > config.max_header_bytes {
+        return err(reject(431, "Request Header Fields Too Large"));
+    }
+    Ok(())
+}
+
+// A body length that can't be read, or that could be read two ways, leaves
+// it unclear where the next request starts, which smuggles requests past a
+// proxy that reads it the other way. So `Transfer-Encoding` must end in a
+// single `chunked` and come without `Content-Length`, and several
+// `Content-Length` values must agree
+fn check_framing(headers: &[httparse::Header<'_>]) -> io::Result<()> {
+    let mut transfer_encoded = false;
+    let mut codings = Vec::new();
+    for h in headers.iter().filter(|h| h.name.eq_ignore_ascii_case("transfer-encoding")) {
+        let value = std::str::from_utf8(h.value).map_err(|_| reject(400, "Bad Request"))?;
+        transfer_encoded = true;
+        codings.extend(value.split(',').map(str::trim).filter(|c| !c.is_empty()));
+    }
+    if transfer_encoded {
+        let chunked = |c: &&str| c.eq_ignore_ascii_case("chunked");
+        match codings.split_last() {
+            Some((last, rest)) if chunked(last) && !rest.iter().any(chunked) => {}
+            _ => return err(reject(400, "Bad Request")),
+        }
+        if codings.len() > 1 {
+            return err(reject(501, "Not Implemented"));
+        }
+        if headers.iter().any(|h| h.name.eq_ignore_ascii_case("content-length")) {
+            return err(reject(400, "Bad Request"));
+        }
+    }
+    let mut length = None;
+    for h in headers.iter().filter(|h| h.name.eq_ignore_ascii_case("content-length")) {
+        let parsed = std::str::from_utf8(h.value)
+            .ok()
+            .map(str::trim)
+            .filter(|value| value.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|value| value.parse::<usize>().ok());
+        match (parsed, length) {
+            (None, _) => return err(reject(400, "Bad Request")),
+            (Some(n), Some(previous)) if n != previous => return err(reject(400, "Bad Request")),
+            (n, _) => length = n,
+        }
+    }
+    Ok(())
+}
+
+/// Lends the header slots kept for a connection to the next request.
+pub(crate) fn header_slots<'h, 'buf>(
+    slots: &'h mut [MaybeUninit<httparse::Header<'static>>],
+) -> &'h mut [MaybeUninit<httparse::Header<'buf>>] {
+    // safety: only the lifetime of the uninitialized slots changes, and the
+    // request borrowing them is gone before they are lent out again
+    unsafe { std::slice::from_raw_parts_mut(slots.as_mut_ptr().cast(), slots.len()) }
+}
+
 pub fn decode<'header, 'buf, 'stream>(
-    headers: &'header mut [MaybeUninit<httparse::Header<'buf>>; MAX_HEADERS],
+    headers: &'header mut [MaybeUninit<httparse::Header<'buf>>],
     req_buf: &'buf mut BytesMut,
-    stream: &'stream mut TcpStream,
+    stream: &'stream TcpStream,
+    conn: &'stream Conn,
 ) -> io::Result<Option<Request<'buf, 'header, 'stream>>> {
     let mut req = httparse::Request::new(&mut []);
     // safety: don't hold the reference of req_buf
@@ -151,23 +1282,46 @@ pub fn decode<'header, 'buf, 'stream>(
     let buf: &[u8] = unsafe { std::mem::transmute(req_buf.chunk()) };
     let status = match req.parse_with_uninit_headers(buf, headers) {
         Ok(s) => s,
-        Err(e) => {
-            let msg = format!("failed to parse http request: {e:?}");
-            eprintln!("{msg}");
-            return err(io::Error::new(io::ErrorKind::Other, msg));
+        Err(httparse::Error::TooManyHeaders) => {
+            return err(reject(431, "Request Header Fields Too Large"));
         }
+        // includes header names and values with bytes such as CR, LF or
+        // NUL that could smuggle in lines of their own
+        Err(_) => return err(reject(400, "Bad Request")),
     };
 
     let len = match status {
         httparse::Status::Complete(amt) => amt,
-        httparse::Status::Partial => return Ok(None),
+        httparse::Status::Partial => {
+            check_head(buf, &conn.config)?;
+            return Ok(None);
+        }
     };
+    check_head(&buf[..len], &conn.config)?;
+    check_framing(req.headers)?;
     req_buf.advance(len);
 
     // println!("req: {:?}", std::str::from_utf8(req_buf).unwrap());
-    Ok(Some(Request {
+    let req = Request {
         req,
+        head: &buf[..len],
         req_buf,
         stream,
-    }))
+        body: None,
+        trailers: None,
+        #[cfg(feature = "decompression")]
+        decompressed: false,
+        conn,
+        extensions: RefCell::default(),
+        params: RefCell::default(),
+        received: Instant::now(),
+        route_deadline: Cell::new(None),
+    };
+    let unread = match req.content_length() {
+        _ if req.is_chunked() => Some(Unread::Chunked),
+        0 => None,
+        len => Some(Unread::Length(len)),
+    };
+    conn.unread.set(unread);
+    Ok(Some(req))
 }
\ No newline at end of file
@@ -1,53 +1,637 @@
-use std::io;
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::request::MAX_HEADERS;
+use crate::Request;
+use crate::cache_control::CacheControl;
+use crate::cookie::Cookie;
+use crate::date::Clock;
+use crate::error_handler::{ErrorHandler, GenericErrors};
+use crate::range::{self, content_range};
+use crate::security_headers::SecurityHeaders;
+use crate::upgrade::Upgrade;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, CACHE_CONTROL,
+    CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LAST_MODIFIED, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, TRAILER, TRANSFER_ENCODING,
+    SET_COOKIE, UPGRADE, VARY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use may::net::TcpStream;
+use serde::Serialize;
+use smallvec::SmallVec;
+
+// The header limit of `ServerConfig` and of responses built outside a
+// connection
+pub(crate) const DEFAULT_MAX_HEADERS: usize = 64;
+
+// The charset of `ServerConfig` and of responses built outside a
+// connection
+pub(crate) const DEFAULT_CHARSET: &str = "utf-8";
+
+enum Header {
+    // a full `Name: value` line
+    Line(Cow<'static, str>),
+    Pair(HeaderName, HeaderValue),
+}
+
+impl Header {
+    fn value(&self) -> &[u8] {
+        match self {
+            Header::Line(line) => line.split_once(':').map_or("", |(_, v)| v.trim()).as_bytes(),
+            Header::Pair(_, value) => value.as_bytes(),
+        }
+    }
+
+    fn is_named(&self, name: &HeaderName) -> bool {
+        match self {
+            Header::Line(line) => line
+                .split_once(':')
+                .is_some_and(|(n, _)| n.trim().eq_ignore_ascii_case(name.as_str())),
+            Header::Pair(n, _) => n == name,
+        }
+    }
+}
 
-use bytes::BytesMut;
 pub struct Response<'a> {
-    headers: [&'static str; MAX_HEADERS],
-    headers_len: usize,
+    // most responses have a handful of headers, kept inline
+    headers: SmallVec<[Header; 8]>,
+    max_headers: usize,
+    // a header was dropped over `max_headers`, answered with a 500
+    overflowed: bool,
+    // a computed header wasn't valid in a header, answered with a 500
+    invalid_header: bool,
     status_message: StatusMessage,
-    body: Body,
-    rsp_buf: &'a mut BytesMut,
+    body: Body<'a>,
+    // Content-Length announced without sending a body, for HEAD
+    head_length: Option<usize>,
+    // sent after the last chunk of a chunked body
+    trailers: HeaderMap,
+    // replaces any `Cache-Control` header unless empty
+    cache_control: CacheControl,
+    // where `1xx` responses are written as they are added, unless responses
+    // to earlier requests are still buffered
+    interim: Option<&'a TcpStream>,
+    // encoded `1xx` responses sent with this one, for want of `interim`
+    informational: Vec<u8>,
+    // answered on the connection already, by a route timeout, and dropped
+    // unsent
+    sent_early: bool,
+    // `json` indents its output
+    pretty_json: bool,
+    // dates the response instead of the system time
+    clock: Option<Arc<dyn Clock>>,
+    // added to a `text/*` Content-Type without one
+    charset: Option<&'static str>,
+    // what the connection switches to after a 101
+    upgrade: Option<Upgrade>,
+    rsp_buf: RspBuf<'a>,
+}
+
+// Where body bytes are written: the connection's buffer, reused from one
+// response to the next, or a buffer of its own for a response built apart
+// from a connection
+enum RspBuf<'a> {
+    Borrowed(&'a mut BytesMut),
+    Owned(BytesMut),
+}
+
+impl Deref for RspBuf<'_> {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        match self {
+            RspBuf::Borrowed(buf) => buf,
+            RspBuf::Owned(buf) => buf,
+        }
+    }
 }
 
-pub enum Body {
+impl DerefMut for RspBuf<'_> {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        match self {
+            RspBuf::Borrowed(buf) => buf,
+            RspBuf::Owned(buf) => buf,
+        }
+    }
+}
+
+pub(crate) type StreamBody<'a> = Box<dyn FnOnce(&mut StreamWriter<'_>) -> io::Result<()> + 'a>;
+
+pub enum Body<'a> {
     Dummy,
     Vec(Vec<u8>),
     Str(&'static str),
+    // shared with other responses, such as a cached payload
+    Shared(Bytes),
+    // pieces sent one after the other, see `Response::body_chunks`
+    Fragments(Vec<Bytes>),
+    // chunks already framed in `rsp_buf`
+    Chunked,
+    // written to the connection after the head, see `Response::stream`
+    Stream(StreamBody<'a>),
+    // this many bytes of the file from its current position
+    File(File, u64),
 }
 
+/// A body `encode` leaves to be written to the connection after the head.
+pub(crate) enum Deferred<'a> {
+    Stream(StreamBody<'a>, HeaderMap),
+    File(File, u64),
+    // written next to the head in vectored writes
+    Bytes(SmallVec<[Bytes; 1]>),
+}
+
+// Bodies up to this size are copied after the head, larger ones are left
+// where they are for a vectored write
+const COPY_BODY_LEN: usize = 16 * 1024;
+
 struct StatusMessage {
     code: usize,
-    msg: &'static str,
+    msg: Cow<'static, str>,
 }
 
 impl<'a> Response<'a> {
-    pub(crate) fn new(rsp_buf: &'a mut BytesMut) -> Response<'a> {
-        let headers: [&'static str; 16] = [""; 16];
+    pub(crate) fn new(rsp_buf: &'a mut BytesMut, max_headers: usize) -> Response<'a> {
+        Self::with_buf(RspBuf::Borrowed(rsp_buf), max_headers)
+    }
 
+    fn with_buf(rsp_buf: RspBuf<'a>, max_headers: usize) -> Response<'a> {
         Response {
-            headers,
-            headers_len: 0,
+            headers: SmallVec::new(),
+            max_headers,
+            overflowed: false,
+            invalid_header: false,
             body: Body::Dummy,
+            head_length: None,
+            trailers: HeaderMap::new(),
+            cache_control: CacheControl::new(),
+            interim: None,
+            informational: Vec::new(),
+            sent_early: false,
+            pretty_json: false,
+            clock: None,
+            charset: Some(DEFAULT_CHARSET),
+            upgrade: None,
             status_message: StatusMessage {
                 code: 200,
-                msg: "Ok",
+                msg: Cow::Borrowed("Ok"),
             },
             rsp_buf,
         }
     }
 
+    /// Sets the status, such as `StatusCode::NOT_FOUND`, sent with its
+    /// canonical reason phrase.
     #[inline]
-    pub fn status_code(&mut self, code: usize, msg: &'static str) -> &mut Self {
+    pub fn status(&mut self, status: StatusCode) -> &mut Self {
+        let msg = status.canonical_reason().unwrap_or("Unknown Status Code");
+        self.status_code(status.as_u16() as usize, msg)
+    }
+
+    /// Sets the status with a reason phrase of its own, see
+    /// [`status`](Self::status) for the canonical one. The phrase may be
+    /// computed, such as one passed through from an upstream response;
+    /// control characters in it are sent as spaces, so it can't break the
+    /// status line.
+    #[inline]
+    pub fn status_code(&mut self, code: usize, msg: impl Into<Cow<'static, str>>) -> &mut Self {
+        let mut msg = msg.into();
+        let control = |c: char| c.is_ascii_control() && c != '\t';
+        if msg.contains(control) {
+            msg = Cow::Owned(msg.replace(control, " "));
+        }
         self.status_message = StatusMessage { code, msg };
         self
     }
 
+    /// Adds a full header line such as `"Content-Type: text/plain"`,
+    /// stored without copying.
+    ///
+    /// The line is sent as it is, so it only takes static strings; computed
+    /// headers go through [`set_header`](Self::set_header) and
+    /// [`append_header`](Self::append_header), which check names and values.
+    ///
+    /// A header over [`ServerConfig::max_response_headers`] is dropped and
+    /// the response is replaced by a `500 Internal Server Error`, the
+    /// checked methods fail instead.
+    ///
+    /// [`ServerConfig::max_response_headers`]: crate::ServerConfig::max_response_headers
     #[inline]
     pub fn header(&mut self, header: &'static str) -> &mut Self {
-        self.headers[self.headers_len] = header;
-        self.headers_len += 1;
+        self.push_header(Header::Line(Cow::Borrowed(header)));
+        self
+    }
+
+    /// Adds a header from its name and value, without formatting a line.
+    /// Limited like [`header`](Self::header).
+    #[inline]
+    pub fn header_pair(&mut self, name: HeaderName, value: HeaderValue) -> &mut Self {
+        self.push_header(Header::Pair(name, value));
+        self
+    }
+
+    fn push_header(&mut self, header: Header) {
+        if self.headers.len() < self.max_headers {
+            self.headers.push(header);
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    // Adds a header whose value the response computes, limited like
+    // `header`; a value that isn't valid in a header is dropped and
+    // answered with a 500
+    fn computed_header<V: TryInto<HeaderValue>>(&mut self, name: HeaderName, value: V) -> &mut Self {
+        match value.try_into() {
+            Ok(value) => self.push_header(Header::Pair(name, value)),
+            Err(_) => self.invalid_header = true,
+        }
+        self
+    }
+
+    // Adds a header the server sends itself, which doesn't count against
+    // the limit of the handler
+    pub(crate) fn server_header(&mut self, header: impl Into<Cow<'static, str>>) {
+        self.headers.push(Header::Line(header.into()));
+    }
+
+    /// Sets the header `name` to `value`, replacing any header of that name
+    /// added before, compared ignoring case. Meant for headers sent once,
+    /// such as `Content-Type` or `Cache-Control`.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` for a name or value that isn't
+    /// valid in a header, such as one containing a line break, and with
+    /// `ErrorKind::OutOfMemory` when the response already has
+    /// [`ServerConfig::max_response_headers`] headers.
+    ///
+    /// [`ServerConfig::max_response_headers`]: crate::ServerConfig::max_response_headers
+    ///
+    /// ```ignore
+    /// rsp.set_header("Content-Type", "application/json")?
+    ///     .append_header("Set-Cookie", "theme=dark")?;
+    /// ```
+    pub fn set_header<K, V>(&mut self, name: K, value: V) -> io::Result<&mut Self>
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        let (name, value) = header(name, value)?;
+        self.headers.retain(|h| !h.is_named(&name));
+        self.checked_push(Header::Pair(name, value))
+    }
+
+    /// Adds the header `name` with `value`, keeping those added before.
+    /// Meant for headers that may be sent several times, such as
+    /// `Set-Cookie` or `Link`. Every value goes out on a line of its own in
+    /// the order added, values are never joined with commas, which
+    /// `Set-Cookie` doesn't allow.
+    ///
+    /// Fails like [`set_header`](Self::set_header).
+    pub fn append_header<K, V>(&mut self, name: K, value: V) -> io::Result<&mut Self>
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        let (name, value) = header(name, value)?;
+        self.checked_push(Header::Pair(name, value))
+    }
+
+    /// Sets the `Content-Type` to `mime`, such as `"image/svg+xml"`,
+    /// replacing one added before. Limited like [`header`](Self::header);
+    /// a `mime` that isn't a valid header value has the response answered
+    /// with a `500 Internal Server Error`.
+    #[inline]
+    pub fn content_type(&mut self, mime: &'static str) -> &mut Self {
+        self.headers.retain(|h| !h.is_named(&CONTENT_TYPE));
+        self.computed_header(CONTENT_TYPE, mime)
+    }
+
+    /// Sets the `Content-Type` to `application/json`, see
+    /// [`content_type`](Self::content_type).
+    #[inline]
+    pub fn content_type_json(&mut self) -> &mut Self {
+        self.content_type("application/json")
+    }
+
+    /// Sets the `Content-Type` to `text/html; charset=utf-8`, see
+    /// [`content_type`](Self::content_type).
+    #[inline]
+    pub fn content_type_html(&mut self) -> &mut Self {
+        self.content_type("text/html; charset=utf-8")
+    }
+
+    fn checked_push(&mut self, header: Header) -> io::Result<&mut Self> {
+        if self.headers.len() >= self.max_headers {
+            return Err(io::Error::new(io::ErrorKind::OutOfMemory, "too many response headers"));
+        }
+        self.headers.push(header);
+        Ok(self)
+    }
+
+    /// Adds a trailer field, sent after the last chunk of a
+    /// [chunked](Self::chunked) or [streamed](Self::stream) body and
+    /// announced in a `Trailer` header. Meant for fields known only once
+    /// the body is written, such as `Server-Timing` or a checksum; see
+    /// [`StreamWriter::trailer`] for those of a streamed body. Responses
+    /// with a `Content-Length` have no trailers.
+    ///
+    /// Fails like [`append_header`](Self::append_header), and with
+    /// `ErrorKind::InvalidInput` for fields that frame the message, such
+    /// as `Content-Length`.
+    pub fn trailer<K, V>(&mut self, name: K, value: V) -> io::Result<&mut Self>
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        let (name, value) = trailer(name, value)?;
+        self.trailers.append(name, value);
+        Ok(self)
+    }
+
+    /// Sets the `Cache-Control` header to `cache`, in place of any added
+    /// as a header or by the shorthands below.
+    pub fn cache_control(&mut self, cache: CacheControl) -> &mut Self {
+        self.cache_control = cache;
+        self
+    }
+
+    /// Lets caches keep the response for `max_age`, see
+    /// [`CacheControl::max_age`]. Combines with the other shorthands, as in
+    /// `rsp.public().cache_for(Duration::from_secs(300))`.
+    pub fn cache_for(&mut self, max_age: Duration) -> &mut Self {
+        self.cache_control = std::mem::take(&mut self.cache_control).max_age(max_age);
+        self
+    }
+
+    /// How long shared caches keep the response, see
+    /// [`CacheControl::s_maxage`].
+    pub fn s_maxage(&mut self, s_maxage: Duration) -> &mut Self {
+        self.cache_control = std::mem::take(&mut self.cache_control).s_maxage(s_maxage);
+        self
+    }
+
+    /// Lets shared caches keep the response, see [`CacheControl::public`].
+    pub fn public(&mut self) -> &mut Self {
+        self.cache_control = std::mem::take(&mut self.cache_control).public();
+        self
+    }
+
+    /// Keeps the response out of every cache, see
+    /// [`CacheControl::no_store`].
+    pub fn no_store(&mut self) -> &mut Self {
+        self.cache_control = std::mem::take(&mut self.cache_control).no_store();
+        self
+    }
+
+    /// Sends a `103 Early Hints` response ahead of this one, with a `Link`
+    /// header for each of `links`, such as
+    /// `</style.css>; rel=preload; as=style`, so the client or a proxy in
+    /// front can start fetching them.
+    ///
+    /// Fails like [`informational`](Self::informational).
+    pub fn early_hints<I>(&mut self, links: I) -> io::Result<&mut Self>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let links: Vec<I::Item> = links.into_iter().collect();
+        let headers: Vec<(&str, &str)> = links.iter().map(|link| ("Link", link.as_ref())).collect();
+        self.informational(StatusCode::EARLY_HINTS, &headers)
+    }
+
+    /// Sends an informational `1xx` response with `headers` ahead of this
+    /// one. It is written to the connection when called, for the client to
+    /// act on while the final response is prepared; behind responses to
+    /// earlier pipelined requests that aren't written yet, it goes out with
+    /// them instead. HTTP/1.0 clients, which don't expect them, get none.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` for a status outside of `1xx`,
+    /// for `101 Switching Protocols`, which changes what follows on the
+    /// connection, and for a header [`set_header`](Self::set_header) would
+    /// reject, as well as with the error of writing to the connection.
+    pub fn informational(&mut self, status: StatusCode, headers: &[(&str, &str)]) -> io::Result<&mut Self> {
+        if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an informational status other than 101",
+            ));
+        }
+        let headers = headers
+            .iter()
+            .map(|&(name, value)| header(name, value))
+            .collect::<io::Result<Vec<_>>>()?;
+        let start = self.informational.len();
+        let out = &mut self.informational;
+        let reason = status.canonical_reason().unwrap_or("Unknown Status Code");
+        out.extend_from_slice(format!("HTTP/1.1 {} {reason}\r\n", status.as_u16()).as_bytes());
+        for (name, value) in headers {
+            out.extend_from_slice(name.as_str().as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"\r\n");
+        if let Some(mut stream) = self.interim {
+            let written = stream.write_all(&self.informational[start..]).and_then(|()| stream.flush());
+            self.informational.truncate(start);
+            written?;
+        }
+        Ok(self)
+    }
+
+    // Has `1xx` responses written to `stream` right away, see
+    // `informational`
+    pub(crate) fn interim(&mut self, stream: &'a TcpStream) {
+        self.interim = Some(stream);
+    }
+
+    // The connection, if nothing is buffered ahead of the response, for
+    // answering on it directly
+    pub(crate) fn direct_stream(&self) -> Option<&'a TcpStream> {
+        self.interim
+    }
+
+    // The clock dating the response, see `clock`
+    pub(crate) fn clock_handle(&self) -> Option<Arc<dyn Clock>> {
+        self.clock.clone()
+    }
+
+    // Notes that the request was answered on the connection already, which
+    // is then closed without sending the response
+    pub(crate) fn sent_early(&mut self) {
+        self.sent_early = true;
+    }
+
+    pub(crate) fn is_sent_early(&self) -> bool {
+        self.sent_early
+    }
+
+    // HTTP/1.0 clients don't know informational responses
+    pub(crate) fn drop_informational(&mut self) {
+        self.informational.clear();
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`. Every call adds a header,
+    /// so a session and a CSRF cookie are set with one call each.
+    ///
+    /// Fails like [`append_header`](Self::append_header), such as for a
+    /// cookie whose value contains a line break.
+    #[inline]
+    pub fn set_cookie(&mut self, cookie: &Cookie) -> io::Result<&mut Self> {
+        self.append_header(SET_COOKIE, cookie)
+    }
+
+    /// Adds a `Content-Disposition` header that has browsers download the
+    /// body and save it as `filename`, paired with
+    /// [`send_file`](Self::send_file) for a download endpoint:
+    ///
+    /// ```ignore
+    /// rsp.attachment("Q3 report.pdf").send_file(&req, "./reports/q3.pdf")?;
+    /// ```
+    ///
+    /// A name that isn't plain ASCII is also sent as a UTF-8 `filename*`
+    /// (RFC 5987), next to an ASCII fallback for older clients.
+    #[inline]
+    pub fn attachment(&mut self, filename: &str) -> &mut Self {
+        self.computed_header(CONTENT_DISPOSITION, content_disposition(filename))
+    }
+
+    /// Adds the headers of `headers`, replacing those of the same names
+    /// added before. Limited like [`header`](Self::header).
+    pub fn security_headers(&mut self, headers: &SecurityHeaders) -> &mut Self {
+        self.add_security_headers(headers, true)
+    }
+
+    // Adds the security headers the handler didn't set itself, for the
+    // router's defaults
+    pub(crate) fn default_security_headers(&mut self, headers: &SecurityHeaders) -> &mut Self {
+        self.add_security_headers(headers, false)
+    }
+
+    fn add_security_headers(&mut self, headers: &SecurityHeaders, replace: bool) -> &mut Self {
+        let fields = [
+            (STRICT_TRANSPORT_SECURITY, &headers.hsts),
+            (X_CONTENT_TYPE_OPTIONS, &headers.content_type_options),
+            (X_FRAME_OPTIONS, &headers.frame_options),
+            (REFERRER_POLICY, &headers.referrer_policy),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                if !replace && self.header_value(&name).is_some() {
+                    continue;
+                }
+                self.headers.retain(|h| !h.is_named(&name));
+                self.header_pair(name, value.clone());
+            }
+        }
+        self
+    }
+
+    /// Adds the recommended security headers, those of
+    /// [`SecurityHeaders::default`], see
+    /// [`security_headers`](Self::security_headers).
+    #[inline]
+    pub fn secure_defaults(&mut self) -> &mut Self {
+        self.security_headers(&SecurityHeaders::default())
+    }
+
+    /// Lets pages from `origin` read the response, with
+    /// `Access-Control-Allow-Origin` and, unless `origin` is `*`, a
+    /// `Vary: Origin` so caches don't serve it to other origins. The
+    /// returned [`Cors`] adds the other `Access-Control-*` headers, the
+    /// same calls answer a preflight and the request that follows:
+    ///
+    /// ```ignore
+    /// let cors = rsp.cors_allow("https://app.example.com")?.credentials();
+    /// if req.method() == "OPTIONS" {
+    ///     cors.methods(["GET", "POST"])
+    ///         .headers(["Content-Type", "Authorization"])
+    ///         .max_age(Duration::from_secs(600));
+    ///     rsp.status(StatusCode::NO_CONTENT);
+    /// }
+    /// ```
+    ///
+    /// An `origin` echoed from the request is checked: fails with
+    /// `ErrorKind::InvalidInput` if it isn't a valid header value, and like
+    /// [`set_header`](Self::set_header) when the response is full.
+    pub fn cors_allow(&mut self, origin: &str) -> io::Result<Cors<'_, 'a>> {
+        self.set_header(ACCESS_CONTROL_ALLOW_ORIGIN, origin)?;
+        if origin != "*" {
+            self.append_header(VARY, "Origin")?;
+        }
+        Ok(Cors { rsp: self })
+    }
+
+    /// Dates the response by `clock` instead of the system time, see
+    /// [`date`](crate::date).
+    #[inline]
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    // The status `encode` sends, for `ServerConfig::on_response`
+    pub(crate) fn status_sent(&self) -> u16 {
+        if self.overflowed || self.invalid_header {
+            500
+        } else {
+            self.status_message.code as u16
+        }
+    }
+
+    // See `ServerConfig::text_charset`
+    #[inline]
+    pub(crate) fn text_charset(&mut self, charset: Option<&'static str>) {
+        self.charset = charset;
+    }
+
+    // The value of the first header named `name`
+    pub(crate) fn header_value(&self, name: &HeaderName) -> Option<&[u8]> {
+        self.headers.iter().find(|h| h.is_named(name)).map(Header::value)
+    }
+
+    // The body if it is held in memory, not one written to the connection
+    // after the head or a HEAD response's length
+    #[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+    pub(crate) fn body_in_memory(&mut self) -> Option<&[u8]> {
+        match self.body {
+            Body::Chunked | Body::Stream(_) | Body::File(..) => None,
+            _ if self.head_length.is_some() => None,
+            _ => Some(self.get_body()),
+        }
+    }
+
+    // Drops the status, headers and body a handler wrote, for the router to
+    // answer with an error instead; the server's settings such as the clock
+    // stay
+    pub(crate) fn reset(&mut self) {
+        self.headers.clear();
+        self.overflowed = false;
+        self.invalid_header = false;
+        self.status_message = StatusMessage {
+            code: 200,
+            msg: Cow::Borrowed("Ok"),
+        };
+        self.body = Body::Dummy;
+        self.head_length = None;
+        self.trailers.clear();
+        self.cache_control = CacheControl::new();
+        self.upgrade = None;
+        self.rsp_buf.clear();
+    }
+
+    /// Sends `Content-Length: len` with no body bytes, as a HEAD response does.
+    #[inline]
+    pub(crate) fn head_content_length(&mut self, len: usize) -> &mut Self {
+        self.head_length = Some(len);
         self
     }
 
@@ -61,42 +645,623 @@ impl<'a> Response<'a> {
         self.body = Body::Vec(v);
     }
 
+    /// Serializes `value` as the body, sent as `application/json`. A body
+    /// set before is dropped. Fails with the serializer's error, such as
+    /// for a map with keys that aren't strings.
+    pub fn json<T: Serialize + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        self.body = Body::Dummy;
+        self.rsp_buf.clear();
+        let out = (&mut *self.rsp_buf).writer();
+        let written = if self.pretty_json {
+            serde_json::to_writer_pretty(out, value)
+        } else {
+            serde_json::to_writer(out, value)
+        };
+        if let Err(e) = written {
+            self.rsp_buf.clear();
+            return Err(e.into());
+        }
+        self.content_type_json();
+        Ok(())
+    }
+
+    /// Has [`json`](Self::json) indent its output for people to read,
+    /// overriding [`ServerConfig::pretty_json`] for this response, such as
+    /// for a `?pretty` query parameter:
+    ///
+    /// ```ignore
+    /// rsp.pretty_json(req.url().query_pairs().contains_key("pretty"));
+    /// rsp.json(&catalog)?;
+    /// ```
+    ///
+    /// [`ServerConfig::pretty_json`]: crate::ServerConfig::pretty_json
+    #[inline]
+    pub fn pretty_json(&mut self, enabled: bool) -> &mut Self {
+        self.pretty_json = enabled;
+        self
+    }
+
+    /// Sets a body that other responses share, such as a large payload
+    /// cached in memory. Cloning `Bytes` doesn't copy the data, and like
+    /// any body that isn't small it goes from there straight to the
+    /// connection, next to the head, instead of being copied after it.
+    #[inline]
+    pub fn body_shared(&mut self, body: Bytes) {
+        self.body = Body::Shared(body);
+    }
+
+    /// Sets a body made of `chunks`, such as fragments of a page rendered
+    /// ahead of time, which are sent one after the other without being
+    /// joined first:
+    ///
+    /// ```ignore
+    /// let len = header.len() + content.len() + footer.len();
+    /// rsp.body_chunks(Some(len as u64), [header.clone(), content, footer.clone()])?;
+    /// ```
+    ///
+    /// With their total length `len`, the chunks are taken right away and
+    /// sent with a `Content-Length`; fails with `ErrorKind::InvalidInput`
+    /// if they add up to another length. Without it, the iterator is only
+    /// run once the head is on its way, its chunks going out with
+    /// `Transfer-Encoding: chunked` as a [`stream`](Self::stream) body does.
+    pub fn body_chunks<I>(&mut self, len: Option<u64>, chunks: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Bytes>,
+        I::IntoIter: 'a,
+    {
+        let chunks = chunks.into_iter();
+        let Some(len) = len else {
+            self.stream(move |out| {
+                for chunk in chunks {
+                    io::Write::write_all(out, &chunk)?;
+                }
+                Ok(())
+            });
+            return Ok(());
+        };
+        let fragments: Vec<Bytes> = chunks.collect();
+        if fragments.iter().map(|f| f.len() as u64).sum::<u64>() != len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "chunks don't add up to the length"));
+        }
+        self.body_fragments(fragments);
+        Ok(())
+    }
+
+    // Sets a body of pieces whose length is that of them all
+    fn body_fragments(&mut self, fragments: Vec<Bytes>) {
+        self.rsp_buf.clear();
+        self.body = Body::Fragments(fragments);
+    }
+
+    /// Answers `req` with `body`, or with the parts of it asked for in a
+    /// `Range` header, so that players can seek in media held in memory:
+    /// `206 Partial Content` with a `Content-Range` for one range, a
+    /// `multipart/byteranges` body for several, and
+    /// `416 Range Not Satisfiable` when none overlaps `body`. Without a
+    /// usable `Range` header the whole body is sent, see
+    /// [`Request::byte_ranges`]. The parts are slices of `body`, none of it
+    /// is copied.
+    pub fn body_ranged(&mut self, req: &Request, content_type: &'static str, body: Bytes) {
+        let len = body.len() as u64;
+        self.header("Accept-Ranges: bytes");
+        match req.byte_ranges(len) {
+            None => {
+                self.content_type(content_type).body_shared(body);
+            }
+            Some(Err(_)) => {
+                self.status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .computed_header(CONTENT_RANGE, format!("bytes */{len}"))
+                    .body("");
+            }
+            Some(Ok(ranges)) if ranges.len() == 1 => {
+                let range = &ranges[0];
+                self.status(StatusCode::PARTIAL_CONTENT)
+                    .content_type(content_type)
+                    .computed_header(CONTENT_RANGE, content_range(range, len))
+                    .body_shared(body.slice(range.start as usize..range.end as usize));
+            }
+            Some(Ok(ranges)) => {
+                let boundary = range::boundary(len);
+                let mut parts = Vec::with_capacity(ranges.len() * 3 + 1);
+                for part in ranges {
+                    parts.push(Bytes::from(range::part_head(&boundary, content_type, &part, len)));
+                    parts.push(body.slice(part.start as usize..part.end as usize));
+                    parts.push(Bytes::from_static(b"\r\n"));
+                }
+                parts.push(Bytes::from(format!("--{boundary}--\r\n")));
+                self.headers.retain(|h| !h.is_named(&CONTENT_TYPE));
+                self.status(StatusCode::PARTIAL_CONTENT)
+                    .computed_header(CONTENT_TYPE, format!("multipart/byteranges; boundary={boundary}"))
+                    .body_fragments(parts);
+            }
+        }
+    }
+
+    /// Sends the body with `Transfer-Encoding: chunked` instead of a
+    /// `Content-Length`, for bodies whose length isn't known up front.
+    /// Every write to the returned [`ChunkWriter`] becomes one chunk, a
+    /// body set before is sent as the first one.
+    ///
+    /// HTTP/1.0 clients don't understand chunks, check
+    /// [`Request::version`](crate::Request::version) before using this.
+    ///
+    /// ```ignore
+    /// let mut chunks = rsp.chunked();
+    /// for row in rows {
+    ///     writeln!(chunks, "{},{}", row.id, row.name)?;
+    /// }
+    /// ```
+    pub fn chunked(&mut self) -> ChunkWriter<'_> {
+        let body = std::mem::replace(&mut self.body, Body::Chunked);
+        match body {
+            Body::Dummy => {
+                let written = self.rsp_buf.split();
+                push_chunk(&mut self.rsp_buf, &written);
+            }
+            Body::Str(s) => push_chunk(&mut self.rsp_buf, s.as_bytes()),
+            Body::Vec(v) => push_chunk(&mut self.rsp_buf, &v),
+            Body::Shared(b) => push_chunk(&mut self.rsp_buf, &b),
+            Body::Fragments(fragments) => {
+                for fragment in fragments {
+                    push_chunk(&mut self.rsp_buf, &fragment);
+                }
+            }
+            Body::Chunked | Body::Stream(_) | Body::File(..) => {}
+        }
+        ChunkWriter { buf: &mut self.rsp_buf }
+    }
+
+    /// Sends the body chunked like [`chunked`](Self::chunked), but has
+    /// `write` produce it once the handler has returned and the head is on
+    /// its way, so a large body such as an export or a proxied download is
+    /// never held in memory at once. The [`StreamWriter`] passes the
+    /// chunks to the connection as its buffer fills.
+    ///
+    /// A body set before is dropped. The head is already sent when `write`
+    /// runs, so if it fails the connection is closed and the client sees
+    /// the body cut short instead of an error status.
+    ///
+    /// ```ignore
+    /// rsp.stream(move |w| {
+    ///     for row in db.rows()? {
+    ///         writeln!(w, "{},{}", row.id, row.name)?;
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn stream<F>(&mut self, write: F)
+    where
+        F: FnOnce(&mut StreamWriter<'_>) -> io::Result<()> + 'a,
+    {
+        self.rsp_buf.clear();
+        self.body = Body::Stream(Box::new(write));
+    }
+
+    /// Answers `101 Switching Protocols` with `Connection: Upgrade` and the
+    /// protocol of `upgrade` in an `Upgrade` header, after which the
+    /// connection is handed to `upgrade`, see [`Upgrade`].
+    ///
+    /// A body set before is dropped; the `101` has none. Read the body of
+    /// the request before, if it has one, or what is left of it reaches the
+    /// new protocol. Setting another status afterwards cancels the upgrade.
+    pub fn upgrade(&mut self, upgrade: Upgrade) -> &mut Self {
+        self.rsp_buf.clear();
+        self.body = Body::Dummy;
+        self.server_header("Connection: Upgrade");
+        self.headers.push(Header::Pair(UPGRADE, upgrade.header_value().clone()));
+        self.upgrade = Some(upgrade);
+        self.status(StatusCode::SWITCHING_PROTOCOLS)
+    }
+
+    // The upgrade the connection switches to once this is sent, if it is
+    // still answered with a 101
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take().filter(|_| self.status_sent() == 101)
+    }
+
+    /// Answers `req` with the file at `path`, which goes from the file to
+    /// the connection after the head, with `sendfile` on Linux, instead of
+    /// being read into memory.
+    ///
+    /// The Content-Type follows the file extension and Last-Modified the
+    /// time the file was modified. A single range asked for in a `Range`
+    /// header is sent as `206 Partial Content` and ranges outside of the
+    /// file answer `416 Range Not Satisfiable`; several ranges get the
+    /// whole file. A HEAD request gets the head alone.
+    ///
+    /// Fails with the error of opening the file, such as
+    /// `ErrorKind::NotFound`, and with `ErrorKind::InvalidInput` for
+    /// anything but a regular file. A body set before is dropped.
+    ///
+    /// ```ignore
+    /// match rsp.send_file(&req, "./public/report.pdf") {
+    ///     Err(e) if e.kind() == io::ErrorKind::NotFound => {
+    ///         rsp.status(StatusCode::NOT_FOUND);
+    ///     }
+    ///     result => result?,
+    /// }
+    /// ```
+    pub fn send_file(&mut self, req: &Request, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        let len = metadata.len();
+
+        self.header("Accept-Ranges: bytes");
+        if let Ok(modified) = metadata.modified() {
+            self.computed_header(LAST_MODIFIED, httpdate::fmt_http_date(modified));
+        }
+        let range = match req.byte_ranges(len) {
+            Some(Err(_)) => {
+                self.status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .computed_header(CONTENT_RANGE, format!("bytes */{len}"));
+                0..0
+            }
+            Some(Ok(ranges)) if ranges.len() == 1 => {
+                self.status(StatusCode::PARTIAL_CONTENT)
+                    .computed_header(CONTENT_RANGE, content_range(&ranges[0], len));
+                ranges[0].clone()
+            }
+            _ => 0..len,
+        };
+        if !range.is_empty() || len == 0 {
+            self.content_type(crate::mime::mime_type(path));
+        }
+
+        self.rsp_buf.clear();
+        let body_len = range.end - range.start;
+        if req.method() == "HEAD" {
+            self.body = Body::Dummy;
+            self.head_content_length(body_len as usize);
+        } else {
+            file.seek(SeekFrom::Start(range.start))?;
+            self.body = Body::File(file, body_len);
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn body_mut(&mut self) -> &mut BytesMut {
         match &self.body {
             Body::Dummy => {}
+            // the chunks are dropped, as with any body set before
+            Body::Chunked | Body::Stream(_) | Body::File(..) => self.rsp_buf.clear(),
             Body::Str(s) => {
                 self.rsp_buf.extend_from_slice(s.as_bytes());
             }
             Body::Vec(v) => {
                 self.rsp_buf.extend_from_slice(v);
             }
+            Body::Shared(b) => {
+                self.rsp_buf.extend_from_slice(b);
+            }
+            Body::Fragments(fragments) => {
+                for fragment in fragments {
+                    self.rsp_buf.extend_from_slice(fragment);
+                }
+            }
         }
         self.body = Body::Dummy;
-        self.rsp_buf
+        &mut self.rsp_buf
     }
 
     #[inline]
     pub fn body_len(&self) -> usize {
         match &self.body {
-            Body::Dummy => self.rsp_buf.len(),
+            Body::Dummy | Body::Chunked | Body::Stream(_) => self.rsp_buf.len(),
+            Body::File(_, len) => *len as usize,
             Body::Str(s) => s.len(),
             Body::Vec(v) => v.len(),
+            Body::Shared(b) => b.len(),
+            Body::Fragments(fragments) => fragments.iter().map(Bytes::len).sum(),
         }
     }
 
     #[inline]
     pub fn get_body(&mut self) -> &[u8] {
+        if let Body::Fragments(_) = self.body {
+            // contiguous only once copied together
+            self.body_mut();
+        }
         match &self.body {
-            Body::Dummy => self.rsp_buf.as_ref(),
+            Body::Dummy | Body::Chunked | Body::Stream(_) | Body::File(..) | Body::Fragments(_) => {
+                self.rsp_buf.as_ref()
+            }
             Body::Str(s) => s.as_bytes(),
             Body::Vec(v) => v.as_ref(),
+            Body::Shared(b) => b.as_ref(),
         }
     }
 
     pub fn builder() -> ResponseBuilder {
         ResponseBuilder::new()
     }
+
+    /// The bytes the response goes out as, for tests that check exactly
+    /// what a handler wrote without starting a server:
+    ///
+    /// ```ignore
+    /// let mut rsp = Response::builder().body("");
+    /// service.call(req, &mut rsp)?;
+    /// let bytes = rsp.to_http1_bytes()?;
+    /// assert!(bytes.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    /// ```
+    ///
+    /// Streamed bodies and files are written out along with the head. The
+    /// `Connection` header the server adds for the request is missing, and
+    /// `Date` has the current time.
+    pub fn to_http1_bytes(self) -> io::Result<Vec<u8>> {
+        let mut buf = BytesMut::new();
+        let clock = self.clock.clone();
+        let deferred = match encode(self, &mut buf, false) {
+            Ok(deferred) => deferred,
+            Err(e) => {
+                encode_error(e, &GenericErrors, clock.as_deref(), &mut buf);
+                None
+            }
+        };
+        let mut out = buf.to_vec();
+        buf.clear();
+        match deferred {
+            None => {}
+            Some(Deferred::Stream(write, trailers)) => {
+                write_stream(write, trailers, &mut out, &mut buf)?;
+            }
+            Some(Deferred::File(file, len)) => {
+                let read = file.take(len).read_to_end(&mut out)?;
+                if read < len as usize {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while read"));
+                }
+            }
+            Some(Deferred::Bytes(bytes)) => {
+                for bytes in bytes {
+                    out.extend_from_slice(&bytes);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Writes the chunks of a `Transfer-Encoding: chunked` response, see
+/// [`Response::chunked`].
+pub struct ChunkWriter<'a> {
+    buf: &'a mut BytesMut,
+}
+
+impl io::Write for ChunkWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        push_chunk(self.buf, data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the body of a streamed response, see [`Response::stream`].
+/// Every write becomes one chunk; they are sent once 32 KiB are buffered,
+/// or right away on [`flush`](io::Write::flush).
+pub struct StreamWriter<'a> {
+    buf: &'a mut BytesMut,
+    out: &'a mut dyn io::Write,
+    trailers: HeaderMap,
+    // bytes sent to `out` so far
+    sent: u64,
+}
+
+impl StreamWriter<'_> {
+    /// Adds a trailer field sent after the last chunk, like
+    /// [`Response::trailer`], for values such as a checksum of what was
+    /// written. Trailers added here can't be announced in the head, set a
+    /// `Trailer` header for clients that want them listed.
+    pub fn trailer<K, V>(&mut self, name: K, value: V) -> io::Result<&mut Self>
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        let (name, value) = trailer(name, value)?;
+        self.trailers.append(name, value);
+        Ok(self)
+    }
+}
+
+impl io::Write for StreamWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        push_chunk(self.buf, data);
+        if self.buf.len() >= STREAM_FLUSH_LEN {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.write_all(self.buf)?;
+        self.sent += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+const STREAM_FLUSH_LEN: usize = 32 * 1024;
+
+/// Adds the `Access-Control-*` headers of a response shared with another
+/// origin, see [`Response::cors_allow`]. Every call replaces the header it
+/// sets; lists are joined with commas, and one that isn't a valid header
+/// value has the response answered with a `500 Internal Server Error`.
+pub struct Cors<'r, 'a> {
+    rsp: &'r mut Response<'a>,
+}
+
+impl Cors<'_, '_> {
+    /// The methods a preflight allows, `Access-Control-Allow-Methods`.
+    pub fn methods<I>(self, methods: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.list(ACCESS_CONTROL_ALLOW_METHODS, methods)
+    }
+
+    /// The request headers a preflight allows,
+    /// `Access-Control-Allow-Headers`.
+    pub fn headers<I>(self, headers: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.list(ACCESS_CONTROL_ALLOW_HEADERS, headers)
+    }
+
+    /// The response headers scripts may read besides the basic ones,
+    /// `Access-Control-Expose-Headers`.
+    pub fn expose_headers<I>(self, headers: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.list(ACCESS_CONTROL_EXPOSE_HEADERS, headers)
+    }
+
+    /// Lets the request carry cookies and credentials,
+    /// `Access-Control-Allow-Credentials`. Browsers ignore it with an
+    /// origin of `*`.
+    pub fn credentials(self) -> Self {
+        self.set(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true".into())
+    }
+
+    /// How long browsers may cache a preflight, in whole seconds,
+    /// `Access-Control-Max-Age`.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        self.set(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().to_string())
+    }
+
+    fn list<I>(self, name: HeaderName, items: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut value = String::new();
+        for item in items {
+            if !value.is_empty() {
+                value.push_str(", ");
+            }
+            value.push_str(item.as_ref());
+        }
+        self.set(name, value)
+    }
+
+    fn set(self, name: HeaderName, value: String) -> Self {
+        self.rsp.headers.retain(|h| !h.is_named(&name));
+        self.rsp.computed_header(name, value);
+        self
+    }
+}
+
+// `attachment` with a quoted `filename` of printable ASCII, and the name
+// percent-encoded in `filename*` when that had to replace characters
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut value = format!("attachment; filename=\"{fallback}\"");
+    if fallback != filename {
+        value.push_str("; filename*=UTF-8''");
+        for &b in filename.as_bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                value.push(b as char);
+            } else {
+                value.push_str(&format!("%{b:02X}"));
+            }
+        }
+    }
+    value
+}
+
+// A text media type that doesn't say how its bytes decode, which clients
+// would otherwise guess
+fn lacks_charset(content_type: &[u8]) -> bool {
+    content_type.len() > 5
+        && content_type[..5].eq_ignore_ascii_case(b"text/")
+        && !content_type.windows(8).any(|w| w.eq_ignore_ascii_case(b"charset="))
+}
+
+// Frames `data` as one chunk; an empty chunk would end the body early, so
+// it is skipped
+fn push_chunk(buf: &mut BytesMut, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    write!(buf, "{:x}\r\n", data.len()).ok();
+    buf.extend_from_slice(data);
+    buf.extend_from_slice(b"\r\n");
+}
+
+// Ends a chunked body with the last chunk and the trailer fields
+fn end_chunks(buf: &mut BytesMut, trailers: &HeaderMap) {
+    buf.extend_from_slice(b"0\r\n");
+    for (name, value) in trailers {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(b"\r\n");
+}
+
+/// Runs the writer of a streamed response, whose head `encode` left in
+/// `buf`, sending everything to `out` including the last chunk and the
+/// `trailers` set on the response. Returns the number of bytes sent, those
+/// in `buf` before included.
+pub(crate) fn write_stream(
+    write: StreamBody<'_>,
+    trailers: HeaderMap,
+    out: &mut dyn io::Write,
+    buf: &mut BytesMut,
+) -> io::Result<u64> {
+    let mut writer = StreamWriter {
+        buf,
+        out,
+        trailers,
+        sent: 0,
+    };
+    write(&mut writer)?;
+    end_chunks(writer.buf, &writer.trailers);
+    io::Write::flush(&mut writer)?;
+    Ok(writer.sent)
+}
+
+// A header that may be sent as a trailer, which excludes those framing
+// the message
+fn trailer<K, V>(name: K, value: V) -> io::Result<(HeaderName, HeaderValue)>
+where
+    K: TryInto<HeaderName>,
+    V: TryInto<HeaderValue>,
+{
+    let (name, value) = header(name, value)?;
+    if [CONTENT_LENGTH, TRANSFER_ENCODING, TRAILER].contains(&name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "field not allowed as a trailer"));
+    }
+    Ok((name, value))
+}
+
+fn header<K, V>(name: K, value: V) -> io::Result<(HeaderName, HeaderValue)>
+where
+    K: TryInto<HeaderName>,
+    V: TryInto<HeaderValue>,
+{
+    let invalid = |what| io::Error::new(io::ErrorKind::InvalidInput, what);
+    let name = name.try_into().map_err(|_| invalid("invalid header name"))?;
+    let value = value.try_into().map_err(|_| invalid("invalid header value"))?;
+    Ok((name, value))
 }
 
 impl Drop for Response<'_> {
@@ -105,8 +1270,38 @@ impl Drop for Response<'_> {
     }
 }
 
-pub(crate) fn encode(mut rsp: Response, buf: &mut BytesMut) {
-    if rsp.status_message.code == 200 {
+/// Writes `rsp` to `buf`. For a [streamed](Response::stream) body, a
+/// [file](Response::send_file) or a body too large to be worth copying only
+/// the head is written, the body is returned for the connection to send.
+///
+/// The answer to a HEAD request, `head_request`, has the length or the
+/// framing of its body but none of the bytes, whatever the handler set.
+/// A response with more headers than it may have, or with a computed header
+/// that isn't valid, fails with nothing written, to be answered by
+/// [`encode_error`].
+pub(crate) fn encode<'a>(
+    rsp: Response<'a>,
+    buf: &mut BytesMut,
+    head_request: bool,
+) -> io::Result<Option<Deferred<'a>>> {
+    if rsp.overflowed {
+        return Err(io::Error::new(io::ErrorKind::OutOfMemory, "too many response headers"));
+    }
+    if rsp.invalid_header {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid response header"));
+    }
+    Ok(encode_within_limits(rsp, buf, head_request))
+}
+
+// `encode` for a response within the header limit
+fn encode_within_limits<'a>(
+    mut rsp: Response<'a>,
+    buf: &mut BytesMut,
+    head_request: bool,
+) -> Option<Deferred<'a>> {
+    buf.extend_from_slice(&rsp.informational);
+    // the status line of the default status, written in one go
+    if rsp.status_message.code == 200 && rsp.status_message.msg == "Ok" {
         buf.extend_from_slice(b"HTTP/1.1 200 Ok\r\nServer: M\r\nDate: ");
     } else {
         buf.extend_from_slice(b"HTTP/1.1 ");
@@ -116,103 +1311,210 @@ pub(crate) fn encode(mut rsp: Response, buf: &mut BytesMut) {
         buf.extend_from_slice(rsp.status_message.msg.as_bytes());
         buf.extend_from_slice(b"\r\nServer: M\r\nDate: ");
     }
-    crate::date::append_date(buf);
-    buf.extend_from_slice(b"\r\nContent-Length: ");
-    let mut length = itoa::Buffer::new();
-    buf.extend_from_slice(length.format(rsp.body_len()).as_bytes());
+    crate::date::append_date_from(rsp.clock.as_deref(), buf);
+    // informational responses such as a 101 have no body, nor its framing
+    let informational = rsp.status_message.code < 200;
+    let chunked = !informational
+        && matches!(rsp.body, Body::Chunked | Body::Stream(_))
+        && rsp.head_length.is_none();
+    if chunked {
+        buf.extend_from_slice(b"\r\nTransfer-Encoding: chunked");
+        for (i, name) in rsp.trailers.keys().enumerate() {
+            buf.extend_from_slice(if i == 0 { b"\r\nTrailer: " } else { b", " });
+            buf.extend_from_slice(name.as_str().as_bytes());
+        }
+    } else if !informational {
+        buf.extend_from_slice(b"\r\nContent-Length: ");
+        let mut length = itoa::Buffer::new();
+        let content_length = rsp.head_length.unwrap_or_else(|| rsp.body_len());
+        buf.extend_from_slice(length.format(content_length).as_bytes());
+    }
 
-    // SAFETY: we already have bound check when insert headers
-    let headers = unsafe { rsp.headers.get_unchecked(..rsp.headers_len) };
-    for h in headers {
+    let cache_control = !rsp.cache_control.is_empty();
+    if cache_control {
+        write!(buf, "\r\nCache-Control: {}", rsp.cache_control).ok();
+    }
+    for h in &rsp.headers {
+        if cache_control && h.is_named(&CACHE_CONTROL) {
+            continue;
+        }
         buf.extend_from_slice(b"\r\n");
-        buf.extend_from_slice(h.as_bytes());
+        match h {
+            Header::Line(line) => buf.extend_from_slice(line.as_bytes()),
+            Header::Pair(name, value) => {
+                buf.extend_from_slice(name.as_str().as_bytes());
+                buf.extend_from_slice(b": ");
+                buf.extend_from_slice(value.as_bytes());
+            }
+        }
+        if let Some(charset) = rsp.charset
+            && h.is_named(&CONTENT_TYPE)
+            && lacks_charset(h.value())
+        {
+            buf.extend_from_slice(b"; charset=");
+            buf.extend_from_slice(charset.as_bytes());
+        }
     }
 
     buf.extend_from_slice(b"\r\n\r\n");
+    if head_request || rsp.head_length.is_some() || informational {
+        return None;
+    }
+    let body_len = rsp.body_len();
+    let body = match std::mem::replace(&mut rsp.body, Body::Dummy) {
+        Body::Stream(write) => return Some(Deferred::Stream(write, std::mem::take(&mut rsp.trailers))),
+        Body::File(file, len) => return Some(Deferred::File(file, len)),
+        body => body,
+    };
+    if !chunked && body_len > COPY_BODY_LEN {
+        let bytes = match body {
+            Body::Vec(v) => Bytes::from(v),
+            Body::Str(s) => Bytes::from_static(s.as_bytes()),
+            Body::Shared(b) => b,
+            Body::Fragments(fragments) => return Some(Deferred::Bytes(fragments.into())),
+            // the written body, its allocation is reused once sent
+            _ => rsp.rsp_buf.split().freeze(),
+        };
+        return Some(Deferred::Bytes(smallvec::smallvec![bytes]));
+    }
+    rsp.body = body;
     buf.extend_from_slice(rsp.get_body());
+    if chunked {
+        end_chunks(buf, &rsp.trailers);
+    }
+    None
 }
 
+/// Answers a request whose service failed with `e` as `handler` says,
+/// returning the status sent.
 #[cold]
-pub(crate) fn encode_error(e: io::Error, buf: &mut BytesMut) {
+pub(crate) fn encode_error(
+    e: io::Error,
+    handler: &dyn ErrorHandler,
+    clock: Option<&dyn Clock>,
+    buf: &mut BytesMut,
+) -> u16 {
     error!("error in service: err = {:?}", e);
-    let msg_string = e.to_string();
-    let msg = msg_string.as_bytes();
+    let rsp = handler.handle(&e);
+    let status = StatusCode::from_u16(rsp.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
 
-    buf.extend_from_slice(b"HTTP/1.1 500 Internal Server Error\r\nServer: M\r\nDate: ");
-    crate::date::append_date(buf);
+    buf.extend_from_slice(b"HTTP/1.1 ");
+    buf.extend_from_slice(status.as_str().as_bytes());
+    buf.extend_from_slice(b" ");
+    buf.extend_from_slice(status.canonical_reason().unwrap_or("Unknown").as_bytes());
+    buf.extend_from_slice(b"\r\nServer: M\r\nDate: ");
+    crate::date::append_date_from(clock, buf);
+    buf.extend_from_slice(b"\r\nContent-Type: ");
+    buf.extend_from_slice(rsp.content_type.as_bytes());
     buf.extend_from_slice(b"\r\nContent-Length: ");
     let mut length = itoa::Buffer::new();
-    buf.extend_from_slice(length.format(msg.len()).as_bytes());
+    buf.extend_from_slice(length.format(rsp.body.len()).as_bytes());
 
     buf.extend_from_slice(b"\r\n\r\n");
-    buf.extend_from_slice(msg);
+    buf.extend_from_slice(&rsp.body);
+    status.as_u16()
+}
+
+/// Answers a request the server refuses to read, such as one over the
+/// [`ServerConfig`](crate::ServerConfig) limits. The connection is closed
+/// afterwards.
+#[cold]
+pub(crate) fn encode_rejection(code: usize, msg: &str, clock: Option<&dyn Clock>, buf: &mut BytesMut) {
+    encode_refusal(code, msg, "", clock, buf);
+}
+
+/// Answers a connection over
+/// [`ServerConfig::max_connections`](crate::ServerConfig::max_connections),
+/// which is closed afterwards.
+#[cold]
+pub(crate) fn encode_overload(retry_after: Duration, clock: Option<&dyn Clock>, buf: &mut BytesMut) {
+    let header = format!("Retry-After: {}\r\n", retry_after.as_secs());
+    encode_refusal(503, "Service Unavailable", &header, clock, buf);
+}
+
+// A response without a body ahead of closing the connection, with `headers`
+// as complete lines
+fn encode_refusal(code: usize, msg: &str, headers: &str, clock: Option<&dyn Clock>, buf: &mut BytesMut) {
+    buf.extend_from_slice(b"HTTP/1.1 ");
+    let mut code_buf = itoa::Buffer::new();
+    buf.extend_from_slice(code_buf.format(code).as_bytes());
+    buf.extend_from_slice(b" ");
+    buf.extend_from_slice(msg.as_bytes());
+    buf.extend_from_slice(b"\r\nServer: M\r\nDate: ");
+    crate::date::append_date_from(clock, buf);
+    buf.extend_from_slice(b"\r\n");
+    buf.extend_from_slice(headers.as_bytes());
+    buf.extend_from_slice(b"Content-Length: 0\r\nConnection: close\r\n\r\n");
 }
 
+/// Collects a status and headers for a [`Response`]. A service applies
+/// them to the response it was given, which keeps what the server set it
+/// up with, such as its clock and header limits, and then sets the body:
+///
+/// ```ignore
+/// Response::builder()
+///     .status(StatusCode::CREATED)
+///     .header("Location", "/users/7")?
+///     .apply(rsp)
+///     .body("created");
+/// ```
+///
+/// [`body`](Self::body) builds a response apart from a connection instead,
+/// e.g. for tests.
 pub struct ResponseBuilder {
-    status: usize,
-    headers: Vec<(&'static str, &'static str)>,
-    _body: Option<Vec<u8>>,
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl ResponseBuilder {
     pub fn new() -> Self {
         ResponseBuilder {
-            status: 200,
+            status: StatusCode::OK,
             headers: Vec::new(),
-            _body: None,
         }
     }
 
-    pub fn status(mut self, code: usize) -> Self {
-        self.status = code;
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
         self
     }
 
-    pub fn header(mut self, key: &'static str, value: &'static str) -> Self {
-        self.headers.push((key, value));
-        self
+    /// Adds the header `name` with `value`, keeping those added before.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` for a name or value that isn't
+    /// valid in a header, such as one containing a line break.
+    pub fn header<K, V>(mut self, name: K, value: V) -> io::Result<Self>
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        self.headers.push(header(name, value)?);
+        Ok(self)
     }
 
+    /// Sets the status and adds the headers to `rsp`, returning it for the
+    /// body to be set.
+    pub fn apply<'r, 'buf>(self, rsp: &'r mut Response<'buf>) -> &'r mut Response<'buf> {
+        rsp.status(self.status);
+        for (name, value) in self.headers {
+            rsp.header_pair(name, value);
+        }
+        rsp
+    }
+
+    /// A response of its own with `body`, holding its own buffer, which is
+    /// freed along with it. Server settings such as
+    /// [`ServerConfig::clock`](crate::ServerConfig::clock) don't apply to
+    /// it; use [`apply`](Self::apply) on the response a service is given.
     pub fn body<T: Into<Vec<u8>>>(self, body: T) -> Response<'static> {
-        let buf = BytesMut::new();
-        let mut response = Response::new(Box::leak(Box::new(buf)));
-        response.status_code(self.status, status_code_to_message(self.status));
-        
-        for (key, value) in self.headers {
-            response.header(key);
-            response.header(value);
-        }
-        
-        response.body_vec(body.into());
+        let mut response = Response::with_buf(RspBuf::Owned(BytesMut::new()), DEFAULT_MAX_HEADERS);
+        self.apply(&mut response).body_vec(body.into());
         response
     }
 }
 
-fn status_code_to_message(code: usize) -> &'static str {
-    match code {
-        100 => "Continue",
-        101 => "Switching Protocols",
-        200 => "OK",
-        201 => "Created",
-        202 => "Accepted",
-        204 => "No Content",
-        301 => "Moved Permanently",
-        302 => "Found",
-        304 => "Not Modified",
-        400 => "Bad Request",
-        401 => "Unauthorized",
-        403 => "Forbidden",
-        404 => "Not Found",
-        405 => "Method Not Allowed",
-        409 => "Conflict",
-        410 => "Gone",
-        413 => "Payload Too Large",
-        429 => "Too Many Requests",
-        500 => "Internal Server Error",
-        501 => "Not Implemented",
-        502 => "Bad Gateway",
-        503 => "Service Unavailable",
-        504 => "Gateway Timeout",
-        _ => "Unknown Status Code",
-    }
-}
\ No newline at end of file
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
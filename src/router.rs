@@ -1,10 +1,42 @@
-use hyper::{Method, Response, StatusCode, header};
-use regex::Regex;
+use http::{Method, StatusCode, header};
+use may::coroutine::JoinHandle;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex, RegexBuilder};
 use std::any::Any;
+use std::borrow::Cow;
 use std::io::{self, Error, ErrorKind};
+use std::net::ToSocketAddrs;
+use std::cmp::Reverse;
+use std::hash::RandomState;
+use std::ops::{Deref, DerefMut, Index, Range};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
-use crate::{Request, Response as KaricsResponse}; // Import both Response types
-use crate::HttpService;
+use crate::{Request, Response};
+use crate::query::percent_decode_path;
+use crate::request::DEFAULT_BODY_LIMIT;
+use crate::{HttpServer, HttpService, Query, SecurityHeaders, ServerConfig};
+
+mod dynamic;
+mod error_format;
+mod guard;
+mod handler;
+#[doc(hidden)]
+pub mod macros;
+mod normalize;
+mod rate_limit;
+mod static_files;
+mod timeout;
+mod tree;
+
+pub use dynamic::{DynamicRouter, RouteId};
+pub use error_format::{ErrorFormatter, JsonErrors, ProblemJson};
+pub use handler::{HandlerError, IntoResponse, WithResponse, with_response};
+pub use normalize::PathNormalization;
+pub use rate_limit::RateLimit;
+use guard::Guard;
+use tree::{Node, Segment};
 
 #[derive(Debug)]
 pub enum RouterError {
@@ -12,41 +44,438 @@ pub enum RouterError {
     MethodNotAllowed(Method),
     NotFound(String),
     InvalidPattern(String),
+    InvalidStatus(u16),
+    InvalidMethod(String),
+    UnsupportedMediaType,
+    NotAcceptable,
 }
 
-#[derive(Debug, PartialEq)]
+impl RouterError {
+    /// The status the router answers this error with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RouterError::InvalidPath => StatusCode::BAD_REQUEST,
+            RouterError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            RouterError::NotFound(_) => StatusCode::NOT_FOUND,
+            RouterError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RouterError::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            RouterError::InvalidPattern(_)
+            | RouterError::InvalidStatus(_)
+            | RouterError::InvalidMethod(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MatchType {
     Exact,
     Regex,
     Prefix,
+    /// Plain paths (optionally wrapped in `^...$`) go to the route tree,
+    /// anything else is compiled as a regex
+    Auto,
+}
+
+// `{name}` anywhere, or `:name` at the start of a segment
+static PARAM_PLACEHOLDER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\{([A-Za-z_][A-Za-z0-9_]*)\}|(^|/):([A-Za-z_][A-Za-z0-9_]*)").unwrap()
+});
+
+pub type Handler =
+    Box<dyn Fn(&Request, Params, &mut Response<'_>) -> Result<(), HandlerError> + Send + Sync>;
+
+/// Code wrapped around a route handler, see [`RouteMut::middleware`].
+pub type Middleware = Arc<
+    dyn for<'n> Fn(&Request, Params, &mut Response<'_>, Next<'n>) -> Result<(), HandlerError>
+        + Send
+        + Sync,
+>;
+
+/// Turns handler errors into responses, see [`Router::error_handler`].
+pub type ErrorHandler =
+    Box<dyn Fn(&Request, HandlerError, &mut Response<'_>) -> Result<(), HandlerError> + Send + Sync>;
+
+fn boxed<F, R>(handler: F) -> Handler
+where
+    F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    Box::new(move |req, params, rsp| handler(req, params).write_to(req, rsp))
+}
+
+fn wrap_middleware<M, R>(middleware: M) -> Middleware
+where
+    M: Fn(&Request, Params, &mut Response<'_>, Next<'_>) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    Arc::new(move |req, params, rsp, next| middleware(req, params, rsp, next).write_to(req, rsp))
 }
 
-pub struct Route<ResponseBody> {
-    pattern: Regex,
+pub struct Route {
+    method: Method,
+    pattern: Arc<str>,
+    // index into `Router::endpoints` for tree routes
+    endpoint: Option<usize>,
+    // compiled pattern for regex routes, `None` for tree routes
+    regex: Option<Regex>,
     _match_type: MatchType,
-    handler: Box<dyn Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync>,
+    param_names: Arc<[Option<String>]>,
+    priority: i32,
+    guards: Vec<Guard>,
+    timeout: Option<Duration>,
+    // `(key, value)` pairs in the order they were added
+    meta: Arc<Vec<(String, String)>>,
+    // outermost first
+    middleware: Vec<Middleware>,
+    handler: Handler,
+}
+
+impl Route {
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The pattern the route was registered with, including any scope
+    /// prefix.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The first metadata value stored under `key` with
+    /// [`RouteMut::meta`].
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        meta_values(&self.meta, key).next()
+    }
+
+    /// All metadata as `(key, value)` pairs, in the order it was added.
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.meta.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Runs the route handler behind the route's middleware, which write
+    /// their answer into `rsp`.
+    pub fn call(&self, req: &Request, params: Params, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        Next {
+            middleware: &self.middleware,
+            handler: &self.handler,
+        }
+        .run(req, params, rsp)
+    }
+}
+
+fn meta_values<'m>(meta: &'m [(String, String)], key: &str) -> impl Iterator<Item = &'m str> {
+    meta.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+}
+
+/// The rest of a route's middleware chain, ending with its handler.
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    handler: &'a Handler,
+}
+
+impl Next<'_> {
+    /// Calls the next middleware, or the handler once all have run, which
+    /// write their answer into `rsp`.
+    pub fn run(self, req: &Request, params: Params, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware(
+                req,
+                params,
+                rsp,
+                Next {
+                    middleware: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => (self.handler)(req, params, rsp),
+        }
+    }
+}
+
+/// Parameters captured while matching a route.
+///
+/// Values can be looked up by position, where index `0` is the whole match
+/// and `1..` are the capture groups in pattern order, or by name for
+/// `{name}` / `:name` / `*name` path segments and `(?P<name>...)` regex
+/// groups. Prefer names: they keep working when groups are added to or
+/// removed from a pattern.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    // `None` for optional regex groups that did not take part in the match
+    values: Vec<Option<String>>,
+    names: Arc<[Option<String>]>,
+    query: Query,
+    pub(crate) pattern: Option<Arc<str>>,
+    meta: Arc<Vec<(String, String)>>,
+}
+
+impl Params {
+    /// Returns the value captured for the parameter called `name`, `None`
+    /// if there is no such parameter or its group did not match.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))
+            .and_then(|i| self.positional(i))
+    }
+
+    /// Returns the value of the capture group at `index`.
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.values.get(index).and_then(Option::as_deref)
+    }
+
+    /// Iterates over the named parameters that matched, as `(name, value)`
+    /// pairs in pattern order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((name.as_deref()?, value.as_deref()?)))
+    }
+
+    /// The parsed query string of the matched request.
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// The pattern of the matched route, such as `/users/{id}` rather than
+    /// `/users/42`, e.g. to aggregate metrics by route. `None` in the
+    /// [not found](Router::not_found) handler.
+    pub fn pattern(&self) -> Option<&str> {
+        self.pattern.as_deref()
+    }
+
+    /// The matched route's metadata values for `key`, see
+    /// [`RouteMut::meta`]; lets middleware enforce e.g. required scopes.
+    pub fn meta<'p>(&'p self, key: &'p str) -> impl Iterator<Item = &'p str> {
+        meta_values(&self.meta, key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Index<usize> for Params {
+    type Output = str;
+
+    /// Panics if `index` is out of range; a group that did not match
+    /// yields `""`.
+    fn index(&self, index: usize) -> &str {
+        self.values[index].as_deref().unwrap_or_default()
+    }
+}
+
+impl Index<&str> for Params {
+    type Output = str;
+
+    fn index(&self, name: &str) -> &str {
+        self.get(name)
+            .unwrap_or_else(|| panic!("no route parameter named `{name}`"))
+    }
+}
+
+// Rewrite `{name}` / `:name` placeholders into named regex groups
+fn expand_params(pattern: &str) -> String {
+    PARAM_PLACEHOLDER
+        .replace_all(pattern, |caps: &Captures| match caps.get(1) {
+            Some(name) => format!("(?P<{}>[^/]+)", name.as_str()),
+            None => format!("{}(?P<{}>[^/]+)", &caps[2], &caps[3]),
+        })
+        .into_owned()
+}
+
+// Methods registered by `any_method`
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::PATCH,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::TRACE,
+];
+
+// Parses an extension method such as `PURGE` or `PROPFIND`
+fn parse_method(name: &str) -> Result<Method, RouterError> {
+    Method::from_bytes(name.as_bytes()).map_err(|_| RouterError::InvalidMethod(name.to_string()))
+}
+
+// Methods a redirect route answers, HEAD is served by the GET route
+const REDIRECT_METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+// Handler answering with `status` and a `Location` built from `to`
+fn redirect_handler(to: &str, status: u16) -> Result<impl Fn(&Request, Params) -> Redirect + Clone + use<>, RouterError> {
+    let status = StatusCode::from_u16(status)
+        .ok()
+        .filter(StatusCode::is_redirection)
+        .ok_or(RouterError::InvalidStatus(status))?;
+    let to = to.to_string();
+
+    Ok(move |_: &Request, params: Params| {
+        // Fill `{name}` / `:name` placeholders from the matched parameters
+        let location = PARAM_PLACEHOLDER.replace_all(&to, |caps: &Captures| {
+            let (prefix, name) = match caps.get(1) {
+                Some(name) => ("", name.as_str()),
+                None => (&caps[2], &caps[3]),
+            };
+            match params.get(name) {
+                Some(value) => format!("{}{}", prefix, value),
+                None => caps[0].to_string(),
+            }
+        });
+        Redirect {
+            status,
+            location: location.into_owned(),
+        }
+    })
+}
+
+// The answer of a redirect route
+struct Redirect {
+    status: StatusCode,
+    location: String,
+}
+
+impl IntoResponse for Redirect {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.status(self.status).set_header(header::LOCATION, self.location)?;
+        Ok(())
+    }
+}
+
+// A pattern ready to have routes added, shared by every method on it
+#[derive(Clone)]
+struct CompiledPattern {
+    // as registered, including any scope prefix
+    pattern: Arc<str>,
+    // index into `Router::endpoints` for tree patterns
+    endpoint: Option<usize>,
+    regex: Option<Regex>,
+    match_type: MatchType,
+    param_names: Arc<[Option<String>]>,
+}
+
+// Tree segments for a pattern, or `None` if it has to be matched as a regex
+fn tree_segments<'p>(pattern: &'p str, match_type: &MatchType) -> Option<Vec<Segment<'p>>> {
+    match match_type {
+        MatchType::Regex => None,
+        MatchType::Exact => tree::parse(pattern),
+        MatchType::Prefix => {
+            let mut segments = tree::parse(pattern)?;
+            if segments.last() == Some(&Segment::Static("")) {
+                segments.pop();
+            }
+            segments.push(Segment::CatchAll(""));
+            Some(segments)
+        }
+        MatchType::Auto => {
+            let unanchored = pattern
+                .strip_prefix('^')
+                .and_then(|p| p.strip_suffix('$'))
+                .unwrap_or(pattern);
+            tree::parse(unanchored)
+        }
+    }
+}
+
+pub struct Router {
+    // every registered route, referenced by index from the matchers below
+    routes: Vec<Route>,
+    // static and parameterized paths, leading to an index into `endpoints`
+    tree: Node<usize>,
+    // routes sharing one tree path, per method
+    endpoints: Vec<HashMap<Method, Vec<usize>>>,
+    // regex routes; all route lists are kept by descending priority,
+    // then registration order
+    regex_routes: HashMap<Method, Vec<usize>>,
+    // custom handler for paths without any route
+    not_found: Option<Handler>,
+    // maps errors returned by handlers and middleware
+    error_handler: Option<ErrorHandler>,
+    // bodies of the router's own error responses
+    error_formatter: Box<dyn ErrorFormatter>,
+    // let POST requests ask for PUT, PATCH or DELETE
+    method_override: bool,
+    // match `%2F` as part of a segment instead of as a separator
+    keep_encoded_slashes: bool,
+    path_normalization: PathNormalization,
+    body_limit: usize,
+    // largest decompressed body, `None` to leave bodies as sent
+    #[cfg(feature = "decompression")]
+    decompress_limit: Option<usize>,
+    // smallest response body to compress, `None` to send bodies as they are
+    #[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+    compress_min_size: Option<usize>,
+    // added to every response `ApiService` sends
+    security_headers: SecurityHeaders,
+}
+
+// The router an `ApiService` dispatches to
+#[derive(Clone)]
+enum ServiceRouter {
+    Fixed(Arc<Router>),
+    Dynamic(Arc<DynamicRouter>),
 }
 
-pub struct Router<ResponseBody> {
-    routes: HashMap<Method, Vec<Route<ResponseBody>>>,
+impl ServiceRouter {
+    fn load(&self) -> Arc<Router> {
+        match self {
+            ServiceRouter::Fixed(router) => router.clone(),
+            ServiceRouter::Dynamic(router) => router.load(),
+        }
+    }
 }
 
+/// Serves a [`Router`], see [`HttpServer::create`].
+#[derive(Clone)]
 pub struct ApiService {
-    router: Arc<Router<Vec<u8>>>,
+    router: ServiceRouter,
     context: Arc<dyn Any + Send + Sync>  // Use Any to allow type retrieval
 }
 
 impl ApiService {
-    pub fn new(router: Arc<Router<Vec<u8>>>) -> Self {
+    pub fn new(router: Arc<Router>) -> Self {
         ApiService {
-            router: router,
+            router: ServiceRouter::Fixed(router),
             context: Arc::new(()) as Arc<dyn Any + Send + Sync>,
         }
     }
 
-    pub fn with_context<T: 'static + Send + Sync>(router: Arc<Router<Vec<u8>>>, context: Arc<T>) -> Self {
+    pub fn with_context<T: 'static + Send + Sync>(router: Arc<Router>, context: Arc<T>) -> Self {
         ApiService {
-            router,
+            router: ServiceRouter::Fixed(router),
+            context: context as Arc<dyn Any + Send + Sync>,
+        }
+    }
+
+    /// Serves a [`DynamicRouter`], each request seeing its routes at the
+    /// time the request arrives.
+    pub fn dynamic(router: Arc<DynamicRouter>) -> Self {
+        ApiService {
+            router: ServiceRouter::Dynamic(router),
+            context: Arc::new(()) as Arc<dyn Any + Send + Sync>,
+        }
+    }
+
+    pub fn dynamic_with_context<T: 'static + Send + Sync>(
+        router: Arc<DynamicRouter>,
+        context: Arc<T>,
+    ) -> Self {
+        ApiService {
+            router: ServiceRouter::Dynamic(router),
             context: context as Arc<dyn Any + Send + Sync>,
         }
     }
@@ -57,324 +486,1287 @@ impl ApiService {
     }
 }
 
+impl HttpServer<ApiService> {
+    /// Serves `router` on `addr`, the usual way to start an application:
+    ///
+    /// ```ignore
+    /// let mut router = Router::new();
+    /// router.get("/", |_, _| "Hello, World!")?;
+    /// HttpServer::create(router, "127.0.0.1:8080")?.join().unwrap();
+    /// ```
+    ///
+    /// Returns the server coroutine, see [`HttpServer::start`].
+    /// [`builder_for`](Self::builder_for) sets up a server with more
+    /// settings.
+    pub fn create<L: ToSocketAddrs>(
+        router: impl Into<Arc<Router>>,
+        addr: L,
+    ) -> io::Result<JoinHandle<()>> {
+        Self::create_with(router, addr, ServerConfig::default())
+    }
+
+    /// Like [`create`](Self::create), with the given server settings.
+    pub fn create_with<L: ToSocketAddrs>(
+        router: impl Into<Arc<Router>>,
+        addr: L,
+        config: ServerConfig,
+    ) -> io::Result<JoinHandle<()>> {
+        HttpServer(ApiService::new(router.into())).start_with(addr, config)
+    }
+}
+
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl<ResponseBody: From<Vec<u8>>> Router<ResponseBody> {
+impl Router {
     pub fn new() -> Self {
         Router {
-            routes: HashMap::with_capacity(32), // Pre-allocate space
+            routes: Vec::with_capacity(32), // Pre-allocate space
+            tree: Node::default(),
+            endpoints: Vec::new(),
+            regex_routes: HashMap::new(),
+            not_found: None,
+            error_handler: None,
+            error_formatter: Box::new(JsonErrors),
+            method_override: false,
+            keep_encoded_slashes: false,
+            path_normalization: PathNormalization::STRICT,
+            body_limit: DEFAULT_BODY_LIMIT,
+            #[cfg(feature = "decompression")]
+            decompress_limit: None,
+            #[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+            compress_min_size: None,
+            security_headers: SecurityHeaders::none()
+                .content_type_options(Some("nosniff"))
+                .frame_options(Some("DENY")),
         }
     }
 
 
 
     // Advanced route registration with method chaining
-    pub fn route<F>(
+    pub fn route<F, R>(
         &mut self,
         method: Method,
         pattern: &str,
         match_type: MatchType,
         handler: F,
-    ) -> Result<&mut Self, RouterError>
+    ) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
+        let id = self.insert_route(method, pattern, match_type, boxed(handler))?;
+        Ok(RouteMut::new(self, id..id + 1))
+    }
+
+    // Register a route and return its index in `routes`
+    fn insert_route(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        match_type: MatchType,
+        handler: Handler,
+    ) -> Result<usize, RouterError> {
+        let pattern = self.compile(pattern, match_type)?;
+        Ok(self.add_route(method, &pattern, handler))
+    }
+
+    // Prepare `pattern` for matching, adding it to the tree if it is a
+    // plain path and compiling it otherwise
+    fn compile(&mut self, pattern: &str, match_type: MatchType) -> Result<CompiledPattern, RouterError> {
+        if let Some(segments) = tree_segments(pattern, &match_type) {
+            let endpoints = &mut self.endpoints;
+            let endpoint = *self.tree.insert(&segments).get_or_insert_with(|| {
+                endpoints.push(HashMap::new());
+                endpoints.len() - 1
+            });
+
+            return Ok(CompiledPattern {
+                pattern: pattern.into(),
+                endpoint: Some(endpoint),
+                regex: None,
+                match_type,
+                param_names: std::iter::once(None)
+                    .chain(segments.iter().map(|s| s.name().map(String::from)).filter(Option::is_some))
+                    .collect(),
+            });
+        }
+
+        let expanded = expand_params(pattern);
         let regex_pattern = match match_type {
-            MatchType::Exact => format!("^{}$", pattern),
-            MatchType::Prefix => format!("^{}.*", pattern),
-            MatchType::Regex => pattern.to_string(),
+            MatchType::Exact => format!("^{}$", expanded),
+            MatchType::Prefix => format!("^{}.*", expanded),
+            MatchType::Regex | MatchType::Auto => expanded,
         };
 
-        let regex = Regex::new(&regex_pattern)
+        let regex = RegexBuilder::new(&regex_pattern)
+            .case_insensitive(self.path_normalization.ignore_case)
+            .build()
             .map_err(|_| RouterError::InvalidPattern(pattern.to_string()))?;
 
-        let route = Route {
-            pattern: regex,
-            _match_type: match_type,
-            handler: Box::new(handler),
+        Ok(CompiledPattern {
+            pattern: pattern.into(),
+            endpoint: None,
+            param_names: regex.capture_names().map(|n| n.map(String::from)).collect(),
+            regex: Some(regex),
+            match_type,
+        })
+    }
+
+
+    // Add handle method
+    /// Dispatches `req` to the matching route as `method`, which answers by
+    /// writing into `rsp`; errors are answered there too.
+    ///
+    /// The request body is only available to handlers through
+    /// [`Request::body_bytes`] if it was read with [`Request::read_body`]
+    /// beforehand, as [`ApiService`] does up to the
+    /// [body limit](Self::body_limit).
+    pub fn handle(&self, method: &Method, req: &Request, rsp: &mut Response<'_>) {
+        let (path, headers) = (req.path(), req.headers());
+        let overridden = self.override_method(method, req);
+        let method = overridden.as_ref().unwrap_or(method);
+        // HEAD falls back to the GET handler when it has no route of its
+        // own, the encoder keeps the body's length and drops its bytes
+        let matched = match self.match_route(method, path, headers) {
+            Err(e) if *method == Method::HEAD => self.match_route(&Method::GET, path, headers).map_err(|_| e),
+            matched => matched,
         };
 
-        self.routes
-            .entry(method)
-            .or_insert_with(Vec::new)
-            .push(route);
+        match matched {
+            Ok((route, params)) => {
+                req.set_params(&params);
+                let outcome = match route.timeout {
+                    Some(limit) => timeout::call(route, req, params, rsp, limit),
+                    None => timeout::Outcome::Done(route.call(req, params, rsp)),
+                };
+                match outcome {
+                    timeout::Outcome::Done(Ok(())) | timeout::Outcome::Answered => {}
+                    timeout::Outcome::Done(Err(e)) => self.handle_error(req, e, rsp),
+                    timeout::Outcome::Late => {
+                        rsp.reset();
+                        self.error_response(StatusCode::SERVICE_UNAVAILABLE, req, rsp);
+                    }
+                }
+            }
+            Err(RouterError::NotFound(_)) if self.not_found.is_some() => {
+                let (route_path, query) = match path.split_once('?') {
+                    Some((route_path, query)) => (route_path, Query::parse(query)),
+                    None => (path, Query::default()),
+                };
+                let params = Params {
+                    values: vec![Some(route_path.to_string())],
+                    names: Arc::new([None]),
+                    query,
+                    pattern: None,
+                    meta: Arc::default(),
+                };
+                req.set_params(&params);
+                let not_found = self.not_found.as_ref().unwrap();
+                if let Err(e) = not_found(req, params, rsp) {
+                    self.handle_error(req, e, rsp);
+                }
+            }
+            Err(RouterError::MethodNotAllowed(_)) => {
+                let allow = self
+                    .allowed_methods(path)
+                    .iter()
+                    .map(Method::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.error_response(StatusCode::METHOD_NOT_ALLOWED, req, rsp);
+                rsp.set_header(header::ALLOW, allow).ok();
+            }
+            Err(e) => self.error_response(e.status(), req, rsp),
+        }
+    }
 
-        Ok(self)
+    /// Answers with `status` and a body built by the router's
+    /// [`ErrorFormatter`].
+    pub fn error_response(&self, status: StatusCode, req: &Request, rsp: &mut Response<'_>) {
+        let (content_type, body) = self.error_formatter.format(status, req);
+        rsp.status(status).content_type(content_type).body_vec(body);
     }
 
+    // The method a POST asks for through `X-HTTP-Method-Override` or a
+    // `_method` form field, if overriding is enabled
+    fn override_method(&self, method: &Method, req: &Request) -> Option<Method> {
+        if !self.method_override || *method != Method::POST {
+            return None;
+        }
 
-    // Add handle method
-    pub fn handle(&self, method: &Method, path: &str) -> Result<Response<ResponseBody>, RouterError> {
-        match self.match_route(method, path) {
-            Ok((handler, params)) => Ok(handler(params)),
-            Err(e) => match e {
-                RouterError::NotFound(_) => {
-                    Ok(Response::builder()
-                        .status(StatusCode::NOT_FOUND)
-                        .body(Vec::from(r#"{"error": "Not Found"}"#).into())
-                        .unwrap())
-                },
-                RouterError::MethodNotAllowed(_) => {
-                    Ok(Response::builder()
-                        .status(StatusCode::METHOD_NOT_ALLOWED)
-                        .body(Vec::from(r#"{"error": "Method Not Allowed"}"#).into())
-                        .unwrap())
-                },
-                _ => {
-                    Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Vec::from(r#"{"error": "Internal Server Error"}"#).into())
-                        .unwrap())
+        let requested = match guard::header_value(req.headers(), "x-http-method-override") {
+            Some(value) => value.to_ascii_uppercase(),
+            None => {
+                if req.content_type()?.essence() != "application/x-www-form-urlencoded" {
+                    return None;
                 }
+                let form = Query::parse(std::str::from_utf8(req.body_bytes()).ok()?);
+                form.get("_method")?.to_ascii_uppercase()
             }
+        };
+        match requested.as_str() {
+            "PUT" => Some(Method::PUT),
+            "PATCH" => Some(Method::PATCH),
+            "DELETE" => Some(Method::DELETE),
+            _ => None,
         }
     }
 
+    // Answer with a handler error in place of what the handler wrote, an
+    // error response with the error's status (500 if it has none) unless
+    // an error handler is set
+    fn handle_error(&self, req: &Request, error: HandlerError, rsp: &mut Response<'_>) {
+        let headers = error.headers().to_vec();
+        rsp.reset();
+        match &self.error_handler {
+            Some(error_handler) => {
+                if error_handler(req, error, rsp).is_err() {
+                    rsp.reset();
+                    self.error_response(StatusCode::INTERNAL_SERVER_ERROR, req, rsp);
+                }
+            }
+            None => {
+                let status = error.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                self.error_response(status, req, rsp);
+            }
+        }
+        for (name, value) in headers {
+            rsp.set_header(name, value).ok();
+        }
+    }
 
         // Add match_route method
-        pub fn match_route(&self, method: &Method, path: &str) 
-        -> Result<(&Box<dyn Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync>, Vec<String>), RouterError> {
-        
-        let routes = self.routes.get(method)
-            .ok_or_else(|| RouterError::MethodNotAllowed(method.clone()))?;
-
-        for route in routes {
-            if let Some(captures) = route.pattern.captures(path) {
-                let mut params = Vec::new();
-                for i in 0..captures.len() {
-                    params.push(captures.get(i)
-                        .map_or("".to_string(), |m| m.as_str().to_string()));
+        pub fn match_route(&self, method: &Method, path: &str, headers: &[httparse::Header<'_>])
+        -> Result<(&Route, Params), RouterError> {
+
+        // Only the path takes part in matching, the query goes to the handler
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, Query::parse(query)),
+            None => (path, Query::default()),
+        };
+        let path = self.request_path(path).ok_or(RouterError::InvalidPath)?;
+        let path = &*path;
+
+        let mut captures = Vec::new();
+        let ignore_case = self.path_normalization.ignore_case;
+        let endpoint = self.tree.find(path, &mut captures, ignore_case).map(|&e| &self.endpoints[e]);
+        let tree_ids = endpoint.and_then(|e| e.get(method)).map_or(&[][..], Vec::as_slice);
+        let regex_ids = self.regex_routes.get(method).map_or(&[][..], Vec::as_slice);
+
+        // Merge both candidate lists by priority, the tree winning ties
+        let (mut t, mut r) = (0, 0);
+        let mut rejection = None;
+        loop {
+            let from_regex = match (tree_ids.get(t), regex_ids.get(r)) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some(&ti), Some(&ri)) => self.routes[ri].priority > self.routes[ti].priority,
+            };
+
+            let route;
+            let values = if from_regex {
+                route = &self.routes[regex_ids[r]];
+                r += 1;
+                let Some(captures) = route.regex.as_ref().and_then(|re| re.captures(path)) else {
+                    continue;
+                };
+                captures
+                    .iter()
+                    .map(|m| m.map(|m| m.as_str().to_string()))
+                    .collect()
+            } else {
+                route = &self.routes[tree_ids[t]];
+                t += 1;
+                let mut values = Vec::with_capacity(captures.len() + 1);
+                values.push(Some(path.to_string()));
+                values.extend(captures.iter().map(|c| Some(c.to_string())));
+                values
+            };
+
+            // Remember the most specific reason for skipping a route
+            if let Err(e) = route.guards.iter().try_for_each(|g| g.check(headers)) {
+                if rejection.as_ref().is_none_or(|r| guard::rank(&e) > guard::rank(r)) {
+                    rejection = Some(e);
                 }
-                return Ok((&route.handler, params));
+                continue;
             }
+
+            let params = Params {
+                values,
+                names: route.param_names.clone(),
+                query,
+                pattern: Some(route.pattern.clone()),
+                meta: route.meta.clone(),
+            };
+            return Ok((route, params));
+        }
+
+        match rejection {
+            Some(RouterError::NotFound(_)) => Err(RouterError::NotFound(path.to_string())),
+            Some(e) => Err(e),
+            None if endpoint.is_some_and(|e| !e.is_empty()) || !self.methods_for(path).is_empty() => {
+                Err(RouterError::MethodNotAllowed(method.clone()))
+            }
+            None => Err(RouterError::NotFound(path.to_string())),
         }
+    }
+
+    // The decoded and normalized path matching sees, `None` if the request
+    // has to be rejected
+    fn request_path<'p>(&self, path: &'p str) -> Option<Cow<'p, str>> {
+        let path = percent_decode_path(path, self.keep_encoded_slashes)?;
+        self.path_normalization.apply(path)
+    }
 
-        Err(RouterError::NotFound(path.to_string()))
+    /// All registered routes in registration order, e.g. to list them or
+    /// generate API docs from their [metadata](RouteMut::meta).
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    /// Returns the methods that have a route matching `path`, sorted by name.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        match self.request_path(path) {
+            Some(path) => self.methods_for(&path),
+            None => Vec::new(),
+        }
+    }
+
+    // `allowed_methods` for an already decoded path
+    fn methods_for(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self
+            .tree
+            .find(path, &mut Vec::new(), self.path_normalization.ignore_case)
+            .map(|&e| self.endpoints[e].keys().cloned().collect())
+            .unwrap_or_default();
+        for (method, ids) in &self.regex_routes {
+            let matches = ids
+                .iter()
+                .filter_map(|&id| self.routes[id].regex.as_ref())
+                .any(|r| r.is_match(path));
+            if matches && !methods.contains(method) {
+                methods.push(method.clone());
+            }
+        }
+        if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+            methods.push(Method::HEAD);
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        methods
     }
 
     // Add convenience method for GET with specific status code
-    pub fn get_with_status<F>(&mut self, pattern: &str, status: StatusCode, handler: F) 
-        -> Result<&mut Self, RouterError>
-    where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
-        ResponseBody: From<Vec<u8>>, // Add this bound
-    {
-        self.route(Method::GET, pattern, MatchType::Regex, move |params| {
-            Response::builder()
-                .status(status.clone()) // Use StatusCode directly
-                .body(handler(params).into_body())
-                .unwrap_or_else(|_| {
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Vec::from("Internal Server Error").into()) // Convert to Vec<u8> first
-                        .unwrap()
-                })
+    pub fn get_with_status<F, R>(&mut self, pattern: &str, status: StatusCode, handler: F) 
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::GET, pattern, MatchType::Auto, move |req, params| {
+            (status, handler(req, params))
         })
     }
 
     // Add method to register multiple methods for same path
-    pub fn any<F>(&mut self, methods: &[Method], pattern: &str, handler: F) 
-        -> Result<&mut Self, RouterError>
+    pub fn any<F, R>(&mut self, methods: &[Method], pattern: &str, handler: F) 
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static + Clone,
+        R: IntoResponse,
+    {
+        let ids = self.insert_any(methods, pattern, handler)?;
+        Ok(RouteMut::new(self, ids))
+    }
+
+    /// Registers `handler` for all nine standard methods, GET through TRACE.
+    pub fn any_method<F, R>(&mut self, pattern: &str, handler: F)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static + Clone,
+        R: IntoResponse,
+    {
+        self.any(&STANDARD_METHODS, pattern, handler)
+    }
+
+    /// Registers a route for a method given by name, including extension
+    /// methods like `PURGE` or WebDAV's `PROPFIND`.
+    pub fn method<F, R>(&mut self, method: &str, pattern: &str, handler: F)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let method = parse_method(method)?;
+        self.route(method, pattern, MatchType::Auto, handler)
+    }
+
+    /// Starts a resource, registering several methods on `pattern` while
+    /// parsing or compiling it only once:
+    ///
+    /// ```ignore
+    /// router.at("/users/{id}")?.get(show_user).put(update_user).delete(delete_user);
+    /// ```
+    pub fn at(&mut self, pattern: &str) -> Result<Resource<'_, Self>, RouterError> {
+        let pattern = self.compile(pattern, MatchType::Auto)?;
+        Ok(Resource {
+            registry: self,
+            pattern,
+        })
+    }
+
+    /// Redirects requests for `from` to `to` with `status`, which must be
+    /// a 3xx code such as `301` or `308`.
+    ///
+    /// `{name}` and `:name` placeholders in `to` are filled from the
+    /// parameters `from` captured:
+    ///
+    /// ```ignore
+    /// router.redirect("/old", "/new", 308)?;
+    /// router.redirect("/u/{id}", "/users/{id}", 301)?;
+    /// ```
+    pub fn redirect(&mut self, from: &str, to: &str, status: u16)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    {
+        let handler = redirect_handler(to, status)?;
+        self.any(&REDIRECT_METHODS, from, handler)
+    }
+
+    /// Serves the files below directory `dir` under `prefix`, so with
+    /// `router.static_files("/assets", "./public")` a request for
+    /// `/assets/css/site.css` gets `./public/css/site.css`.
+    ///
+    /// The Content-Type follows the file extension, and `Range` requests
+    /// are answered with the parts asked for. Missing files and
+    /// directories answer `404`. Paths never reach outside of `dir`: `..`
+    /// and `.` segments are rejected or resolved by the router's
+    /// [path normalization](Self::path_normalization), and any that remain
+    /// answer `404` as well.
+    pub fn static_files(&mut self, prefix: &str, dir: impl Into<PathBuf>)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    {
+        let root = dir.into();
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        self.get(&pattern, move |_req, params| {
+            static_files::serve(&root, params.get("path").unwrap_or_default())
+        })
+    }
+
+    fn insert_any<F, R>(&mut self, methods: &[Method], pattern: &str, handler: F)
+        -> Result<Range<usize>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static + Clone,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static + Clone,
+        R: IntoResponse,
     {
+        let start = self.routes.len();
         for method in methods {
-            self.route(method.clone(), pattern, MatchType::Regex, handler.clone())?;
+            let handler = handler.clone();
+            self.insert_route(method.clone(), pattern, MatchType::Auto, boxed(handler))?;
         }
-        Ok(self)
+        Ok(start..self.routes.len())
     }
 
     // Convenience methods for common HTTP methods
 
     // GET method registration
-    pub fn get<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn get<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::GET, pattern, MatchType::Regex, handler)
+        self.route(Method::GET, pattern, MatchType::Auto, handler)
     }
 
     // POST method registration
-    pub fn post<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn post<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::POST, pattern, MatchType::Regex, handler)
+        self.route(Method::POST, pattern, MatchType::Auto, handler)
     }
 
     // PUT method registration
-    pub fn put<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn put<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::PUT, pattern, MatchType::Regex, handler)
+        self.route(Method::PUT, pattern, MatchType::Auto, handler)
     }
     
     // DELETE method registration
-    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn delete<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::DELETE, pattern, MatchType::Regex, handler)
+        self.route(Method::DELETE, pattern, MatchType::Auto, handler)
     }
     
     // PATCH method registration
-    pub fn patch<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn patch<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where   
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::PATCH, pattern, MatchType::Regex, handler)
+        self.route(Method::PATCH, pattern, MatchType::Auto, handler)
     }
     
     // HEAD method registration
-    pub fn head<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn head<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::HEAD, pattern, MatchType::Regex, handler)
+        self.route(Method::HEAD, pattern, MatchType::Auto, handler)
     }
 
     // OPTIONS method registration
-    pub fn options<F>(&mut self, pattern: &str, handler: F) -> Result<&mut Self, RouterError>
+    pub fn options<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
     where
-        F: Fn(Vec<String>) -> Response<ResponseBody> + Send + Sync + 'static,
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
     {
-        self.route(Method::OPTIONS, pattern, MatchType::Regex, handler)
+        self.route(Method::OPTIONS, pattern, MatchType::Auto, handler)
+    }
+
+    /// Replaces the default `{"error": "Not Found"}` response for paths
+    /// that match no route.
+    ///
+    /// The handler receives the request path as parameter `0` along with
+    /// the query string.
+    pub fn not_found<F, R>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.not_found = Some(boxed(handler));
+        self
+    }
+
+    /// Lets `POST` requests be routed as `PUT`, `PATCH` or `DELETE`, for
+    /// clients such as HTML forms that can only send `GET` and `POST`.
+    ///
+    /// The method is taken from the `X-HTTP-Method-Override` header, or
+    /// else from the `_method` field of an urlencoded form body. Other
+    /// methods and other override values are ignored. Off by default.
+    pub fn method_override(&mut self, enabled: bool) -> &mut Self {
+        self.method_override = enabled;
+        self
+    }
+
+    /// Keeps `%2F` encoded while matching, so `/files/a%2Fb` matches
+    /// `/files/{name}` with `name` set to `a%2Fb`.
+    ///
+    /// Request paths are percent-decoded before matching, paths with
+    /// malformed escapes or invalid UTF-8 are answered with
+    /// `400 Bad Request`. By default `%2F` decodes to `/` like any other
+    /// escape.
+    pub fn keep_encoded_slashes(&mut self, keep: bool) -> &mut Self {
+        self.keep_encoded_slashes = keep;
+        self
+    }
+
+    /// Sets the largest request body [`ApiService`] reads for handlers,
+    /// 1 MiB by default. Larger bodies are answered with
+    /// `413 Payload Too Large` without calling a handler.
+    pub fn body_limit(&mut self, bytes: usize) -> &mut Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    /// Has [`ApiService`] decompress `gzip`, `deflate` and `br` request
+    /// bodies before handlers see them, see [`Request::decompress_body`].
+    ///
+    /// Bodies that decompress to more than `limit` bytes are answered with
+    /// `413 Payload Too Large`, other codings with
+    /// `415 Unsupported Media Type` and corrupt data with
+    /// `400 Bad Request`. The compressed body is still held to the
+    /// [body limit](Self::body_limit) first.
+    #[cfg(feature = "decompression")]
+    pub fn decompress_bodies(&mut self, limit: usize) -> &mut Self {
+        self.decompress_limit = Some(limit);
+        self
+    }
+
+    /// Has [`ApiService`] compress response bodies of at least `min_size`
+    /// bytes with the coding the client prefers in its `Accept-Encoding`
+    /// header: `br` with the `compression-br` feature, `gzip` and
+    /// `deflate` with `compression-gzip`.
+    ///
+    /// Compressed responses get a `Content-Encoding` header and a weak
+    /// `ETag`, and every response that could have been compressed gets
+    /// `Vary: Accept-Encoding`. Bodies the handler encoded itself, partial
+    /// content, answers to HEAD and media types that are compressed
+    /// already, such as images and archives, are sent as they are.
+    #[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+    pub fn compress_responses(&mut self, min_size: usize) -> &mut Self {
+        self.compress_min_size = Some(min_size);
+        self
+    }
+
+    /// The security headers [`ApiService`] adds to every response, which
+    /// the handler's headers of the same names replace.
+    /// `X-Content-Type-Options: nosniff` and `X-Frame-Options: DENY` by
+    /// default; [`SecurityHeaders::default`] adds HSTS and a
+    /// `Referrer-Policy`, [`SecurityHeaders::none`] leaves them all out.
+    pub fn security_headers(&mut self, headers: SecurityHeaders) -> &mut Self {
+        self.security_headers = headers;
+        self
+    }
+
+    /// Sets how request paths are normalized before matching,
+    /// [`PathNormalization::STRICT`] by default.
+    ///
+    /// Normalizing matters beyond convenience: a prefix route guarding
+    /// `/admin` must not be sidestepped by `//admin` or `/public/../admin`.
+    pub fn path_normalization(&mut self, mode: PathNormalization) -> &mut Self {
+        if mode.ignore_case != self.path_normalization.ignore_case {
+            for route in &mut self.routes {
+                if let Some(regex) = &route.regex {
+                    route.regex = Some(
+                        RegexBuilder::new(regex.as_str())
+                            .case_insensitive(mode.ignore_case)
+                            .build()
+                            .expect("pattern compiled before"),
+                    );
+                }
+            }
+        }
+        self.path_normalization = mode;
+        self
+    }
+
+    /// Sets how the bodies of the router's own error responses look,
+    /// [`JsonErrors`] by default.
+    ///
+    /// ```ignore
+    /// router.error_formatter(ProblemJson);
+    /// ```
+    pub fn error_formatter<F: ErrorFormatter + 'static>(&mut self, formatter: F) -> &mut Self {
+        self.error_formatter = Box::new(formatter);
+        self
+    }
+
+    /// Sets how errors returned by handlers and middleware are answered.
+    ///
+    /// Without an error handler they become an error response with the
+    /// [status](HandlerError::status) of the error, `500` if it has none.
+    /// [Headers](HandlerError::headers) of the error are added to the
+    /// response either way.
+    ///
+    /// ```ignore
+    /// router.error_handler(|_req, err| match err.downcast_ref::<ParseIntError>() {
+    ///     Some(_) => (StatusCode::BAD_REQUEST, "not a number"),
+    ///     None => (StatusCode::INTERNAL_SERVER_ERROR, "internal error"),
+    /// });
+    /// ```
+    ///
+    /// The handler writes into a fresh response, whatever the failed
+    /// handler wrote before is dropped; if it fails too, the answer is a
+    /// `500`.
+    pub fn error_handler<F, R>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, HandlerError) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.error_handler = Some(Box::new(move |req, error, rsp| handler(req, error).write_to(req, rsp)));
+        self
+    }
+
+    /// Opens a scope whose routes are all registered under `prefix`.
+    ///
+    /// ```ignore
+    /// let mut api = router.scope("/api/v1");
+    /// api.get("/users", list_users)?.post("/users", create_user)?;
+    /// ```
+    pub fn scope(&mut self, prefix: &str) -> Scope<'_> {
+        Scope {
+            router: self,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            middleware: Vec::new(),
+        }
+    }
+}
+
+/// A group of routes sharing a common path prefix, created by [`Router::scope`].
+pub struct Scope<'a> {
+    router: &'a mut Router,
+    prefix: String,
+    middleware: Vec<Middleware>,
+}
+
+impl Scope<'_> {
+    // Prefix the pattern, keeping a leading `^` anchor in front
+    fn full_pattern(&self, pattern: &str) -> String {
+        match pattern.strip_prefix('^') {
+            Some(rest) => format!("^{}{}", self.prefix, rest),
+            None => format!("{}{}", self.prefix, pattern),
+        }
+    }
+
+    pub fn route<F, R>(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        match_type: MatchType,
+        handler: F,
+    ) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let pattern = self.full_pattern(pattern);
+        let id = self.router.insert_route(method, &pattern, match_type, boxed(handler))?;
+        Ok(self.scoped(id..id + 1))
+    }
+
+    /// Opens a nested scope below this one.
+    pub fn scope(&mut self, prefix: &str) -> Scope<'_> {
+        Scope {
+            prefix: format!("{}{}", self.prefix, prefix.trim_end_matches('/')),
+            router: self.router,
+            middleware: self.middleware.clone(),
+        }
+    }
+
+    /// Wraps `middleware` around every route registered on this scope from
+    /// now on, including routes of nested scopes.
+    ///
+    /// Scope middleware runs outside of middleware added to a single route.
+    pub fn middleware<M, R>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&Request, Params, &mut Response<'_>, Next<'_>) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.middleware.push(wrap_middleware(middleware));
+        self
+    }
+
+    /// Limits each client to `requests` per `per` across all routes
+    /// registered on this scope from now on, see [`RateLimit`].
+    pub fn rate_limit(&mut self, requests: u32, per: Duration) -> &mut Self {
+        self.rate_limiter(RateLimit::new(requests, per))
+    }
+
+    /// Applies a configured [`RateLimit`] to the scope, see
+    /// [`rate_limit`](Self::rate_limit).
+    pub fn rate_limiter(&mut self, limit: RateLimit) -> &mut Self {
+        self.middleware(move |req, params, rsp, next| {
+            limit.check(req)?;
+            next.run(req, params, rsp)
+        })
+    }
+
+    // Apply the scope's middleware to freshly registered routes
+    fn scoped(&mut self, ids: Range<usize>) -> RouteMut<'_, Self> {
+        for id in ids.clone() {
+            self.route_added(id);
+        }
+        RouteMut::new(self, ids)
+    }
+
+    pub fn any<F, R>(&mut self, methods: &[Method], pattern: &str, handler: F)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static + Clone,
+        R: IntoResponse,
+    {
+        let pattern = self.full_pattern(pattern);
+        let ids = self.router.insert_any(methods, &pattern, handler)?;
+        Ok(self.scoped(ids))
+    }
+
+    /// Registers `handler` for all standard methods, see [`Router::any_method`].
+    pub fn any_method<F, R>(&mut self, pattern: &str, handler: F)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static + Clone,
+        R: IntoResponse,
+    {
+        self.any(&STANDARD_METHODS, pattern, handler)
+    }
+
+    /// Registers a route for a method given by name, see [`Router::method`].
+    pub fn method<F, R>(&mut self, method: &str, pattern: &str, handler: F)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let method = parse_method(method)?;
+        self.route(method, pattern, MatchType::Auto, handler)
+    }
+
+    /// Starts a resource below the scope prefix, see [`Router::at`].
+    pub fn at(&mut self, pattern: &str) -> Result<Resource<'_, Self>, RouterError> {
+        let pattern = self.full_pattern(pattern);
+        let pattern = self.router.compile(&pattern, MatchType::Auto)?;
+        Ok(Resource {
+            registry: self,
+            pattern,
+        })
+    }
+
+    /// Redirects `from`, below the scope prefix, to `to`; see
+    /// [`Router::redirect`]. `to` is not prefixed.
+    pub fn redirect(&mut self, from: &str, to: &str, status: u16)
+        -> Result<RouteMut<'_, Self>, RouterError>
+    {
+        let handler = redirect_handler(to, status)?;
+        self.any(&REDIRECT_METHODS, from, handler)
+    }
+
+    pub fn get<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::GET, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn post<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::POST, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn put<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::PUT, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn delete<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::DELETE, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn patch<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::PATCH, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn head<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::HEAD, pattern, MatchType::Auto, handler)
+    }
+
+    pub fn options<F, R>(&mut self, pattern: &str, handler: F) -> Result<RouteMut<'_, Self>, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::OPTIONS, pattern, MatchType::Auto, handler)
+    }
+}
+
+/// Implemented by [`Router`] and [`Scope`], the two places routes are
+/// registered on, so a [`RouteMut`] can reach the route it points to.
+pub trait RouteRegistry {
+    #[doc(hidden)]
+    fn router_mut(&mut self) -> &mut Router;
+
+    // Called for every route registered through the registry
+    #[doc(hidden)]
+    fn route_added(&mut self, _id: usize) {}
+}
+
+// Route options, applied through `RouteMut`
+impl Router {
+    // Add a route for `method` on an already compiled pattern, returning
+    // its index in `routes`
+    fn add_route(&mut self, method: Method, pattern: &CompiledPattern, handler: Handler) -> usize {
+        let id = self.routes.len();
+        let ids = match pattern.endpoint {
+            Some(endpoint) => &mut self.endpoints[endpoint],
+            None => &mut self.regex_routes,
+        };
+        ids.entry(method.clone()).or_default().push(id);
+
+        self.routes.push(Route {
+            method,
+            pattern: pattern.pattern.clone(),
+            endpoint: pattern.endpoint,
+            regex: pattern.regex.clone(),
+            _match_type: pattern.match_type.clone(),
+            param_names: pattern.param_names.clone(),
+            priority: 0,
+            guards: Vec::new(),
+            timeout: None,
+            meta: Arc::default(),
+            middleware: Vec::new(),
+            handler,
+        });
+        id
+    }
+
+    fn set_priority(&mut self, id: usize, priority: i32) {
+        self.routes[id].priority = priority;
+        let routes = &self.routes;
+        let route = &routes[id];
+        let ids = match route.endpoint {
+            Some(e) => self.endpoints[e].get_mut(&route.method),
+            None => self.regex_routes.get_mut(&route.method),
+        };
+        if let Some(ids) = ids {
+            ids.sort_by_key(|&i| (Reverse(routes[i].priority), i));
+        }
+    }
+
+    fn add_guard(&mut self, id: usize, guard: Guard) {
+        self.routes[id].guards.push(guard);
+    }
+}
+
+impl RouteRegistry for Router {
+    fn router_mut(&mut self) -> &mut Router {
+        self
+    }
+}
+
+impl RouteRegistry for Scope<'_> {
+    fn router_mut(&mut self) -> &mut Router {
+        self.router
+    }
+
+    fn route_added(&mut self, id: usize) {
+        self.router.routes[id].middleware = self.middleware.clone();
+    }
+}
+
+/// Routes on one shared pattern, created by [`Router::at`] or [`Scope::at`].
+///
+/// Each method registers a route for that HTTP method and hands the
+/// resource back, so the methods can be chained.
+pub struct Resource<'a, R: RouteRegistry> {
+    registry: &'a mut R,
+    pattern: CompiledPattern,
+}
+
+impl<R: RouteRegistry> Resource<'_, R> {
+    pub fn route<F, T>(self, method: Method, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        let id = self.registry.router_mut().add_route(method, &self.pattern, boxed(handler));
+        self.registry.route_added(id);
+        self
+    }
+
+    pub fn get<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::GET, handler)
+    }
+
+    pub fn post<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::POST, handler)
+    }
+
+    pub fn put<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::PUT, handler)
+    }
+
+    pub fn delete<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::DELETE, handler)
+    }
+
+    pub fn patch<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::PATCH, handler)
+    }
+
+    pub fn head<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::HEAD, handler)
+    }
+
+    pub fn options<F, T>(self, handler: F) -> Self
+    where
+        F: Fn(&Request, Params) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        self.route(Method::OPTIONS, handler)
+    }
+}
+
+/// Handle to the route(s) just registered, returned by the registration
+/// methods so options can be set on them.
+///
+/// It dereferences to the [`Router`] or [`Scope`] the route was registered
+/// on, so registrations can keep being chained:
+///
+/// ```ignore
+/// router
+///     .get("/users/new", new_user_form)?
+///     .get(r"^/users/(\w+)$", show_user)?
+///     .priority(-1);
+/// ```
+pub struct RouteMut<'a, R: RouteRegistry> {
+    registry: &'a mut R,
+    ids: Range<usize>,
+}
+
+impl<'a, R: RouteRegistry> RouteMut<'a, R> {
+    fn new(registry: &'a mut R, ids: Range<usize>) -> Self {
+        RouteMut { registry, ids }
+    }
+
+    /// Sets the route priority, `0` by default.
+    ///
+    /// When several routes match a request, higher priorities are tried
+    /// first. Within the route tree static segments always beat parameters,
+    /// which beat catch-alls; on equal priority a tree route beats a regex
+    /// route and regex routes keep their registration order.
+    pub fn priority(self, priority: i32) -> Self {
+        let router = self.registry.router_mut();
+        for id in self.ids.clone() {
+            router.set_priority(id, priority);
+        }
+        self
+    }
+
+    /// Only matches requests whose `Content-Type` is `mime`.
+    ///
+    /// `mime` may use `*` for the subtype, as in `"text/*"`. When no route
+    /// for the path accepts the request's content type the router answers
+    /// `415 Unsupported Media Type`.
+    pub fn consumes(self, mime: &str) -> Self {
+        self.guard(|| Guard::Consumes(mime.to_string()))
+    }
+
+    /// Only matches requests whose `Accept` header allows `mime`.
+    ///
+    /// Requests without `Accept` accept anything. When no route for the
+    /// path can produce an acceptable type the router answers
+    /// `406 Not Acceptable`.
+    pub fn produces(self, mime: &str) -> Self {
+        self.guard(|| Guard::Produces(mime.to_string()))
+    }
+
+    /// Only matches requests carrying header `name` with exactly `value`,
+    /// e.g. `.header("X-Api-Version", "2")`.
+    pub fn header(self, name: &str, value: &str) -> Self {
+        self.guard(|| Guard::Header(name.to_string(), value.to_string()))
+    }
+
+    /// Only matches requests for which `predicate` holds on the request
+    /// headers; others go on to the next matching route. Routes a share of
+    /// users to a new handler by header or cookie:
+    ///
+    /// ```ignore
+    /// router.get("/search", search_v2)?
+    ///     .when(|headers| headers.iter().any(|h| h.name.eq_ignore_ascii_case("x-beta")));
+    /// router.get("/search", search_v1)?;
+    /// ```
+    pub fn when<F>(self, predicate: F) -> Self
+    where
+        F: Fn(&[httparse::Header<'_>]) -> bool + Send + Sync + 'static,
+    {
+        let predicate: guard::Predicate = Arc::new(predicate);
+        self.guard(|| Guard::Predicate(predicate.clone()))
+    }
+
+    /// Only takes about `percent` of the requests it matches, the others
+    /// go on to the next matching route. Canaries a new handler with a
+    /// share of the traffic:
+    ///
+    /// ```ignore
+    /// router.get("/search", search_v2)?.split(10);
+    /// router.get("/search", search_v1)?;
+    /// ```
+    ///
+    /// Routes are tried by [priority](Self::priority), then in registration
+    /// order, so register the canary first or give it a higher priority.
+    /// Requests are picked at random, not per client; use
+    /// [`when`](Self::when) for sticky routing. `percent` is capped at 100.
+    pub fn split(self, percent: u8) -> Self {
+        self.guard(|| Guard::Split {
+            percent: percent.min(100),
+            requests: AtomicU64::new(0),
+            hasher: RandomState::new(),
+        })
+    }
+
+    fn guard(self, guard: impl Fn() -> Guard) -> Self {
+        let router = self.registry.router_mut();
+        for id in self.ids.clone() {
+            router.add_guard(id, guard());
+        }
+        self
+    }
+
+    /// Answers `503 Service Unavailable` when the route's middleware and
+    /// handler take longer than `timeout`.
+    ///
+    /// The handler isn't interrupted: it sees the limit as the
+    /// [`Request::deadline`] to budget its work by, and reads from the
+    /// connection, such as of the body, fail once it has passed. The 503
+    /// is sent at the deadline and the connection closed, what the handler
+    /// answers later is dropped. With responses to earlier pipelined
+    /// requests still waiting to be sent, or for an HTTP/1.0 client, the
+    /// 503 waits for the handler to return instead.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        let router = self.registry.router_mut();
+        for id in self.ids.clone() {
+            router.routes[id].timeout = Some(timeout);
+        }
+        self
+    }
+
+    /// Attaches metadata to the route, such as a description, tags or the
+    /// scopes it requires. A key may be given several values.
+    ///
+    /// Metadata does not affect routing; it can be read back through
+    /// [`Router::routes`], e.g. to generate API docs, and from
+    /// [`Params::meta`] in middleware and handlers.
+    ///
+    /// ```ignore
+    /// router.delete("/users/{id}", delete_user)?
+    ///     .meta("description", "Deletes a user")
+    ///     .meta("tag", "users")
+    ///     .meta("scope", "users:write");
+    /// ```
+    pub fn meta(self, key: &str, value: &str) -> Self {
+        let router = self.registry.router_mut();
+        for id in self.ids.clone() {
+            Arc::make_mut(&mut router.routes[id].meta).push((key.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    /// Wraps `middleware` around the route handler.
+    ///
+    /// Middleware gets the request, the route parameters, the response and
+    /// a [`Next`] to continue the chain; it can answer early instead (e.g. a
+    /// failed auth check) or add to the response on the way out, with `?`
+    /// passing on errors from [`Next::run`]. Middleware added first runs
+    /// outermost.
+    pub fn middleware<M, T>(self, middleware: M) -> Self
+    where
+        M: Fn(&Request, Params, &mut Response<'_>, Next<'_>) -> T + Send + Sync + 'static,
+        T: IntoResponse,
+    {
+        let middleware = wrap_middleware(middleware);
+        let router = self.registry.router_mut();
+        for id in self.ids.clone() {
+            router.routes[id].middleware.push(middleware.clone());
+        }
+        self
+    }
+
+    /// Limits each client to `requests` per `per` on this route, answering
+    /// `429 Too Many Requests` beyond that. Clients are told apart by IP
+    /// address, use [`rate_limiter`](Self::rate_limiter) for other keys.
+    pub fn rate_limit(self, requests: u32, per: Duration) -> Self {
+        self.rate_limiter(RateLimit::new(requests, per))
+    }
+
+    /// Applies a configured [`RateLimit`] to the route.
+    pub fn rate_limiter(self, limit: RateLimit) -> Self {
+        self.middleware(move |req, params, rsp, next| {
+            limit.check(req)?;
+            next.run(req, params, rsp)
+        })
+    }
+}
+
+impl<R: RouteRegistry> Deref for RouteMut<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.registry
     }
-    
 }
 
+impl<R: RouteRegistry> DerefMut for RouteMut<'_, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.registry
+    }
+}
 
 impl HttpService for ApiService {
-    fn call(&mut self, req: Request, rsp: &mut KaricsResponse) -> io::Result<()> {
+    fn call(&mut self, req: Request, rsp: &mut Response) -> io::Result<()> {
         // Parse method safely
         let method = Method::from_bytes(req.method().as_bytes())
             .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid method"))?;
 
-        // Route the request
-        match self.router.handle(&method, req.path()) {
-            Ok(response) => {
-                // Set status code
-                let status = response.status().as_u16() as usize;
-                rsp.status_code(status, status_code_to_message(status));
-
-                // Add standard headers
-                rsp.header("Server: Karics")
-                   .header("X-Content-Type-Options: nosniff")
-                   .header("X-Frame-Options: DENY");
-
-                // Add Content-Type if present
-                if let Some(ct) = response.headers().get(header::CONTENT_TYPE) {
-                    if let Ok(ct_str) = ct.to_str() {
-                        match ct_str {
-                            "application/json" => rsp.header("Content-Type: application/json"),
-                            "text/plain" => rsp.header("Content-Type: text/plain"),
-                            "text/html" => rsp.header("Content-Type: text/html"),
-                            // Add other common content types as needed
-                            _ => rsp.header("Content-Type: application/octet-stream")
-                        };
-                    }
-                }
-
-                // Set response body
-                rsp.body_vec(response.into_body());
-                Ok(())
+        // Handlers only see `&Request`, so read the body up front
+        let router = self.router.load();
+        let mut req = req;
+        let body = req.read_body_limited(router.body_limit).map(drop);
+        #[cfg(feature = "decompression")]
+        let body = body.and_then(|()| match router.decompress_limit {
+            Some(limit) => req.decompress_body(limit).map(drop),
+            None => Ok(()),
+        });
+        match body {
+            Ok(()) => router.handle(&method, &req, rsp),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                router.error_response(StatusCode::PAYLOAD_TOO_LARGE, &req, rsp)
             }
-
-            Err(e) => {
-                // Map router errors to responses
-                let (status, msg) = match e {
-                    RouterError::NotFound(_) => (404, "Not Found"),
-                    RouterError::MethodNotAllowed(_) => (405, "Method Not Allowed"), 
-                    _ => (500, "Internal Server Error")
-                };
-            
-                // Use static strings for error messages instead of format!
-                rsp.status_code(status, msg)
-                   .header("Content-Type: application/json");
-                
-                match status {
-                    404 => rsp.body(r#"{"error": "Not Found"}"#),
-                    405 => rsp.body(r#"{"error": "Method Not Allowed"}"#),
-                    _ => rsp.body(r#"{"error": "Internal Server Error"}"#)
-                }
-            
-                Ok(())
+            // malformed chunked framing or compressed data
+            Err(e) if e.kind() == ErrorKind::InvalidInput => {
+                router.error_response(StatusCode::BAD_REQUEST, &req, rsp)
+            }
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                router.error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, &req, rsp)
             }
+            Err(e) => return Err(e),
+        }
+        #[cfg(any(feature = "compression-gzip", feature = "compression-br"))]
+        if let Some(min_size) = router.compress_min_size {
+            let accept = crate::request::header_values(req.headers(), "accept-encoding");
+            let coding = crate::compress::negotiate(accept).filter(|_| method != Method::HEAD);
+            crate::compress::compress_response(rsp, coding, min_size)?;
         }
+
+        // Add standard headers, those the handler set itself win
+        rsp.header("Server: Karics")
+           .default_security_headers(&router.security_headers);
+        Ok(())
     }
 }
-
-
-// Helper function for status code messages
-fn status_code_to_message(code: usize) -> &'static str {
-    match code {
-        100 => "Continue",
-        101 => "Switching Protocols",
-        102 => "Processing",
-        103 => "Early Hints",
-        
-        200 => "OK",
-        201 => "Created",
-        202 => "Accepted", 
-        203 => "Non-Authoritative Information",
-        204 => "No Content",
-        205 => "Reset Content",
-        206 => "Partial Content",
-        207 => "Multi-Status",
-        208 => "Already Reported",
-        226 => "IM Used",
-        
-        300 => "Multiple Choices",
-        301 => "Moved Permanently",
-        302 => "Found",
-        303 => "See Other",
-        304 => "Not Modified",
-        305 => "Use Proxy",
-        307 => "Temporary Redirect",
-        308 => "Permanent Redirect",
-        
-        400 => "Bad Request",
-        401 => "Unauthorized",
-        402 => "Payment Required",
-        403 => "Forbidden",
-        404 => "Not Found",
-        405 => "Method Not Allowed",
-        406 => "Not Acceptable",
-        407 => "Proxy Authentication Required",
-        408 => "Request Timeout",
-        409 => "Conflict",
-        410 => "Gone",
-        411 => "Length Required",
-        412 => "Precondition Failed",
-        413 => "Payload Too Large",
-        414 => "URI Too Long",
-        415 => "Unsupported Media Type",
-        416 => "Range Not Satisfiable",
-        417 => "Expectation Failed",
-        418 => "I'm a teapot",
-        421 => "Misdirected Request",
-        422 => "Unprocessable Content",
-        423 => "Locked",
-        424 => "Failed Dependency",
-        425 => "Too Early",
-        426 => "Upgrade Required",
-        428 => "Precondition Required",
-        429 => "Too Many Requests",
-        431 => "Request Header Fields Too Large",
-        451 => "Unavailable For Legal Reasons",
-        
-        500 => "Internal Server Error",
-        501 => "Not Implemented",
-        502 => "Bad Gateway",
-        503 => "Service Unavailable",
-        504 => "Gateway Timeout",
-        505 => "HTTP Version Not Supported",
-        506 => "Variant Also Negotiates",
-        507 => "Insufficient Storage",
-        508 => "Loop Detected",
-        510 => "Not Extended",
-        511 => "Network Authentication Required",
-        
-        _ => "Unknown Status Code"
-    }
-}
\ No newline at end of file
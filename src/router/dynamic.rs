@@ -0,0 +1,145 @@
+//! a router whose routes can change while the server runs
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+use http::Method;
+
+use super::{HandlerError, IntoResponse, MatchType, Params, Router, RouterError};
+use crate::{Request, Response};
+
+type Configure = Box<dyn Fn(&mut Router) -> Result<(), RouterError> + Send + Sync>;
+
+type SharedHandler =
+    Arc<dyn Fn(&Request, Params, &mut Response<'_>) -> Result<(), HandlerError> + Send + Sync>;
+
+/// Identifies a route added to a [`DynamicRouter`], for removing it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RouteId(u64);
+
+struct DynamicRoute {
+    id: RouteId,
+    method: Method,
+    pattern: String,
+    handler: SharedHandler,
+}
+
+struct State {
+    configure: Configure,
+    routes: Vec<DynamicRoute>,
+    next_id: u64,
+}
+
+/// A [`Router`] that routes can be added to and removed from while it is
+/// serving, e.g. for plugins or webhooks configured at runtime.
+///
+/// Requests are dispatched to an immutable snapshot that is swapped
+/// atomically, so reads never take a lock. Every change builds a new
+/// snapshot from the base configuration plus the added routes, which makes
+/// changes comparatively expensive.
+///
+/// ```ignore
+/// let router = Arc::new(DynamicRouter::new(|router| {
+///     router.get("/health", health)?;
+///     Ok(())
+/// })?);
+/// let id = router.add(Method::POST, "/hooks/{name}", webhook)?;
+/// router.remove(id)?;
+/// ```
+pub struct DynamicRouter {
+    current: ArcSwap<Router>,
+    // serializes changes
+    state: Mutex<State>,
+}
+
+impl DynamicRouter {
+    /// Creates the router, with `configure` registering the routes and
+    /// settings every snapshot starts from.
+    pub fn new<F>(configure: F) -> Result<Self, RouterError>
+    where
+        F: Fn(&mut Router) -> Result<(), RouterError> + Send + Sync + 'static,
+    {
+        let state = State {
+            configure: Box::new(configure),
+            routes: Vec::new(),
+            next_id: 0,
+        };
+        Ok(DynamicRouter {
+            current: ArcSwap::from_pointee(Self::build(&state)?),
+            state: Mutex::new(state),
+        })
+    }
+
+    fn build(state: &State) -> Result<Router, RouterError> {
+        let mut router = Router::new();
+        (state.configure)(&mut router)?;
+        for route in &state.routes {
+            let handler = route.handler.clone();
+            router.insert_route(
+                route.method.clone(),
+                &route.pattern,
+                MatchType::Auto,
+                Box::new(move |req, params, rsp| handler(req, params, rsp)),
+            )?;
+        }
+        Ok(router)
+    }
+
+    /// Adds a route, serving it from the next request on.
+    ///
+    /// Nothing changes if the pattern is invalid.
+    pub fn add<F, R>(&self, method: Method, pattern: &str, handler: F) -> Result<RouteId, RouterError>
+    where
+        F: Fn(&Request, Params) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let mut state = self.state.lock().unwrap();
+        let id = RouteId(state.next_id);
+        state.routes.push(DynamicRoute {
+            id,
+            method,
+            pattern: pattern.to_string(),
+            handler: Arc::new(move |req, params, rsp| handler(req, params).write_to(req, rsp)),
+        });
+        match Self::build(&state) {
+            Ok(router) => {
+                state.next_id += 1;
+                self.current.store(Arc::new(router));
+                Ok(id)
+            }
+            Err(e) => {
+                state.routes.pop();
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes a route added with [`add`](Self::add), returning whether
+    /// it was still present.
+    pub fn remove(&self, id: RouteId) -> Result<bool, RouterError> {
+        let mut state = self.state.lock().unwrap();
+        let Some(index) = state.routes.iter().position(|route| route.id == id) else {
+            return Ok(false);
+        };
+        let route = state.routes.remove(index);
+        match Self::build(&state) {
+            Ok(router) => {
+                self.current.store(Arc::new(router));
+                Ok(true)
+            }
+            Err(e) => {
+                state.routes.insert(index, route);
+                Err(e)
+            }
+        }
+    }
+
+    /// The current snapshot of the routes.
+    pub fn load(&self) -> Arc<Router> {
+        self.current.load_full()
+    }
+
+    /// Dispatches `req` on the current snapshot, see [`Router::handle`].
+    pub fn handle(&self, method: &Method, req: &Request, rsp: &mut Response<'_>) {
+        self.current.load().handle(method, req, rsp)
+    }
+}
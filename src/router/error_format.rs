@@ -0,0 +1,58 @@
+//! bodies of the error responses the router generates itself
+use http::StatusCode;
+
+use crate::Request;
+
+/// Builds the body of the error responses the router answers with on its
+/// own: unmatched routes, rejected methods, timeouts and handler errors
+/// without an [error handler](super::Router::error_handler).
+///
+/// Closures `Fn(StatusCode, &Request) -> (&'static str, Vec<u8>)` are
+/// formatters too.
+pub trait ErrorFormatter: Send + Sync {
+    /// Returns the Content-Type and body for an error with `status`.
+    fn format(&self, status: StatusCode, req: &Request) -> (&'static str, Vec<u8>);
+}
+
+impl<F> ErrorFormatter for F
+where
+    F: Fn(StatusCode, &Request) -> (&'static str, Vec<u8>) + Send + Sync,
+{
+    fn format(&self, status: StatusCode, req: &Request) -> (&'static str, Vec<u8>) {
+        self(status, req)
+    }
+}
+
+fn reason(status: StatusCode) -> &'static str {
+    status.canonical_reason().unwrap_or("Unknown Status Code")
+}
+
+/// The default formatter, `{"error": "Not Found"}` and the like.
+pub struct JsonErrors;
+
+impl ErrorFormatter for JsonErrors {
+    fn format(&self, status: StatusCode, _req: &Request) -> (&'static str, Vec<u8>) {
+        let body = format!(r#"{{"error": "{}"}}"#, reason(status));
+        ("application/json", body.into_bytes())
+    }
+}
+
+/// RFC 7807 problem details, served as `application/problem+json`:
+///
+/// ```json
+/// {"type": "about:blank", "title": "Not Found", "status": 404, "instance": "/users/7"}
+/// ```
+pub struct ProblemJson;
+
+impl ErrorFormatter for ProblemJson {
+    fn format(&self, status: StatusCode, req: &Request) -> (&'static str, Vec<u8>) {
+        let instance = req.path().split_once('?').map_or(req.path(), |(path, _)| path);
+        let body = serde_json::json!({
+            "type": "about:blank",
+            "title": reason(status),
+            "status": status.as_u16(),
+            "instance": instance,
+        });
+        ("application/problem+json", body.to_string().into_bytes())
+    }
+}
@@ -0,0 +1,91 @@
+//! request header conditions attached to routes
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::RouterError;
+use crate::MediaType;
+
+pub(crate) type Predicate = Arc<dyn Fn(&[httparse::Header<'_>]) -> bool + Send + Sync>;
+
+pub(crate) enum Guard {
+    // Content-Type must match, 415 otherwise
+    Consumes(String),
+    // Accept must allow the type, 406 otherwise
+    Produces(String),
+    // header must be present with this exact value
+    Header(String, String),
+    // user supplied condition on the headers
+    Predicate(Predicate),
+    // lets through about `percent` of the requests
+    Split {
+        percent: u8,
+        requests: AtomicU64,
+        hasher: RandomState,
+    },
+}
+
+pub(crate) fn header_value<'h>(headers: &'h [httparse::Header<'_>], name: &str) -> Option<&'h str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .and_then(|h| std::str::from_utf8(h.value).ok())
+        .map(str::trim)
+}
+
+// `type/subtype` without parameters, compared case-insensitively;
+// either side may use `*` for the type or subtype
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match (MediaType::parse(pattern), MediaType::parse(mime)) {
+        (Some(pattern), Some(mime)) => {
+            let part = |p: &str, m: &str| p == "*" || m == "*" || p == m;
+            part(pattern.main_type(), mime.main_type()) && part(pattern.subtype(), mime.subtype())
+        }
+        _ => false,
+    }
+}
+
+// 415 beats 406, which beats a plain miss
+pub(crate) fn rank(e: &RouterError) -> u8 {
+    match e {
+        RouterError::UnsupportedMediaType => 2,
+        RouterError::NotAcceptable => 1,
+        _ => 0,
+    }
+}
+
+impl Guard {
+    /// Checks the guard, returning the error a failure maps to.
+    pub(crate) fn check(&self, headers: &[httparse::Header<'_>]) -> Result<(), RouterError> {
+        match self {
+            Guard::Consumes(mime) => match header_value(headers, "content-type") {
+                Some(ct) if mime_matches(mime, ct) => Ok(()),
+                _ => Err(RouterError::UnsupportedMediaType),
+            },
+            Guard::Produces(mime) => match header_value(headers, "accept") {
+                None => Ok(()),
+                Some(accept) if accept.split(',').any(|a| mime_matches(a, mime)) => Ok(()),
+                Some(_) => Err(RouterError::NotAcceptable),
+            },
+            Guard::Header(name, value) => match header_value(headers, name) {
+                Some(v) if v == value => Ok(()),
+                _ => Err(RouterError::NotFound(String::new())),
+            },
+            Guard::Predicate(predicate) if predicate(headers) => Ok(()),
+            Guard::Predicate(_) => Err(RouterError::NotFound(String::new())),
+            Guard::Split {
+                percent,
+                requests,
+                hasher,
+            } => {
+                // hashing the request count spreads the picks evenly
+                let n = requests.fetch_add(1, Ordering::Relaxed);
+                if hasher.hash_one(n) % 100 < u64::from(*percent) {
+                    Ok(())
+                } else {
+                    Err(RouterError::NotFound(String::new()))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! what route handlers may return, and the error type they fail with
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use http::StatusCode;
+use http::header::{self, HeaderName, HeaderValue};
+
+use super::Params;
+use crate::{Request, Response};
+
+/// The error returned by a fallible handler or middleware.
+///
+/// Any error type converts into it through `?`, and the router hands it to
+/// the error handler installed with [`Router::error_handler`], which can
+/// recover the original error with [`downcast_ref`](Self::downcast_ref).
+///
+/// [`Router::error_handler`]: super::Router::error_handler
+pub struct HandlerError {
+    error: Box<dyn Error + Send + Sync>,
+    status: Option<StatusCode>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HandlerError {
+    pub fn new<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> Self {
+        HandlerError {
+            error: error.into(),
+            status: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// An error that should be answered with `status`, e.g. `404` for a
+    /// missing record.
+    pub fn with_status<E: Into<Box<dyn Error + Send + Sync>>>(status: StatusCode, error: E) -> Self {
+        HandlerError {
+            error: error.into(),
+            status: Some(status),
+            headers: Vec::new(),
+        }
+    }
+
+    /// The status set with [`with_status`](Self::with_status).
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
+
+    /// Adds a header to the error response, e.g. `Retry-After` on a `429`.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// The headers added with [`with_header`](Self::with_header).
+    pub fn headers(&self) -> &[(HeaderName, HeaderValue)] {
+        &self.headers
+    }
+
+    /// Returns the original error if it is of type `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync> {
+        self.error
+    }
+}
+
+impl<E: Into<Box<dyn Error + Send + Sync>>> From<E> for HandlerError {
+    fn from(error: E) -> Self {
+        HandlerError::new(error)
+    }
+}
+
+impl fmt::Debug for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+/// Values a handler or middleware can answer with, written into the
+/// response the connection sends.
+///
+/// Plain values become a `200 OK` with a matching Content-Type:
+///
+/// | type                | Content-Type                |
+/// |---------------------|-----------------------------|
+/// | `String`, `&str`    | `text/plain; charset=utf-8` |
+/// | `Vec<u8>`           | `application/octet-stream`  |
+/// | `serde_json::Value` | `application/json`          |
+///
+/// `()` leaves the response as it is, for middleware that passed the
+/// request on. `(StatusCode, T)` answers `T` with another status,
+/// `Result<T, E>` fails with `E` if it converts into a [`HandlerError`],
+/// and an `http::Response` has its status, headers and body copied over.
+///
+/// The request is at hand for answers that depend on it, such as the
+/// ranges it asks for. Handlers that need the response itself go through
+/// [`with_response`].
+pub trait IntoResponse {
+    fn write_to(self, req: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError>;
+}
+
+impl IntoResponse for () {
+    fn write_to(self, _: &Request, _: &mut Response<'_>) -> Result<(), HandlerError> {
+        Ok(())
+    }
+}
+
+impl IntoResponse for String {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.content_type("text/plain; charset=utf-8").body_vec(self.into_bytes());
+        Ok(())
+    }
+}
+
+impl IntoResponse for &str {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.content_type("text/plain; charset=utf-8").body_vec(self.as_bytes().to_vec());
+        Ok(())
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.content_type("application/octet-stream").body_vec(self);
+        Ok(())
+    }
+}
+
+impl IntoResponse for serde_json::Value {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.content_type("application/json").body_vec(self.to_string().into_bytes());
+        Ok(())
+    }
+}
+
+impl<B: Into<Vec<u8>>> IntoResponse for http::Response<B> {
+    fn write_to(self, _: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        let (parts, body) = self.into_parts();
+        rsp.status(parts.status);
+        // Repeated values of a header come without a name; the length is
+        // set by the encoder
+        let mut current = None;
+        for (name, value) in parts.headers {
+            if name.is_some() {
+                current = name;
+            }
+            match &current {
+                Some(name) if *name == header::CONTENT_LENGTH => {}
+                Some(name) => {
+                    rsp.append_header(name.clone(), value)?;
+                }
+                None => {}
+            }
+        }
+        rsp.body_vec(body.into());
+        Ok(())
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn write_to(self, req: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        self.1.write_to(req, rsp)?;
+        rsp.status(self.0);
+        Ok(())
+    }
+}
+
+impl<T, E> IntoResponse for Result<T, E>
+where
+    T: IntoResponse,
+    E: Into<HandlerError>,
+{
+    fn write_to(self, req: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        self.map_err(Into::into)?.write_to(req, rsp)
+    }
+}
+
+/// Adapts a handler that writes into the connection's [`Response`] itself,
+/// for answers a returned value can't express, such as
+/// [`stream`](Response::stream), [`send_file`](Response::send_file),
+/// [`early_hints`](Response::early_hints) or
+/// [`body_shared`](Response::body_shared). What the handler returns is
+/// written after it, so `()` or `io::Result<()>` keep its response as it
+/// is.
+///
+/// ```ignore
+/// router.get("/report", with_response(|req, _, rsp| rsp.send_file(req, "./report.pdf")))?;
+/// ```
+pub fn with_response<F, R>(handler: F) -> impl Fn(&Request, Params) -> WithResponse<F> + Clone + Send + Sync + 'static
+where
+    F: Fn(&Request, Params, &mut Response<'_>) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    let handler = Arc::new(handler);
+    move |_, params| WithResponse {
+        handler: handler.clone(),
+        params,
+    }
+}
+
+/// A call of a handler adapted by [`with_response`], made once the
+/// response is at hand.
+pub struct WithResponse<F> {
+    handler: Arc<F>,
+    params: Params,
+}
+
+impl<F, R> IntoResponse for WithResponse<F>
+where
+    F: Fn(&Request, Params, &mut Response<'_>) -> R,
+    R: IntoResponse,
+{
+    fn write_to(self, req: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        (self.handler)(req, self.params, rsp).write_to(req, rsp)
+    }
+}
@@ -0,0 +1,253 @@
+//! the `routes!` macro and the compile-time checks behind it
+pub use http::Method;
+
+/// Builds a [`Router`](crate::Router) from a table of routes, checking the
+/// table while compiling:
+///
+/// ```ignore
+/// let router: Router = routes! {
+///     GET "/users" => list_users,
+///     POST "/users" => create_user,
+///     GET "/users/{id}" => show_user,
+///     DELETE "/users/:id" => delete_user,
+///     GET "/assets/*path" => assets,
+/// };
+/// ```
+///
+/// Patterns must be plain paths made of literal segments, `{name}` or
+/// `:name` parameters and a trailing `*name` catch-all, so every route
+/// lives in the route tree and no regex is compiled at startup. A pattern
+/// that is not a plain path, repeats a parameter name or is registered
+/// twice for the same method is a compile error; `/users/{id}` and
+/// `/users/:user` count as the same pattern.
+///
+/// Methods are the constants of `http::Method`, `GET` through `TRACE`.
+#[macro_export]
+macro_rules! routes {
+    ($($method:ident $pattern:literal => $handler:expr),* $(,)?) => {{
+        const ROUTES: &[(&str, &str)] = &[$((stringify!($method), $pattern)),*];
+        $(
+            const _: () = assert!(
+                $crate::router::macros::is_tree_pattern($pattern),
+                "{}",
+                concat!(
+                    "routes!: `", $pattern, "` is not a plain path, ",
+                    "use literal segments, `{name}`, `:name` and a trailing `*name`"
+                ),
+            );
+            const _: () = assert!(
+                $crate::router::macros::has_unique_params($pattern),
+                "{}",
+                concat!("routes!: `", $pattern, "` uses a parameter name twice"),
+            );
+            const _: () = assert!(
+                $crate::router::macros::count(ROUTES, stringify!($method), $pattern) <= 1,
+                "{}",
+                concat!("routes!: ", stringify!($method), " `", $pattern, "` is registered more than once"),
+            );
+        )*
+        let mut router = $crate::Router::new();
+        $(
+            router
+                .route(
+                    $crate::router::macros::Method::$method,
+                    $pattern,
+                    $crate::router::MatchType::Auto,
+                    $handler,
+                )
+                .expect("routes! patterns are checked at compile time");
+        )*
+        router
+    }};
+}
+
+#[derive(Clone, Copy)]
+enum Segment {
+    Static,
+    // name between the two offsets
+    Param(usize, usize),
+    CatchAll(usize, usize),
+    Invalid,
+}
+
+// `pattern` without `^...$` anchors, the way `MatchType::Auto` sees it
+const fn bounds(pattern: &[u8]) -> (usize, usize) {
+    let n = pattern.len();
+    if n >= 2 && pattern[0] == b'^' && pattern[n - 1] == b'$' {
+        (1, n - 1)
+    } else {
+        (0, n)
+    }
+}
+
+const fn segment_end(pattern: &[u8], mut i: usize, end: usize) -> usize {
+    while i < end && pattern[i] != b'/' {
+        i += 1;
+    }
+    i
+}
+
+const fn is_ident(pattern: &[u8], start: usize, end: usize) -> bool {
+    if start >= end || !(pattern[start] == b'_' || pattern[start].is_ascii_alphabetic()) {
+        return false;
+    }
+    let mut i = start + 1;
+    while i < end {
+        if !(pattern[i] == b'_' || pattern[i].is_ascii_alphanumeric()) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn is_literal(pattern: &[u8], start: usize, end: usize) -> bool {
+    let mut i = start;
+    while i < end {
+        if matches!(
+            pattern[i],
+            b'\\' | b'+' | b'*' | b'?' | b'(' | b')' | b'|' | b'[' | b']' | b'{' | b'}' | b'^' | b'$'
+        ) {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Same rules as `tree::parse`
+const fn classify(pattern: &[u8], start: usize, end: usize, last: bool) -> Segment {
+    if start < end && pattern[start] == b'{' {
+        if end - start >= 2 && pattern[end - 1] == b'}' && is_ident(pattern, start + 1, end - 1) {
+            return Segment::Param(start + 1, end - 1);
+        }
+        return Segment::Invalid;
+    }
+    if start < end && pattern[start] == b':' {
+        if is_ident(pattern, start + 1, end) {
+            return Segment::Param(start + 1, end);
+        }
+        return Segment::Invalid;
+    }
+    if start < end && pattern[start] == b'*' {
+        if last && (start + 1 == end || is_ident(pattern, start + 1, end)) {
+            return Segment::CatchAll(start + 1, end);
+        }
+        return Segment::Invalid;
+    }
+    if is_literal(pattern, start, end) {
+        Segment::Static
+    } else {
+        Segment::Invalid
+    }
+}
+
+const fn bytes_eq(a: &[u8], a_start: usize, a_end: usize, b: &[u8], b_start: usize, b_end: usize) -> bool {
+    if a_end - a_start != b_end - b_start {
+        return false;
+    }
+    let mut i = 0;
+    while i < a_end - a_start {
+        if a[a_start + i] != b[b_start + i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether `pattern` goes to the route tree rather than the regex matcher.
+pub const fn is_tree_pattern(pattern: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let (start, end) = bounds(pattern);
+    if start >= end || pattern[start] != b'/' {
+        return false;
+    }
+    let mut i = start + 1;
+    loop {
+        let seg_end = segment_end(pattern, i, end);
+        if matches!(classify(pattern, i, seg_end, seg_end == end), Segment::Invalid) {
+            return false;
+        }
+        if seg_end == end {
+            return true;
+        }
+        i = seg_end + 1;
+    }
+}
+
+// the name of the parameter or catch-all in the segment at `i`
+const fn param_name(pattern: &[u8], i: usize, end: usize) -> Option<(usize, usize)> {
+    let seg_end = segment_end(pattern, i, end);
+    match classify(pattern, i, seg_end, seg_end == end) {
+        Segment::Param(start, end) | Segment::CatchAll(start, end) if start < end => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Whether every parameter name occurs only once in `pattern`.
+pub const fn has_unique_params(pattern: &str) -> bool {
+    if !is_tree_pattern(pattern) {
+        return true;
+    }
+    let pattern = pattern.as_bytes();
+    let (start, end) = bounds(pattern);
+    let mut i = start + 1;
+    while i <= end {
+        if let Some((a_start, a_end)) = param_name(pattern, i, end) {
+            let mut j = segment_end(pattern, i, end) + 1;
+            while j <= end {
+                if let Some((b_start, b_end)) = param_name(pattern, j, end)
+                    && bytes_eq(pattern, a_start, a_end, pattern, b_start, b_end)
+                {
+                    return false;
+                }
+                j = segment_end(pattern, j, end) + 1;
+            }
+        }
+        i = segment_end(pattern, i, end) + 1;
+    }
+    true
+}
+
+// Whether both patterns end up on the same tree node
+const fn same_shape(a: &str, b: &str) -> bool {
+    if !is_tree_pattern(a) || !is_tree_pattern(b) {
+        return false;
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let ((a_start, a_end), (b_start, b_end)) = (bounds(a), bounds(b));
+    // skip the leading `/`
+    let (mut i, mut j) = (a_start + 1, b_start + 1);
+    loop {
+        let (a_seg, b_seg) = (segment_end(a, i, a_end), segment_end(b, j, b_end));
+        let same = match (classify(a, i, a_seg, a_seg == a_end), classify(b, j, b_seg, b_seg == b_end)) {
+            (Segment::Static, Segment::Static) => bytes_eq(a, i, a_seg, b, j, b_seg),
+            (Segment::Param(..), Segment::Param(..)) | (Segment::CatchAll(..), Segment::CatchAll(..)) => true,
+            _ => false,
+        };
+        if !same {
+            return false;
+        }
+        match (a_seg == a_end, b_seg == b_end) {
+            (true, true) => return true,
+            (false, false) => (i, j) = (a_seg + 1, b_seg + 1),
+            _ => return false,
+        }
+    }
+}
+
+/// How many routes in `routes` are registered for `method` on a pattern
+/// matching the same paths as `pattern`.
+pub const fn count(routes: &[(&str, &str)], method: &str, pattern: &str) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while i < routes.len() {
+        let (m, p) = routes[i];
+        if bytes_eq(m.as_bytes(), 0, m.len(), method.as_bytes(), 0, method.len()) && same_shape(p, pattern) {
+            n += 1;
+        }
+        i += 1;
+    }
+    n
+}
@@ -0,0 +1,71 @@
+//! how request paths are cleaned up before matching
+use std::borrow::Cow;
+
+/// Controls how request paths are normalized before matching, see
+/// [`Router::path_normalization`](super::Router::path_normalization).
+///
+/// Normalization runs after percent-decoding, so `/%2E%2E/admin` is
+/// treated like `/../admin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PathNormalization {
+    /// Matches literal path segments and regex routes ignoring ASCII case.
+    pub ignore_case: bool,
+    /// Treats runs of slashes as one, `//users` as `/users`.
+    pub merge_slashes: bool,
+    /// Resolves `.` and `..` segments, `/a/../b` as `/b`. Otherwise
+    /// paths containing them are answered with `400 Bad Request`.
+    pub resolve_dot_segments: bool,
+}
+
+impl PathNormalization {
+    /// Paths are matched as sent, the default.
+    pub const STRICT: PathNormalization = PathNormalization {
+        ignore_case: false,
+        merge_slashes: false,
+        resolve_dot_segments: false,
+    };
+
+    /// Case-insensitive matching, merged slashes and resolved dot segments.
+    pub const LENIENT: PathNormalization = PathNormalization {
+        ignore_case: true,
+        merge_slashes: true,
+        resolve_dot_segments: true,
+    };
+
+    /// Normalizes a decoded path, `None` if it has to be rejected.
+    pub(crate) fn apply<'p>(&self, path: Cow<'p, str>) -> Option<Cow<'p, str>> {
+        // `*` and absolute URIs are left alone
+        if !path.starts_with('/') {
+            return Some(path);
+        }
+        let has_dots = path.split('/').any(|s| s == "." || s == "..");
+        if has_dots && !self.resolve_dot_segments {
+            return None;
+        }
+        let merge = self.merge_slashes && path.contains("//");
+        if !has_dots && !merge {
+            return Some(path);
+        }
+
+        let mut segments: Vec<&str> = Vec::new();
+        let mut parts = path.split('/').skip(1).peekable();
+        while let Some(segment) = parts.next() {
+            match segment {
+                // a trailing `.` or `..` still names a directory
+                "." | ".." if parts.peek().is_none() => {
+                    if segment == ".." {
+                        segments.pop();
+                    }
+                    segments.push("");
+                }
+                "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                "" if self.merge_slashes && parts.peek().is_some() => {}
+                _ => segments.push(segment),
+            }
+        }
+        Some(Cow::Owned(format!("/{}", segments.join("/"))))
+    }
+}
@@ -0,0 +1,114 @@
+//! limiting how often clients may call a route
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http::StatusCode;
+use http::header::{HeaderValue, RETRY_AFTER};
+
+use super::HandlerError;
+use super::guard::header_value;
+use crate::Request;
+
+type KeyFn = Box<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+// requests seen from one client in the current window
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Allows each client a number of requests per time window, answering
+/// `429 Too Many Requests` with `Retry-After` beyond that.
+///
+/// Clients are told apart by their [IP address](crate::Request::client_ip)
+/// unless another key is set with [`by_header`](Self::by_header) or
+/// [`by_key`](Self::by_key).
+/// Requests without such a key fall back to the IP address.
+///
+/// ```ignore
+/// router.post("/login", login)?.rate_limiter(RateLimit::new(5, Duration::from_secs(60)));
+/// router.scope("/api").rate_limiter(RateLimit::new(100, Duration::from_secs(1)).by_header("x-api-key"));
+/// ```
+pub struct RateLimit {
+    requests: u32,
+    per: Duration,
+    key: Option<KeyFn>,
+    state: Mutex<State>,
+}
+
+struct State {
+    windows: HashMap<String, Window>,
+    last_sweep: Instant,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, per: Duration) -> Self {
+        RateLimit {
+            requests,
+            per,
+            key: None,
+            state: Mutex::new(State {
+                windows: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Tells clients apart by the value of a request header, such as an
+    /// API key or the `Authorization` token.
+    pub fn by_header(self, name: &str) -> Self {
+        let name = name.to_string();
+        self.by_key(move |req| header_value(req.headers(), &name).map(str::to_string))
+    }
+
+    /// Tells clients apart by a key computed from the request.
+    pub fn by_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    fn key(&self, req: &Request) -> String {
+        self.key
+            .as_ref()
+            .and_then(|key| key(req))
+            .or_else(|| req.client_ip().map(|ip| ip.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Counts the request, failing with a `429` once the client is over
+    /// its limit.
+    pub(crate) fn check(&self, req: &Request) -> Result<(), HandlerError> {
+        let key = self.key(req);
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // forget clients whose window has run out
+        if now.duration_since(state.last_sweep) >= self.per {
+            let per = self.per;
+            state.windows.retain(|_, w| now.duration_since(w.start) < per);
+            state.last_sweep = now;
+        }
+
+        let window = state.windows.entry(key).or_insert(Window { start: now, count: 0 });
+        let elapsed = now.duration_since(window.start);
+        if elapsed >= self.per {
+            *window = Window { start: now, count: 0 };
+        }
+        if window.count < self.requests {
+            window.count += 1;
+            return Ok(());
+        }
+
+        // whole seconds until the window resets, at least one
+        let wait = self.per - now.duration_since(window.start);
+        let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        Err(
+            HandlerError::with_status(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded")
+                .with_header(RETRY_AFTER, HeaderValue::from(secs.max(1))),
+        )
+    }
+}
@@ -0,0 +1,54 @@
+//! serving files from a directory, see `Router::static_files`
+use std::io::ErrorKind;
+use std::path::Path;
+
+use http::StatusCode;
+
+use super::{HandlerError, IntoResponse};
+use crate::{Request, Response, mime};
+
+// Only plain names are allowed, so the path can't leave the root
+fn is_safe_segment(segment: &str) -> bool {
+    !matches!(segment, "" | "." | "..") && !segment.contains(['\\', ':', '\0'])
+}
+
+fn not_found() -> HandlerError {
+    HandlerError::with_status(StatusCode::NOT_FOUND, "file not found")
+}
+
+// A file read into memory, sent as the ranges of it the request asks for
+pub(crate) struct File {
+    content_type: &'static str,
+    contents: Vec<u8>,
+}
+
+impl IntoResponse for File {
+    fn write_to(self, req: &Request, rsp: &mut Response<'_>) -> Result<(), HandlerError> {
+        rsp.body_ranged(req, self.content_type, self.contents.into());
+        Ok(())
+    }
+}
+
+/// Reads the file at `path` below `root`, to be answered with the ranges of
+/// it the request asks for.
+pub(crate) fn serve(root: &Path, path: &str) -> Result<File, HandlerError> {
+    if !path.split('/').all(is_safe_segment) {
+        return Err(not_found());
+    }
+
+    let file = root.join(path);
+    if file.is_dir() {
+        return Err(not_found());
+    }
+    match std::fs::read(&file) {
+        Ok(contents) => Ok(File {
+            content_type: mime::mime_type(&file),
+            contents,
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(not_found()),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            Err(HandlerError::with_status(StatusCode::FORBIDDEN, e))
+        }
+        Err(e) => Err(HandlerError::new(e)),
+    }
+}
@@ -0,0 +1,112 @@
+//! running a route handler under a time limit
+use std::io::Write;
+use std::net::Shutdown;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use may::coroutine::{self, JoinHandle};
+use may::go;
+
+use super::{HandlerError, Params, Route};
+use crate::{Request, Response, response};
+
+/// How a handler run under a time limit ended.
+pub(crate) enum Outcome {
+    /// It finished in time.
+    Done(Result<(), HandlerError>),
+    /// It missed the deadline, its answer is to be replaced by a 503.
+    Late,
+    /// The 503 was sent at the deadline and the connection closed, what
+    /// the handler answered is dropped.
+    Answered,
+}
+
+/// Runs `route` with `timeout` as the deadline of the request.
+///
+/// The handler runs on the calling coroutine and sees the deadline through
+/// [`Request::deadline`]; reads from the connection, such as of the body,
+/// fail once it has passed. A handler busy with work of its own can't be
+/// interrupted, so when nothing is buffered ahead of the response a
+/// watchdog coroutine sends the 503 at the deadline and closes the
+/// connection, and the handler's late answer is dropped. Otherwise the
+/// handler's answer is replaced once it returns.
+pub(crate) fn call(
+    route: &Route,
+    req: &Request,
+    params: Params,
+    rsp: &mut Response<'_>,
+    timeout: Duration,
+) -> Outcome {
+    // a timeout too large for an `Instant` means no limit
+    let Some(deadline) = Instant::now().checked_add(timeout) else {
+        return Outcome::Done(route.call(req, params, rsp));
+    };
+    req.limit(deadline);
+    let stream = req.stream();
+    let read_timeout = stream.read_timeout().ok().flatten();
+    // reads end with the request's deadline, which may be earlier still
+    let left = req.time_left().unwrap_or(timeout).max(Duration::from_millis(1));
+    let limited = read_timeout.map_or(left, |read_timeout| read_timeout.min(left));
+    let limited = stream.set_read_timeout(Some(limited)).is_ok();
+
+    let watchdog = watch(rsp, deadline);
+    let result = route.call(req, params, rsp);
+
+    if limited {
+        stream.set_read_timeout(read_timeout).ok();
+    }
+    match watchdog {
+        Some(watchdog) if watchdog.settle() => {
+            rsp.sent_early();
+            Outcome::Answered
+        }
+        _ if Instant::now() < deadline => Outcome::Done(result),
+        _ => Outcome::Late,
+    }
+}
+
+// A coroutine sending the 503 at the deadline unless the handler settled
+// the request before
+struct Watchdog {
+    settled: Arc<AtomicBool>,
+    coroutine: JoinHandle<()>,
+}
+
+impl Watchdog {
+    // Settles the request for the handler, true if the watchdog answered
+    // it already
+    fn settle(&self) -> bool {
+        if self.settled.swap(true, Ordering::AcqRel) {
+            return true;
+        }
+        if !self.coroutine.is_done() {
+            // SAFETY: the watchdog only sleeps until it sees `settled`,
+            // it holds no locks and nothing waits for it
+            unsafe { self.coroutine.coroutine().cancel() };
+        }
+        false
+    }
+}
+
+// Starts a watchdog for the response, `None` if responses to earlier
+// requests are buffered ahead of it, which a 503 written now would overtake
+fn watch(rsp: &Response<'_>, deadline: Instant) -> Option<Watchdog> {
+    let mut stream = rsp.direct_stream()?.try_clone().ok()?;
+    let clock = rsp.clock_handle();
+    let settled = Arc::new(AtomicBool::new(false));
+    let watched = settled.clone();
+    let coroutine = go!(move || {
+        coroutine::sleep(deadline.saturating_duration_since(Instant::now()));
+        if watched.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let mut buf = BytesMut::new();
+        response::encode_rejection(503, "Service Unavailable", clock.as_deref(), &mut buf);
+        stream.write_all(&buf).ok();
+        // the handler's reads and writes fail from now on
+        stream.shutdown(Shutdown::Both).ok();
+    });
+    Some(Watchdog { settled, coroutine })
+}
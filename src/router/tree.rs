@@ -0,0 +1,149 @@
+//! segment trie used by the router for static and parameterized paths
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str),
+    CatchAll(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    pub(crate) fn name(&self) -> Option<&'a str> {
+        match *self {
+            Segment::Static(_) => None,
+            Segment::Param(name) | Segment::CatchAll(name) => Some(name),
+        }
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+// `.` is accepted as a literal so paths like `/favicon.ico` stay in the tree
+fn is_literal(s: &str) -> bool {
+    !s.contains(['\\', '+', '*', '?', '(', ')', '|', '[', ']', '{', '}', '^', '$'])
+}
+
+/// Splits `pattern` into trie segments.
+///
+/// Returns `None` when the pattern is not a plain path, i.e. it needs the
+/// regex matcher. Recognised placeholders are `{name}` and `:name` for a
+/// single segment and a trailing `*name` for the rest of the path.
+pub(crate) fn parse(pattern: &str) -> Option<Vec<Segment<'_>>> {
+    let rest = pattern.strip_prefix('/')?;
+    let parts: Vec<&str> = rest.split('/').collect();
+    let last = parts.len() - 1;
+
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+                is_ident(name).then_some(Segment::Param(name))
+            } else if let Some(name) = part.strip_prefix(':') {
+                is_ident(name).then_some(Segment::Param(name))
+            } else if let Some(name) = part.strip_prefix('*') {
+                (i == last && (name.is_empty() || is_ident(name))).then_some(Segment::CatchAll(name))
+            } else {
+                is_literal(part).then_some(Segment::Static(part))
+            }
+        })
+        .collect()
+}
+
+pub(crate) struct Node<T> {
+    value: Option<T>,
+    statics: HashMap<Box<str>, Node<T>>,
+    param: Option<Box<Node<T>>>,
+    catch_all: Option<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            value: None,
+            statics: HashMap::new(),
+            param: None,
+            catch_all: None,
+        }
+    }
+}
+
+impl<T> Node<T> {
+    /// Returns the slot for the route described by `segments`, creating
+    /// intermediate nodes as needed.
+    pub(crate) fn insert(&mut self, segments: &[Segment<'_>]) -> &mut Option<T> {
+        match segments.split_first() {
+            None => &mut self.value,
+            Some((Segment::Static(s), rest)) => {
+                self.statics.entry((*s).into()).or_default().insert(rest)
+            }
+            Some((Segment::Param(_), rest)) => {
+                self.param.get_or_insert_with(Default::default).insert(rest)
+            }
+            Some((Segment::CatchAll(_), _)) => &mut self.catch_all,
+        }
+    }
+
+    /// Looks up `path`, pushing the value of every placeholder onto `captures`.
+    ///
+    /// Static segments win over parameters, which win over catch-alls. With
+    /// `ignore_case` static segments also match when they differ in ASCII
+    /// case only, exact matches being tried first.
+    pub(crate) fn find<'p>(&self, path: &'p str, captures: &mut Vec<&'p str>, ignore_case: bool) -> Option<&T> {
+        self.find_from(path.strip_prefix('/'), captures, ignore_case)
+    }
+
+    fn find_from<'p>(&self, rest: Option<&'p str>, captures: &mut Vec<&'p str>, ignore_case: bool) -> Option<&T> {
+        let Some(rest) = rest else {
+            if self.value.is_none() && self.catch_all.is_some() {
+                captures.push("");
+                return self.catch_all.as_ref();
+            }
+            return self.value.as_ref();
+        };
+
+        let (segment, next) = match rest.split_once('/') {
+            Some((segment, next)) => (segment, Some(next)),
+            None => (rest, None),
+        };
+
+        if let Some(child) = self.statics.get(segment)
+            && let Some(found) = child.find_from(next, captures, ignore_case)
+        {
+            return Some(found);
+        }
+
+        if ignore_case {
+            for (key, child) in &self.statics {
+                if **key != *segment
+                    && key.eq_ignore_ascii_case(segment)
+                    && let Some(found) = child.find_from(next, captures, ignore_case)
+                {
+                    return Some(found);
+                }
+            }
+        }
+
+        if !segment.is_empty()
+            && let Some(child) = &self.param
+        {
+            let len = captures.len();
+            captures.push(segment);
+            if let Some(found) = child.find_from(next, captures, ignore_case) {
+                return Some(found);
+            }
+            captures.truncate(len);
+        }
+
+        if let Some(value) = &self.catch_all {
+            captures.push(rest);
+            return Some(value);
+        }
+        None
+    }
+}
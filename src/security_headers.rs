@@ -0,0 +1,81 @@
+use http::HeaderValue;
+
+/// Response headers that have browsers guard a site's pages, see
+/// [`Response::security_headers`](crate::Response::security_headers) and
+/// [`Router::security_headers`](crate::Router::security_headers).
+///
+/// The default sends all of them:
+///
+/// | header                      | default                           |
+/// |-----------------------------|-----------------------------------|
+/// | `Strict-Transport-Security` | `max-age=31536000`                |
+/// | `X-Content-Type-Options`    | `nosniff`                         |
+/// | `X-Frame-Options`           | `DENY`                            |
+/// | `Referrer-Policy`           | `strict-origin-when-cross-origin` |
+///
+/// Every value can be changed or, with `None`, left out:
+///
+/// ```ignore
+/// let headers = SecurityHeaders::default()
+///     .hsts(Some("max-age=63072000; includeSubDomains; preload"))
+///     .frame_options(Some("SAMEORIGIN"));
+/// ```
+///
+/// Values are static strings; one that isn't a valid header value panics,
+/// as `HeaderValue::from_static` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    pub(crate) hsts: Option<HeaderValue>,
+    pub(crate) content_type_options: Option<HeaderValue>,
+    pub(crate) frame_options: Option<HeaderValue>,
+    pub(crate) referrer_policy: Option<HeaderValue>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders::none()
+            .hsts(Some("max-age=31536000"))
+            .content_type_options(Some("nosniff"))
+            .frame_options(Some("DENY"))
+            .referrer_policy(Some("strict-origin-when-cross-origin"))
+    }
+}
+
+impl SecurityHeaders {
+    /// None of the headers, to add only some of them.
+    pub fn none() -> SecurityHeaders {
+        SecurityHeaders {
+            hsts: None,
+            content_type_options: None,
+            frame_options: None,
+            referrer_policy: None,
+        }
+    }
+
+    /// `Strict-Transport-Security`, which has browsers use only HTTPS for
+    /// the site. They ignore it on plain HTTP.
+    pub fn hsts(mut self, value: Option<&'static str>) -> Self {
+        self.hsts = value.map(HeaderValue::from_static);
+        self
+    }
+
+    /// `X-Content-Type-Options`, `nosniff` to keep browsers from guessing
+    /// a type other than the Content-Type.
+    pub fn content_type_options(mut self, value: Option<&'static str>) -> Self {
+        self.content_type_options = value.map(HeaderValue::from_static);
+        self
+    }
+
+    /// `X-Frame-Options`, whether other sites may show the page in a frame.
+    pub fn frame_options(mut self, value: Option<&'static str>) -> Self {
+        self.frame_options = value.map(HeaderValue::from_static);
+        self
+    }
+
+    /// `Referrer-Policy`, how much of the page URL links and requests from
+    /// it pass on.
+    pub fn referrer_policy(mut self, value: Option<&'static str>) -> Self {
+        self.referrer_policy = value.map(HeaderValue::from_static);
+        self
+    }
+}
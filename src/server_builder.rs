@@ -0,0 +1,262 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use may::coroutine::JoinHandle;
+
+use crate::access_log::AccessLog;
+use crate::config::{InvalidCidr, Overload, ResponseStats, ServerConfig};
+use crate::date::Clock;
+use crate::error_handler::ErrorHandler;
+use crate::http_server::{self, HttpServer, HttpService};
+use crate::router::{ApiService, Router};
+use crate::shutdown::ShutdownHandle;
+
+/// Sets up a server one setting at a time, then starts it:
+///
+/// ```ignore
+/// let server = HttpServer::builder_for(router)
+///     .bind("0.0.0.0:8080")
+///     .header_timeout(Duration::from_secs(10))
+///     .keep_alive_timeout(Duration::from_secs(75))
+///     .max_connections(10_000, Overload::Queue)
+///     .workers(8)
+///     .start()?;
+/// server.join().unwrap();
+/// ```
+///
+/// The request and connection settings are those of [`ServerConfig`],
+/// which [`config`](Self::config) sets all at once.
+///
+/// The runtime settings, [`workers`](Self::workers),
+/// [`stack_size`](Self::stack_size), [`pool_capacity`](Self::pool_capacity)
+/// and [`worker_pin`](Self::worker_pin), are those of the coroutine
+/// scheduler of the whole process, shared by every server in it. `start`
+/// applies them before binding. The scheduler starts along with the first
+/// coroutine and fixes its threads then, so `workers` and `worker_pin` are
+/// ignored once anything has run one, such as an earlier server; set them
+/// on the builder of the first server, or with `may::config()` first thing
+/// in `main`. The stack size and the pool capacity apply to coroutines
+/// started afterwards.
+pub struct HttpServerBuilder<T> {
+    service: T,
+    // the addresses of each listener
+    addrs: io::Result<Vec<Vec<SocketAddr>>>,
+    listeners: Vec<TcpListener>,
+    config: ServerConfig,
+    runtime: Runtime,
+}
+
+// Settings of the coroutine scheduler, left as they are unless set
+#[derive(Default)]
+struct Runtime {
+    workers: Option<usize>,
+    stack_size: Option<usize>,
+    pool_capacity: Option<usize>,
+    worker_pin: Option<bool>,
+}
+
+impl Runtime {
+    fn apply(&self) {
+        let config = may::config();
+        if let Some(workers) = self.workers {
+            config.set_workers(workers);
+        }
+        if let Some(bytes) = self.stack_size {
+            // counted in words
+            config.set_stack_size(bytes.div_ceil(std::mem::size_of::<usize>()));
+        }
+        if let Some(capacity) = self.pool_capacity {
+            config.set_pool_capacity(capacity);
+        }
+        if let Some(pin) = self.worker_pin {
+            config.set_worker_pin(pin);
+        }
+    }
+}
+
+// Setters passed on to the `ServerConfig` of the builder
+macro_rules! config_setters {
+    ($($name:ident($($arg:ident: $ty:ty),*);)*) => {$(
+        #[doc = concat!("See [`ServerConfig::", stringify!($name), "`].")]
+        pub fn $name(mut self, $($arg: $ty),*) -> Self {
+            self.config = self.config.$name($($arg),*);
+            self
+        }
+    )*};
+}
+
+impl<T: HttpService + Clone + Send + Sync + 'static> HttpServerBuilder<T> {
+    pub(crate) fn new(service: T) -> Self {
+        HttpServerBuilder {
+            service,
+            addrs: Ok(Vec::new()),
+            listeners: Vec::new(),
+            config: ServerConfig::default(),
+            runtime: Runtime::default(),
+        }
+    }
+
+    /// An address to listen on; a server needs one, or a
+    /// [`listener`](Self::listener). Every call adds a listener, for
+    /// serving ports such as `80` and `8080` or IPv4 and IPv6 together:
+    ///
+    /// ```ignore
+    /// HttpServer::builder_for(router).bind("0.0.0.0:80").bind("[::]:80").start()?;
+    /// ```
+    ///
+    /// A name resolving to several addresses listens on the first that
+    /// can be bound, as `TcpListener::bind` does; failing to resolve it
+    /// fails [`start`](Self::start).
+    pub fn bind<L: ToSocketAddrs>(mut self, addr: L) -> Self {
+        if let Ok(addrs) = &mut self.addrs {
+            match addr.to_socket_addrs() {
+                Ok(resolved) => addrs.push(resolved.collect()),
+                Err(e) => self.addrs = Err(e),
+            }
+        }
+        self
+    }
+
+    /// A listener bound beforehand to accept on too, such as one a test
+    /// bound to port `0` to learn the address of the server. The socket
+    /// settings applying to listeners are left to whoever bound it.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Listeners bound beforehand, as [`listener`](Self::listener) takes
+    /// one, such as those of [`systemd_listeners`](crate::systemd_listeners)
+    /// or those handed over by [`Predecessor`](crate::Predecessor).
+    pub fn listeners(mut self, listeners: impl IntoIterator<Item = TcpListener>) -> Self {
+        self.listeners.extend(listeners);
+        self
+    }
+
+    /// Replaces the settings made so far with `config`.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    config_setters! {
+        max_headers(count: usize);
+        max_header_bytes(bytes: usize);
+        max_uri_length(bytes: usize);
+        max_response_headers(count: usize);
+        max_connections(max: usize, overload: Overload);
+        max_requests_per_connection(count: usize);
+        reuse_port(listeners: usize);
+        backlog(connections: u32);
+        recv_buffer_size(bytes: usize);
+        send_buffer_size(bytes: usize);
+        nodelay(enabled: bool);
+        linger(timeout: Duration);
+        tcp_keepalive(idle: Duration);
+        keep_alive(enabled: bool);
+        keep_alive_timeout(timeout: Duration);
+        header_timeout(timeout: Duration);
+        body_timeout(timeout: Duration);
+        write_timeout(timeout: Duration);
+        request_timeout(timeout: Duration);
+        log_exchanges(enabled: bool);
+        access_log(log: AccessLog);
+        pretty_json(enabled: bool);
+        text_charset(charset: Option<&'static str>);
+        shutdown(handle: ShutdownHandle);
+    }
+
+    /// See [`ServerConfig::clock`].
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.config = self.config.clock(clock);
+        self
+    }
+
+    /// See [`ServerConfig::error_handler`].
+    pub fn error_handler(mut self, handler: impl ErrorHandler + 'static) -> Self {
+        self.config = self.config.error_handler(handler);
+        self
+    }
+
+    /// See [`ServerConfig::on_response`].
+    pub fn on_response<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ResponseStats) + Send + Sync + 'static,
+    {
+        self.config = self.config.on_response(hook);
+        self
+    }
+
+    /// See [`ServerConfig::trusted_proxies`].
+    pub fn trusted_proxies<I>(mut self, proxies: I) -> Result<Self, InvalidCidr>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.config = self.config.trusted_proxies(proxies)?;
+        Ok(self)
+    }
+
+    /// The number of threads running coroutines, the number of CPUs by
+    /// default. It is a setting of the whole process, taking effect only
+    /// if no coroutine has run yet; see the runtime settings of
+    /// [`HttpServerBuilder`].
+    pub fn workers(mut self, count: usize) -> Self {
+        self.runtime.workers = Some(count);
+        self
+    }
+
+    /// The stack size of coroutines, those serving connections included,
+    /// rounded up to whole words. Handlers with large locals or deep
+    /// recursion need more, and overflowing the stack crashes the process.
+    /// Like [`workers`](Self::workers) it is a setting of the whole
+    /// process, for coroutines started afterwards.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.runtime.stack_size = Some(bytes);
+        self
+    }
+
+    /// How many finished coroutines are kept, with their stacks, to run
+    /// new ones on instead of allocating them. Servers with many short
+    /// connections opened at once keep more. A setting of the whole process
+    /// like [`workers`](Self::workers).
+    pub fn pool_capacity(mut self, count: usize) -> Self {
+        self.runtime.pool_capacity = Some(count);
+        self
+    }
+
+    /// Whether each worker thread is pinned to a CPU core of its own, which
+    /// helps machines serving little else, with `workers` at most the
+    /// number of cores. Like [`workers`](Self::workers) it is a setting of
+    /// the whole process, taking effect only if no coroutine has run yet.
+    pub fn worker_pin(mut self, pin: bool) -> Self {
+        self.runtime.worker_pin = Some(pin);
+        self
+    }
+
+    /// Binds the addresses and starts serving, returning the server
+    /// coroutine, see [`HttpServer::start_from`].
+    pub fn start(self) -> io::Result<JoinHandle<()>> {
+        let addrs = self.addrs?;
+        self.runtime.apply();
+        let mut listeners = http_server::bind_all(addrs.iter().map(Vec::as_slice), &self.config)?;
+        listeners.extend(self.listeners);
+        HttpServer(self.service).start_from(listeners, self.config)
+    }
+}
+
+impl<T: HttpService + Clone + Send + Sync + 'static> HttpServer<T> {
+    /// Sets up a server for `service`, see [`HttpServerBuilder`].
+    pub fn builder(service: T) -> HttpServerBuilder<T> {
+        HttpServerBuilder::new(service)
+    }
+}
+
+impl HttpServer<ApiService> {
+    /// Sets up a server for `router`, see [`HttpServerBuilder`].
+    pub fn builder_for(router: impl Into<Arc<Router>>) -> HttpServerBuilder<ApiService> {
+        HttpServerBuilder::new(ApiService::new(router.into()))
+    }
+}
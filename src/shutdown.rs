@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use may::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use crate::handover::{self, Successor};
+
+/// Stops servers gracefully, from any thread: they stop accepting
+/// connections, close those waiting for a request, and answer the
+/// requests already being handled with `Connection: close` before closing
+/// those too.
+///
+/// The handle is given to servers with
+/// [`ServerConfig::shutdown`](crate::ServerConfig::shutdown); clones stop
+/// the same servers.
+///
+/// ```ignore
+/// let shutdown = ShutdownHandle::new();
+/// let config = ServerConfig::new().shutdown(shutdown.clone());
+/// let server = HttpServer::create_with(router, "0.0.0.0:8080", config)?;
+/// shutdown.on_signals()?;
+/// // returns once a signal stopped the server from accepting
+/// server.join().unwrap();
+/// shutdown.wait(Duration::from_secs(30));
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    requested: AtomicBool,
+    // clones of the servers' listeners, to wake their accept loops by
+    listeners: Mutex<Vec<std::net::TcpListener>>,
+    // the listeners are shared with a new process, see `hand_over`
+    handed_over: AtomicBool,
+    connections: Mutex<HashMap<usize, Tracked>>,
+    next_key: AtomicUsize,
+}
+
+// An open connection, with a clone of its socket to close it by while it
+// waits for a request
+struct Tracked {
+    stream: TcpStream,
+    idle: bool,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the shutdown, without waiting for connections to close; see
+    /// [`wait`](Self::wait) for that. Further calls do nothing.
+    pub fn shutdown(&self) {
+        let mut connections = self.connections();
+        if self.0.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for tracked in connections.values_mut().filter(|tracked| tracked.idle) {
+            tracked.stream.shutdown(Shutdown::Both).ok();
+        }
+        drop(connections);
+
+        let listeners = std::mem::take(&mut *self.0.listeners.lock().unwrap());
+        let handed_over = self.0.handed_over.load(Ordering::SeqCst);
+        for listener in listeners {
+            if handed_over {
+                wake_shared(&listener);
+                continue;
+            }
+            // accepting on a listener shut down for reading fails on Linux,
+            // elsewhere it takes a connection
+            socket2::SockRef::from(&listener).shutdown(Shutdown::Read).ok();
+            if let Some(addr) = connect_addr(&listener) {
+                std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(1)).ok();
+            }
+        }
+    }
+
+    /// Whether [`shutdown`](Self::shutdown) was called.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.requested.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to `timeout` for the connections of the servers to close,
+    /// returning whether they all did.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while !self.connections().is_empty() {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            may::coroutine::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    /// Shuts down on `SIGINT`, `SIGTERM` and `SIGHUP` on Unix, and on
+    /// Ctrl-C and closing the console on Windows. A second signal exits the
+    /// process right away, for servers that don't wind down quickly.
+    ///
+    /// Signals can be handled this way by one handle per process; setting
+    /// up a second one fails.
+    #[cfg(feature = "signals")]
+    pub fn on_signals(&self) -> io::Result<()> {
+        let handle = self.clone();
+        ctrlc::try_set_handler(move || {
+            if handle.is_shutdown() {
+                std::process::exit(130);
+            }
+            handle.shutdown();
+        })
+        .map_err(io::Error::other)
+    }
+
+    /// Starts `command`, such as the new binary of a deployment, with the
+    /// listeners of the servers, for it to accept connections on them along
+    /// with this process until it is shut down. Connections waiting to be
+    /// accepted are taken by either process, none are refused.
+    ///
+    /// The new process takes the listeners over with
+    /// [`Predecessor::take`](crate::Predecessor::take) and says when its
+    /// servers are started, after which this one shuts down and drains its
+    /// connections:
+    ///
+    /// ```ignore
+    /// // the old process, on a signal of the deployment
+    /// let mut command = Command::new(env::current_exe()?);
+    /// command.args(env::args_os().skip(1));
+    /// let mut successor = shutdown.hand_over(command)?;
+    /// successor.wait_ready(Duration::from_secs(30))?;
+    /// shutdown.shutdown();
+    /// server.join().unwrap();
+    /// shutdown.wait(Duration::from_secs(30));
+    ///
+    /// // the new process, at startup
+    /// let mut predecessor = Predecessor::take()?;
+    /// let builder = HttpServer::builder_for(router).shutdown(shutdown.clone());
+    /// let server = match &mut predecessor {
+    ///     Some(predecessor) => builder.listeners(predecessor.take_listeners()),
+    ///     None => builder.bind("0.0.0.0:8080"),
+    /// }
+    /// .start()?;
+    /// if let Some(predecessor) = predecessor {
+    ///     predecessor.ready()?;
+    /// }
+    /// ```
+    ///
+    /// The listeners are passed as inherited descriptors, the first as `3`
+    /// as systemd numbers them, with the variable `KARICS_HANDOVER` naming
+    /// this process. Fails with the error of starting `command`, and if the
+    /// shutdown has started or no server listens yet.
+    #[cfg(unix)]
+    pub fn hand_over(&self, command: Command) -> io::Result<Successor> {
+        // held while starting, for a shutdown meanwhile to wait
+        let listeners = self.0.listeners.lock().unwrap();
+        if self.is_shutdown() {
+            return Err(io::Error::other("the server was shut down"));
+        }
+        let successor = handover::spawn(&listeners, command)?;
+        self.0.handed_over.store(true, Ordering::SeqCst);
+        Ok(successor)
+    }
+
+    /// Has the server accepting on `listener` be woken by the shutdown,
+    /// `false` if it has already started.
+    pub(crate) fn listen(&self, listener: &TcpListener) -> io::Result<bool> {
+        let listener = listener.inner().try_clone()?;
+        let mut listeners = self.0.listeners.lock().unwrap();
+        listeners.push(listener);
+        Ok(!self.is_shutdown())
+    }
+
+    /// Tracks a connection until the returned guard drops, `None` if the
+    /// shutdown has started and the connection is to be closed.
+    pub(crate) fn register(&self, stream: &TcpStream) -> io::Result<Option<Registration>> {
+        let stream = stream.try_clone()?;
+        let mut connections = self.connections();
+        if self.is_shutdown() {
+            return Ok(None);
+        }
+        let key = self.0.next_key.fetch_add(1, Ordering::Relaxed);
+        connections.insert(key, Tracked { stream, idle: false });
+        Ok(Some(Registration {
+            handle: self.clone(),
+            key,
+        }))
+    }
+
+    fn connections(&self) -> MutexGuard<'_, HashMap<usize, Tracked>> {
+        // a connection that panicked leaves a consistent map behind
+        self.0.connections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownHandle")
+            .field("shutdown", &self.is_shutdown())
+            .field("connections", &self.connections().len())
+            .finish()
+    }
+}
+
+// The address to connect to `listener` on, localhost for one bound to all
+// addresses
+fn connect_addr(listener: &std::net::TcpListener) -> Option<SocketAddr> {
+    let mut addr = listener.local_addr().ok()?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    Some(addr)
+}
+
+// Wakes the accept loop of a listener shared with the new process of a
+// handover, which shutting the socket down would stop too. Either process
+// may accept a connection: the loop here closes it right away, the new
+// process waits for a request, so connecting goes on until one is closed.
+fn wake_shared(listener: &std::net::TcpListener) {
+    let Some(addr) = connect_addr(listener) else {
+        return;
+    };
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        let Ok(mut stream) = std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(1)) else {
+            return;
+        };
+        stream.set_read_timeout(Some(Duration::from_millis(20))).ok();
+        match stream.read(&mut [0]) {
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            _ => return,
+        }
+    }
+}
+
+/// A connection tracked by a [`ShutdownHandle`].
+pub(crate) struct Registration {
+    handle: ShutdownHandle,
+    key: usize,
+}
+
+impl Registration {
+    /// Whether the connection is to close after the response being written.
+    #[inline]
+    pub(crate) fn stopping(&self) -> bool {
+        self.handle.is_shutdown()
+    }
+
+    /// Marks the connection as waiting for a request, which the shutdown
+    /// closes it in; `false` if it has started and the connection is to
+    /// close now.
+    pub(crate) fn idle(&self) -> bool {
+        self.set_idle(true)
+    }
+
+    /// Marks the connection as handling requests again after
+    /// [`idle`](Self::idle), `false` if the shutdown closed it meanwhile.
+    pub(crate) fn busy(&self) -> bool {
+        self.set_idle(false)
+    }
+
+    fn set_idle(&self, idle: bool) -> bool {
+        let mut connections = self.handle.connections();
+        if self.stopping() {
+            return false;
+        }
+        if let Some(tracked) = connections.get_mut(&self.key) {
+            tracked.idle = idle;
+        }
+        true
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.handle.connections().remove(&self.key);
+    }
+}
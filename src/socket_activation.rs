@@ -0,0 +1,63 @@
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use socket2::{Socket, Type};
+
+// The first of the sockets systemd passes, `SD_LISTEN_FDS_START`
+const FIRST_FD: RawFd = 3;
+
+// Whether the sockets were taken over, which happens once per process
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The listeners systemd passed the process by socket activation, in the
+/// order of the `ListenStream=` settings of the socket unit:
+///
+/// ```ignore
+/// HttpServer::builder_for(router).listeners(karics::systemd_listeners()?).start()?;
+/// ```
+///
+/// None if the process wasn't started this way, as well as from calls
+/// after the first, which takes the sockets over; child processes don't
+/// inherit them. Fails if a socket is no TCP listener, such as the
+/// `ListenStream=` of a Unix socket path.
+pub fn systemd_listeners() -> io::Result<Vec<TcpListener>> {
+    let (Ok(pid), Ok(fds)) = (env::var("LISTEN_PID"), env::var("LISTEN_FDS")) else {
+        return Ok(Vec::new());
+    };
+    // the variables may be left over from starting a parent process
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "LISTEN_FDS is not a number"))?;
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+    (FIRST_FD..FIRST_FD.saturating_add(count))
+        .map(|fd| listener(fd, "systemd"))
+        .collect()
+}
+
+/// Takes over the socket `fd` passed by `from`.
+pub(crate) fn listener(fd: RawFd, from: &str) -> io::Result<TcpListener> {
+    // the socket is left open for the process, and the callers take it
+    // over once
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    let tcp = socket.r#type().is_ok_and(|ty| ty == Type::STREAM)
+        && socket.local_addr().is_ok_and(|addr| addr.as_socket().is_some());
+    if !tcp {
+        // left open, for whatever else it is meant for
+        let _ = socket.into_raw_fd();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket {fd} passed by {from} is no TCP listener"),
+        ));
+    }
+    socket.set_cloexec(true)?;
+    Ok(socket.into())
+}
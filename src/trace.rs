@@ -0,0 +1,169 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::{HeaderMap, HeaderValue};
+use tracing::Span;
+use tracing::field::Empty;
+
+use crate::request::{self, Request};
+
+/// The W3C trace context of a request, for spans of other services it
+/// calls to join the same trace.
+///
+/// With the `tracing` feature servers put one in the extensions of every
+/// request, continuing the trace of its `traceparent` header or starting
+/// a new one, along with the [`Span`] of the request. Spans aren't
+/// entered, as a coroutine may move between threads while one is;
+/// handlers log into them by naming them as the parent:
+///
+/// ```ignore
+/// let span = req.extensions().get::<Span>().cloned().unwrap_or_else(Span::none);
+/// tracing::info!(parent: &span, user = id, "loading the profile");
+///
+/// let trace = req.extensions().get::<TraceContext>().copied();
+/// let mut headers = HeaderMap::new();
+/// if let Some(trace) = trace {
+///     trace.inject(&mut headers);
+/// }
+/// ```
+///
+/// The spans are named `connection`, with the `peer` address, and
+/// `request` within it, with the `method`, `path`, `trace_id`, `span_id`
+/// and `parent_id` of the request and, once it is answered, the `route`
+/// pattern and the `status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    flags: u8,
+}
+
+impl TraceContext {
+    /// The context of a new trace, sampled.
+    pub fn new_root() -> Self {
+        TraceContext {
+            trace_id: u128::from(random_id()) << 64 | u128::from(random_id()),
+            span_id: random_id(),
+            flags: 1,
+        }
+    }
+
+    /// The context of a new span in the same trace, such as the server's
+    /// span of a request made with `self`.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            span_id: random_id(),
+            ..*self
+        }
+    }
+
+    /// Parses a `traceparent` header value such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, `None` if
+    /// it is malformed. Values of later versions are read as far as
+    /// version `00` goes.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (version, rest) = value.split_once('-')?;
+        let version = hex(version, 2)?;
+        let (trace_id, rest) = rest.split_once('-')?;
+        let (span_id, rest) = rest.split_once('-')?;
+        let flags = match version {
+            0 => rest,
+            0xff => return None,
+            _ => rest.split('-').next()?,
+        };
+        let context = TraceContext {
+            trace_id: hex(trace_id, 32)?,
+            span_id: hex(span_id, 16)? as u64,
+            flags: hex(flags, 2)? as u8,
+        };
+        (context.trace_id != 0 && context.span_id != 0).then_some(context)
+    }
+
+    /// Sets the `traceparent` header of a request about to be sent to
+    /// `self`, for its server to continue the trace.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let value = HeaderValue::from_str(&self.to_string()).expect("hex digits and dashes");
+        headers.insert("traceparent", value);
+    }
+
+    pub fn trace_id(&self) -> u128 {
+        self.trace_id
+    }
+
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    /// Whether the caller records the trace, the `sampled` flag.
+    pub fn sampled(&self) -> bool {
+        self.flags & 1 != 0
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+// `digits` lowercase hex digits, as trace context values are written
+fn hex(value: &str, digits: usize) -> Option<u128> {
+    let valid = value.len() == digits
+        && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    valid.then(|| u128::from_str_radix(value, 16).ok()).flatten()
+}
+
+// A nonzero id, by hashing a counter with the random keys the standard
+// library seeds hash maps with
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish().max(1)
+}
+
+/// The span of a connection from `peer`.
+pub(crate) fn connection_span(peer: Option<SocketAddr>) -> Span {
+    match peer {
+        Some(peer) => tracing::info_span!("connection", peer = %peer),
+        None => tracing::info_span!("connection", peer = Empty),
+    }
+}
+
+/// The span of `req`, put in its extensions along with its trace context.
+pub(crate) fn request_span(req: &Request, connection: &Span) -> Span {
+    let parent = request::header_values(req.headers(), "traceparent")
+        .next()
+        .and_then(TraceContext::parse);
+    let context = parent.map_or_else(TraceContext::new_root, |parent| parent.child());
+    let span = tracing::info_span!(
+        parent: connection,
+        "request",
+        method = req.method(),
+        path = req.path(),
+        trace_id = %format_args!("{:032x}", context.trace_id),
+        span_id = %format_args!("{:016x}", context.span_id),
+        parent_id = Empty,
+        route = Empty,
+        status = Empty,
+    );
+    if let Some(parent) = parent {
+        span.record("parent_id", format_args!("{:016x}", parent.span_id));
+    }
+    let mut extensions = req.extensions_mut();
+    extensions.insert(span.clone());
+    extensions.insert(context);
+    span
+}
+
+/// Records how the request of `span` was answered.
+pub(crate) fn record(span: &Span, status: u16, route: Option<&str>) {
+    span.record("status", status);
+    if let Some(route) = route {
+        span.record("route", route);
+    }
+}
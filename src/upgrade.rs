@@ -0,0 +1,140 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+use bytes::{Buf, Bytes};
+use http::HeaderValue;
+use may::net::TcpStream;
+
+type OnUpgrade = Box<dyn FnOnce(Upgraded) -> io::Result<()> + Send>;
+
+/// A switch of the connection to another protocol, such as `h2c` or a
+/// tunnel of its own, answered with `101 Switching Protocols` by
+/// [`Response::upgrade`](crate::Response::upgrade).
+///
+/// Once the `101` and the responses before it are written, the connection
+/// leaves HTTP/1.1: its coroutine calls `on_upgrade` with the stream, which
+/// speaks the new protocol until it returns. Check that the client asked
+/// for the protocol with
+/// [`Request::upgrade_requested`](crate::Request::upgrade_requested) first:
+///
+/// ```ignore
+/// if !req.upgrade_requested("echo") {
+///     rsp.status(StatusCode::UPGRADE_REQUIRED).header("Upgrade: echo");
+///     return Ok(());
+/// }
+/// rsp.upgrade(Upgrade::new("echo", |mut io| {
+///     let mut buf = [0; 1024];
+///     loop {
+///         let n = io.read(&mut buf)?;
+///         if n == 0 {
+///             return Ok(());
+///         }
+///         io.write_all(&buf[..n])?;
+///     }
+/// })?);
+/// ```
+///
+/// The upgraded connection still counts against
+/// [`ServerConfig::max_connections`](crate::ServerConfig::max_connections),
+/// has no read or write timeouts and isn't closed by a graceful shutdown,
+/// which leaves it to `on_upgrade`.
+pub struct Upgrade {
+    protocol: HeaderValue,
+    on_upgrade: OnUpgrade,
+}
+
+impl Upgrade {
+    /// A switch to `protocol`, sent in the `Upgrade` header.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if `protocol` isn't a valid
+    /// header value.
+    pub fn new<F>(protocol: &str, on_upgrade: F) -> io::Result<Self>
+    where
+        F: FnOnce(Upgraded) -> io::Result<()> + Send + 'static,
+    {
+        let protocol = HeaderValue::from_str(protocol)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid header value"))?;
+        Ok(Upgrade {
+            protocol,
+            on_upgrade: Box::new(on_upgrade),
+        })
+    }
+
+    pub fn protocol(&self) -> &str {
+        self.protocol.to_str().unwrap_or_default()
+    }
+
+    pub(crate) fn header_value(&self) -> &HeaderValue {
+        &self.protocol
+    }
+
+    /// Hands `stream` to `on_upgrade`, with the bytes already read past the
+    /// upgrade request.
+    pub(crate) fn run(self, stream: TcpStream, buffered: Bytes) -> io::Result<()> {
+        // the timeouts of HTTP requests don't fit the new protocol
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        (self.on_upgrade)(Upgraded { stream, buffered })
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade").field("protocol", &self.protocol).finish_non_exhaustive()
+    }
+}
+
+/// The connection after a [`Upgrade`], reading first what the client sent
+/// right behind the upgrade request.
+pub struct Upgraded {
+    stream: TcpStream,
+    buffered: Bytes,
+}
+
+impl Upgraded {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    /// The stream and the bytes read from it ahead of the new protocol,
+    /// which come before anything read from the stream.
+    pub fn into_parts(self) -> (TcpStream, Bytes) {
+        (self.stream, self.buffered)
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.is_empty() {
+            return self.stream.read(buf);
+        }
+        let n = buf.len().min(self.buffered.len());
+        buf[..n].copy_from_slice(&self.buffered[..n]);
+        self.buffered.advance(n);
+        Ok(n)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("peer", &self.stream.peer_addr().ok())
+            .field("buffered", &self.buffered.len())
+            .finish()
+    }
+}
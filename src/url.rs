@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use crate::query::{Query, percent_decode};
+
+/// The request target split into its parts, see
+/// [`Request::url`](crate::Request::url).
+///
+/// `/a/./b/../c?x=1#top` has the path `/a/c`, the query `x=1` and the
+/// fragment `top`. The path keeps its percent-encoding; the
+/// [`segments`](Self::segments) are decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url<'a> {
+    authority: Option<&'a str>,
+    path: Cow<'a, str>,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Url<'a> {
+    /// Splits a request target in origin form, such as `/search?q=x`, or
+    /// absolute form, such as `http://example.com/search?q=x`.
+    pub fn parse(target: &'a str) -> Url<'a> {
+        let (rest, fragment) = match target.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (target, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (authority, path) = match rest.split_once("://") {
+            Some((scheme, rest)) if !scheme.contains('/') => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            _ => (None, rest),
+        };
+        let path = match path {
+            "" => Cow::Borrowed("/"),
+            path => remove_dot_segments(path),
+        };
+        Url {
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+
+    /// The host and port of an absolute-form target, as sent to proxies.
+    pub fn authority(&self) -> Option<&'a str> {
+        self.authority
+    }
+
+    /// The path with `.` and `..` segments resolved, still percent-encoded.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The raw query string following `?`.
+    pub fn query(&self) -> Option<&'a str> {
+        self.query
+    }
+
+    /// The parsed query string, see [`Query`].
+    pub fn query_pairs(&self) -> Query {
+        self.query.map(Query::parse).unwrap_or_default()
+    }
+
+    /// The fragment following `#`, which clients aren't supposed to send.
+    pub fn fragment(&self) -> Option<&'a str> {
+        self.fragment
+    }
+
+    /// The percent-decoded path segments, `["users", "42"]` for
+    /// `/users/42`. A trailing slash adds an empty segment.
+    pub fn segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let path = self.path.strip_prefix('/').unwrap_or(&self.path);
+        path.split('/')
+            .filter(move |_| !path.is_empty())
+            .map(|segment| percent_decode(segment, false))
+    }
+}
+
+// RFC 3986 section 5.2.4, borrowing the path when there is nothing to do
+fn remove_dot_segments(path: &str) -> Cow<'_, str> {
+    if !path.starts_with('/') || !path.split('/').any(|s| s == "." || s == "..") {
+        return Cow::Borrowed(path);
+    }
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path[1..].split('/') {
+        trailing_slash = matches!(segment, "." | "..");
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut resolved = String::with_capacity(path.len());
+    for segment in &segments {
+        resolved.push('/');
+        resolved.push_str(segment);
+    }
+    if trailing_slash || resolved.is_empty() {
+        resolved.push('/');
+    }
+    Cow::Owned(resolved)
+}